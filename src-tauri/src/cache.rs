@@ -0,0 +1,119 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::file_util::hash_file;
+
+/// Disk cache of downloaded archives, keyed by the archive hash
+/// and evicted least-recently-used first once over its size cap.
+pub(crate) struct ArchiveCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl ArchiveCache {
+    pub(crate) async fn open(dir: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self { dir, max_bytes })
+    }
+
+    fn entry_path(&self, hash: &[u8; 32]) -> PathBuf {
+        self.dir.join(hex::encode(hash))
+    }
+
+    /// Path to download an entry to before it is verified and inserted.
+    pub(crate) fn temp_path(&self, hash: &[u8; 32]) -> PathBuf {
+        self.dir.join(hex::encode(hash) + ".tmp")
+    }
+
+    /// Returns the cached copy of an archive if it is present and intact.
+    pub(crate) async fn get(&self, hash: &[u8; 32]) -> std::io::Result<Option<PathBuf>> {
+        let path = self.entry_path(hash);
+        match hash_file(&path).await {
+            Ok(actual_hash) if &actual_hash == hash => {
+                // The modification time doubles as the last-use time for eviction.
+                let touch_path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    std::fs::File::options()
+                        .write(true)
+                        .open(touch_path)?
+                        .set_modified(SystemTime::now())
+                })
+                .await??;
+                Ok(Some(path))
+            }
+            Ok(_) => {
+                tokio::fs::remove_file(&path).await?;
+                Ok(None)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Moves a verified download into the cache, then evicts old entries.
+    pub(crate) async fn insert(
+        &self,
+        hash: &[u8; 32],
+        temp_path: &Path,
+    ) -> std::io::Result<PathBuf> {
+        let path = self.entry_path(hash);
+        tokio::fs::rename(temp_path, &path).await?;
+        self.evict(&path).await?;
+        Ok(path)
+    }
+
+    async fn evict(&self, keep: &Path) -> std::io::Result<()> {
+        for (path, _) in self.over_cap(Some(keep)).await? {
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Lists the entries, with their sizes, that have to be evicted
+    /// least-recently-used first to bring the cache back under its cap.
+    pub(crate) async fn over_cap(
+        &self,
+        keep: Option<&Path>,
+    ) -> std::io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = Vec::new();
+        let mut total_len = 0;
+
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let meta = entry.metadata().await?;
+            if meta.is_file() {
+                total_len += meta.len();
+                entries.push((meta.modified()?, meta.len(), entry.path()));
+            }
+        }
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        let mut evicted = Vec::new();
+        for (_, len, path) in entries {
+            if total_len <= self.max_bytes {
+                break;
+            }
+            if Some(path.as_path()) != keep {
+                total_len -= len;
+                evicted.push((path, len));
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Lists downloads left behind in the cache by interrupted installs.
+    pub(crate) async fn temp_files(&self) -> std::io::Result<Vec<(PathBuf, u64)>> {
+        let mut temp_files = Vec::new();
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                temp_files.push((path, entry.metadata().await?.len()));
+            }
+        }
+        Ok(temp_files)
+    }
+}
@@ -0,0 +1,275 @@
+//! Validates an rsync delta before it is applied.
+//!
+//! `fast_rsync::apply_limited` trusts the commands in a delta up to the output
+//! limit it is given. Walking the commands first lets a delta that reads past
+//! the source, or does not produce exactly the declared size, be rejected
+//! without touching the destination file.
+
+const DELTA_MAGIC: u32 = 0x7273_0236;
+
+const OP_END: u8 = 0x00;
+const OP_LITERAL_INLINE_MAX: u8 = 0x40;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_LITERAL_N8: u8 = 0x44;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+
+/// Byte widths of the integers following a command, indexed by its encoding.
+const INT_WIDTHS: [usize; 4] = [1, 2, 4, 8];
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum DeltaError {
+    #[error("bad magic 0x{0:08x}")]
+    BadMagic(u32),
+    #[error("truncated at offset {0}")]
+    Truncated(usize),
+    #[error("unknown command 0x{command:02x} at offset {offset}")]
+    UnknownCommand { command: u8, offset: usize },
+    #[error("copy of {len} bytes at {start} exceeds the {src_len} byte source")]
+    CopyOutOfBounds { start: u64, len: u64, src_len: u64 },
+    #[error("output exceeds the declared {dst_len} bytes")]
+    OutputTooLong { dst_len: u64 },
+    #[error("output is {actual} bytes instead of the declared {dst_len}")]
+    OutputTooShort { dst_len: u64, actual: u64 },
+    #[error("{0} trailing bytes after the end command")]
+    TrailingBytes(usize),
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], DeltaError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or(DeltaError::Truncated(self.pos))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_int(&mut self, width: usize) -> Result<u64, DeltaError> {
+        Ok(self
+            .take(width)?
+            .iter()
+            .fold(0, |acc, &byte| (acc << 8) | u64::from(byte)))
+    }
+}
+
+/// Checks that `delta` is well-formed, only copies from within `src_len`
+/// bytes of the source, and produces exactly `dst_len` bytes.
+pub(crate) fn check_delta(delta: &[u8], src_len: u64, dst_len: u64) -> Result<(), DeltaError> {
+    let mut cursor = Cursor { buf: delta, pos: 0 };
+
+    let magic = cursor.read_int(4)? as u32;
+    if magic != DELTA_MAGIC {
+        return Err(DeltaError::BadMagic(magic));
+    }
+
+    let mut out_len: u64 = 0;
+    loop {
+        let offset = cursor.pos;
+        let command = cursor.read_int(1)? as u8;
+        let len = match command {
+            OP_END => break,
+            1..=OP_LITERAL_INLINE_MAX => {
+                let len = u64::from(command);
+                cursor.take(len as usize)?;
+                len
+            }
+            OP_LITERAL_N1..=OP_LITERAL_N8 => {
+                let len = cursor.read_int(INT_WIDTHS[(command - OP_LITERAL_N1) as usize])?;
+                let len_usize = usize::try_from(len).map_err(|_| DeltaError::Truncated(offset))?;
+                cursor.take(len_usize)?;
+                len
+            }
+            OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                let encoding = (command - OP_COPY_N1_N1) as usize;
+                let start = cursor.read_int(INT_WIDTHS[encoding / 4])?;
+                let len = cursor.read_int(INT_WIDTHS[encoding % 4])?;
+                if start.checked_add(len).is_none_or(|end| end > src_len) {
+                    return Err(DeltaError::CopyOutOfBounds {
+                        start,
+                        len,
+                        src_len,
+                    });
+                }
+                len
+            }
+            _ => return Err(DeltaError::UnknownCommand { command, offset }),
+        };
+
+        out_len = out_len
+            .checked_add(len)
+            .filter(|&out_len| out_len <= dst_len)
+            .ok_or(DeltaError::OutputTooLong { dst_len })?;
+    }
+
+    if out_len != dst_len {
+        return Err(DeltaError::OutputTooShort {
+            dst_len,
+            actual: out_len,
+        });
+    }
+    let trailing = delta.len() - cursor.pos;
+    if trailing != 0 {
+        return Err(DeltaError::TrailingBytes(trailing));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift, enough to shake out edge cases without a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+    }
+
+    fn delta(commands: &[&[u8]]) -> Vec<u8> {
+        let mut delta = DELTA_MAGIC.to_be_bytes().to_vec();
+        for command in commands {
+            delta.extend_from_slice(command);
+        }
+        delta
+    }
+
+    /// A delta of random commands, some of which reach past `src_len`.
+    fn random_delta(rng: &mut Rng, src_len: u64) -> Vec<u8> {
+        let mut delta = DELTA_MAGIC.to_be_bytes().to_vec();
+        for _ in 0..rng.below(8) {
+            match rng.below(3) {
+                0 => {
+                    let len = 1 + rng.below(u64::from(OP_LITERAL_INLINE_MAX));
+                    delta.push(len as u8);
+                    delta.extend((0..len).map(|_| rng.next() as u8));
+                }
+                1 => {
+                    let len = rng.below(300);
+                    delta.extend([OP_LITERAL_N1 + 1, (len >> 8) as u8, len as u8]);
+                    delta.extend((0..len).map(|_| rng.next() as u8));
+                }
+                _ => {
+                    let start = rng.below(src_len + 16);
+                    let len = rng.below(src_len + 16);
+                    // Eight byte start and length.
+                    delta.push(OP_COPY_N8_N8);
+                    delta.extend(start.to_be_bytes());
+                    delta.extend(len.to_be_bytes());
+                }
+            }
+        }
+        delta.push(OP_END);
+        delta
+    }
+
+    #[test]
+    fn copies_out_of_bounds_are_rejected() {
+        assert!(check_delta(&delta(&[&[OP_COPY_N1_N1, 0, 10], &[OP_END]]), 10, 10).is_ok());
+        for (start, len) in [(8u64, 4u64), (10, 1), (u64::MAX, 2)] {
+            let mut copy = vec![OP_COPY_N8_N8];
+            copy.extend(start.to_be_bytes());
+            copy.extend(len.to_be_bytes());
+            assert!(
+                matches!(
+                    check_delta(&delta(&[&copy, &[OP_END]]), 10, len),
+                    Err(DeltaError::CopyOutOfBounds { .. })
+                ),
+                "copy of {len} bytes at {start} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..10_000 {
+            // Past the magic, the commands themselves get exercised.
+            let mut bytes = match rng.below(2) {
+                0 => DELTA_MAGIC.to_be_bytes().to_vec(),
+                _ => vec![],
+            };
+            bytes.extend((0..rng.below(64)).map(|_| rng.next() as u8));
+            _ = check_delta(&bytes, rng.below(1024), rng.below(1024));
+        }
+    }
+
+    #[test]
+    fn accepted_deltas_apply_cleanly() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let src: Vec<u8> = (0..256).map(|_| rng.next() as u8).collect();
+        let mut accepted = 0;
+        for _ in 0..10_000 {
+            let src_len = rng.below(src.len() as u64 + 1);
+            let mut delta = random_delta(&mut rng, src_len);
+            if rng.below(4) == 0 {
+                // Corrupt a byte, which must never lead to an accepted delta that
+                // then fails to apply.
+                let index = rng.below(delta.len() as u64) as usize;
+                delta[index] = rng.next() as u8;
+            }
+            for dst_len in [rng.below(1024), expected_len(&delta)] {
+                if check_delta(&delta, src_len, dst_len).is_err() {
+                    continue;
+                }
+                accepted += 1;
+                let mut out = Vec::new();
+                fast_rsync::apply_limited(
+                    &src[..src_len as usize],
+                    &delta,
+                    &mut out,
+                    dst_len as usize,
+                )
+                .unwrap();
+                assert_eq!(out.len() as u64, dst_len);
+            }
+        }
+        assert!(accepted > 100, "only {accepted} deltas were accepted");
+    }
+
+    /// Output length of a delta from [`random_delta`] if it were valid.
+    fn expected_len(delta: &[u8]) -> u64 {
+        let mut cursor = Cursor { buf: delta, pos: 4 };
+        let mut out_len: u64 = 0;
+        while let Ok(command) = cursor.read_int(1) {
+            let len = match command as u8 {
+                1..=OP_LITERAL_INLINE_MAX => {
+                    _ = cursor.take(command as usize);
+                    command
+                }
+                command @ OP_LITERAL_N1..=OP_LITERAL_N8 => {
+                    let width = INT_WIDTHS[(command - OP_LITERAL_N1) as usize];
+                    let len = cursor.read_int(width).unwrap_or_default();
+                    _ = cursor.take(len.min(delta.len() as u64) as usize);
+                    len
+                }
+                command @ OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                    let encoding = (command - OP_COPY_N1_N1) as usize;
+                    _ = cursor.read_int(INT_WIDTHS[encoding / 4]);
+                    cursor
+                        .read_int(INT_WIDTHS[encoding % 4])
+                        .unwrap_or_default()
+                }
+                _ => break,
+            };
+            out_len = out_len.saturating_add(len);
+        }
+        out_len
+    }
+}
@@ -1,21 +1,52 @@
 use std::{
+    collections::HashMap,
     fs::FileType,
-    path::{PathBuf, StripPrefixError},
+    ops::Deref,
+    path::{Path, PathBuf, StripPrefixError},
+    time::{Duration, SystemTime},
 };
 
 use async_stream::try_stream;
-use futures::{pin_mut, Stream, StreamExt};
-use tokio::fs::{self, DirEntry, File};
+use fast_rsync::sum_hash::{Blake3Hash, SumHash};
+use futures::{pin_mut, stream::FuturesUnordered, Stream, StreamExt};
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, DirEntry, File},
+    io::AsyncReadExt,
+};
+
+use crate::HashAlgorithm;
 
 pub fn visit_stream(
     path: impl Into<PathBuf>,
 ) -> impl Stream<Item = std::io::Result<(FileType, DirEntry)>> {
+    visit_stream_concurrent(path, 1)
+}
+
+/// Like [`visit_stream`], but reading up to `concurrency` directories at once,
+/// which pays off on high-latency filesystems. Entries of one directory are
+/// yielded together, and never before the directory itself, but the order of
+/// directories is unspecified.
+pub fn visit_stream_concurrent(
+    path: impl Into<PathBuf>,
+    concurrency: usize,
+) -> impl Stream<Item = std::io::Result<(FileType, DirEntry)>> {
+    let concurrency = concurrency.max(1);
     try_stream! {
         let mut to_visit = vec![path.into()];
-        while let Some(path) = to_visit.pop() {
-            let mut dir = fs::read_dir(path).await?;
-            while let Some(child) = dir.next_entry().await? {
-                let file_type = child.file_type().await?;
+        let mut reading = FuturesUnordered::new();
+        loop {
+            while reading.len() < concurrency {
+                let Some(path) = to_visit.pop() else {
+                    break;
+                };
+                reading.push(read_dir_entries(path));
+            }
+            let Some(children) = reading.next().await else {
+                break;
+            };
+            for (file_type, child) in children? {
                 if file_type.is_dir() {
                     to_visit.push(child.path());
                 }
@@ -25,29 +56,379 @@ pub fn visit_stream(
     }
 }
 
+async fn read_dir_entries(path: PathBuf) -> std::io::Result<Vec<(FileType, DirEntry)>> {
+    let mut children = Vec::new();
+    let mut dir = fs::read_dir(path).await?;
+    while let Some(child) = dir.next_entry().await? {
+        children.push((child.file_type().await?, child));
+    }
+    Ok(children)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum CopyError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("strip prefix: {0}")]
     StripPrefix(#[from] StripPrefixError),
-    #[error("failed to get parent")]
-    Orphan,
 }
 
-pub async fn copy_dir(src_dir: &PathBuf, dst_dir: &PathBuf) -> Result<(), CopyError> {
-    let entries = visit_stream(&src_dir);
+/// Slack for filesystems that round timestamps or clocks that drift a little.
+const FUTURE_MTIME_TOLERANCE: Duration = Duration::from_secs(60);
+
+/// How many source files may share a modification time before it is taken
+/// for a clock or extraction artifact rather than when they were written.
+const SHARED_MTIME_LIMIT: usize = 32;
+
+/// Tracks source modification times that cannot be trusted for skipping.
+#[derive(Default)]
+struct MtimeGuard {
+    counts: HashMap<SystemTime, usize>,
+    warned_future: bool,
+    warned_shared: bool,
+}
+
+impl MtimeGuard {
+    fn is_plausible(&mut self, path: &Path, mtime: SystemTime) -> bool {
+        if mtime > SystemTime::now() + FUTURE_MTIME_TOLERANCE {
+            if !std::mem::replace(&mut self.warned_future, true) {
+                tracing::warn!(
+                    path = %path.display(),
+                    "modification time is in the future, comparing contents instead"
+                );
+            }
+            return false;
+        }
+
+        let count = self.counts.entry(mtime).or_default();
+        *count += 1;
+        if *count > SHARED_MTIME_LIMIT {
+            if !std::mem::replace(&mut self.warned_shared, true) {
+                tracing::warn!(
+                    path = %path.display(),
+                    "over {SHARED_MTIME_LIMIT} files share a modification time, comparing contents instead"
+                );
+            }
+            return false;
+        }
+        true
+    }
+}
+
+/// Mirrors `src_dir` into `dst_dir`.
+///
+/// Files whose destination already has the same size and modification time,
+/// or the same size and hash, are left alone, so an interrupted copy can be
+/// retried. Modification times in the future, or shared by suspiciously many
+/// files, are not trusted, so those files are only skipped if the hashes match.
+/// With `hard_link`, other files are linked instead of copied where the
+/// filesystem allows it, falling back to a full copy.
+pub async fn copy_dir(
+    src_dir: &Path,
+    dst_dir: &Path,
+    hard_link: bool,
+    walk_concurrency: usize,
+) -> Result<(), CopyError> {
+    if !fs::try_exists(src_dir).await? {
+        return Ok(());
+    }
+    fs::create_dir_all(dst_dir).await?;
+
+    let mut mtime_guard = MtimeGuard::default();
+    let entries = visit_stream_concurrent(src_dir, walk_concurrency);
     pin_mut!(entries);
-    while let Some((_, entry)) = entries.next().await.transpose()? {
+    while let Some((file_type, entry)) = entries.next().await.transpose()? {
         let src_path = entry.path();
-        let relative_path = src_path.strip_prefix(&src_dir)?;
+        let relative_path = src_path.strip_prefix(src_dir)?;
         let dst_path = dst_dir.join(relative_path);
 
-        let dst_parent = dst_path.parent().ok_or(CopyError::Orphan)?;
-        tokio::fs::create_dir(dst_parent).await?;
+        if file_type.is_dir() {
+            fs::create_dir_all(&dst_path).await?;
+            continue;
+        }
 
-        File::create_new(&dst_path).await?;
-        tokio::fs::copy(src_path, dst_path).await?;
+        let src_meta = entry.metadata().await?;
+        let src_modified = src_meta.modified()?;
+        let mtime_plausible = mtime_guard.is_plausible(&src_path, src_modified);
+        match fs::symlink_metadata(&dst_path).await {
+            Ok(dst_meta) if dst_meta.len() == src_meta.len() => {
+                if mtime_plausible && dst_meta.modified()? == src_modified {
+                    continue;
+                }
+                if dst_meta.is_file() && hash_file(&src_path).await? == hash_file(&dst_path).await?
+                {
+                    set_modified(&dst_path, src_modified).await?;
+                    continue;
+                }
+                fs::remove_file(&dst_path).await?;
+            }
+            Ok(_) => fs::remove_file(&dst_path).await?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        if hard_link && fs::hard_link(&src_path, &dst_path).await.is_ok() {
+            continue;
+        }
+
+        // Copy through a temporary file so a partial copy is never mistaken
+        // for a complete one.
+        let part_path = append_extension(&dst_path, "part");
+        fs::copy(&src_path, &part_path).await?;
+        // Keep the source mtime so the next migration can skip this file.
+        set_modified(&part_path, src_modified).await?;
+        fs::rename(&part_path, &dst_path).await?;
     }
     Ok(())
 }
+
+async fn set_modified(path: &Path, time: SystemTime) -> std::io::Result<()> {
+    let file = File::options().write(true).open(path).await?;
+    file.into_std().await.set_modified(time)
+}
+
+/// Sums the sizes of all files below `path`.
+pub async fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    let entries = visit_stream(path);
+    pin_mut!(entries);
+    while let Some((file_type, entry)) = entries.next().await.transpose()? {
+        if file_type.is_file() {
+            size += entry.metadata().await?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Creates `dst` sharing the contents of `src` without copying them: as a
+/// copy-on-write clone where the filesystem supports it, otherwise as a
+/// hard link if `hard_link` allows it. A hard link is the same file, so
+/// writes to either reach both. Returns whether `dst` was created.
+pub async fn link_file(src: &Path, dst: &Path, hard_link: bool) -> std::io::Result<bool> {
+    remove_existing(dst).await?;
+    let (clone_src, clone_dst) = (src.to_path_buf(), dst.to_path_buf());
+    let cloned =
+        tokio::task::spawn_blocking(move || reflink_copy::reflink(clone_src, clone_dst).is_ok())
+            .await?;
+    if cloned {
+        return Ok(true);
+    }
+    Ok(hard_link && fs::hard_link(src, dst).await.is_ok())
+}
+
+/// Removes `path` if it exists. Writing to a fresh file instead of truncating
+/// the old one keeps the write from reaching other hard links to it.
+pub async fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `dir` is on a filesystem that ignores case in file names, probed by
+/// creating a file and looking it up under a lowercased name.
+pub async fn is_case_insensitive(dir: &Path) -> std::io::Result<bool> {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let probe_name = format!(".CaseProbe-{}-{nanos}", std::process::id());
+    let probe_path = dir.join(&probe_name);
+    File::create(&probe_path).await?;
+    let insensitive = fs::try_exists(dir.join(probe_name.to_lowercase())).await;
+    fs::remove_file(&probe_path).await?;
+    insensitive
+}
+
+/// Appends `.{extension}` to the full file name, keeping any existing extension.
+pub fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    path.into()
+}
+
+/// Incremental hash of file contents with the algorithm a manifest declares.
+pub enum FileHasher {
+    Blake3(Box<Blake3Hash>),
+    Sha256(Sha256),
+}
+
+impl FileHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        match self {
+            Self::Blake3(hash) => {
+                hash.update(buf);
+            }
+            Self::Sha256(hash) => hash.update(buf),
+        }
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        match self {
+            Self::Blake3(hash) => hash.finish(),
+            Self::Sha256(hash) => hash.finalize().into(),
+        }
+    }
+}
+
+/// Blake3 hashes of consecutive blocks of a stream, the last of which may be short.
+pub struct BlockHasher {
+    block_len: u64,
+    filled: u64,
+    hash: Blake3Hash,
+    blocks: Vec<[u8; 32]>,
+}
+
+impl BlockHasher {
+    pub fn new(block_len: u64) -> Self {
+        Self {
+            block_len: block_len.max(1),
+            filled: 0,
+            hash: Blake3Hash::default(),
+            blocks: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let take = buf.len().min((self.block_len - self.filled) as usize);
+            self.hash.update(&buf[..take]);
+            self.filled += take as u64;
+            buf = &buf[take..];
+            if self.filled == self.block_len {
+                self.blocks.push(std::mem::take(&mut self.hash).finish());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Hashes of the blocks that are complete so far.
+    pub fn completed(&self) -> &[[u8; 32]] {
+        &self.blocks
+    }
+
+    pub fn finish(mut self) -> Vec<[u8; 32]> {
+        if self.filled > 0 {
+            self.blocks.push(self.hash.finish());
+        }
+        self.blocks
+    }
+}
+
+/// Contents of a file, memory-mapped where the filesystem allows it.
+pub enum FileContents {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for FileContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mmap) => mmap,
+            Self::Read(buf) => buf,
+        }
+    }
+}
+
+/// Maps `file` into memory, or reads it whole if mapping is not supported,
+/// as on some network mounts. Empty files are never mapped, since mapping
+/// zero bytes fails on some platforms.
+pub async fn map_or_read(mut file: File) -> std::io::Result<FileContents> {
+    let len = file.metadata().await?.len();
+    if len == 0 {
+        return Ok(FileContents::Read(Vec::new()));
+    }
+    match unsafe { Mmap::map(&file) } {
+        Ok(mmap) => Ok(FileContents::Mapped(mmap)),
+        Err(err) => {
+            tracing::debug!("failed to map file, reading it instead: {err}");
+            let mut buf = Vec::with_capacity(len as usize);
+            file.read_to_end(&mut buf).await?;
+            Ok(FileContents::Read(buf))
+        }
+    }
+}
+
+pub async fn hash_file(path: &Path) -> std::io::Result<[u8; 32]> {
+    hash_file_as(path, HashAlgorithm::Blake3).await
+}
+
+pub async fn hash_file_as(path: &Path, algorithm: HashAlgorithm) -> std::io::Result<[u8; 32]> {
+    let mut file = File::open(path).await?;
+    let mut hash = FileHasher::new(algorithm);
+    let mut buf = vec![0u8; 1024 * 64];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buf[..read]);
+    }
+    Ok(hash.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn modifying_a_linked_file_keeps_its_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src.pak"), dir.path().join("dst.pak"));
+        std::fs::write(&src, "old").unwrap();
+
+        if link_file(&src, &dst, false).await.unwrap() {
+            // Writing in place, as a game would, rather than replacing the file.
+            std::fs::write(&dst, "new").unwrap();
+            assert_eq!(std::fs::read_to_string(&src).unwrap(), "old");
+        } else {
+            assert!(!dst.exists());
+        }
+
+        assert!(link_file(&src, &dst, true).await.unwrap());
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[tokio::test]
+    async fn interrupted_copy_is_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src_dir, dst_dir) = (dir.path().join("src"), dir.path().join("dst"));
+        std::fs::create_dir_all(src_dir.join("SaveGames")).unwrap();
+        std::fs::write(src_dir.join("Game.ini"), "a".repeat(1000)).unwrap();
+        std::fs::write(src_dir.join("Input.ini"), "b".repeat(1000)).unwrap();
+        std::fs::write(src_dir.join("SaveGames/Slot1.sav"), "c".repeat(1000)).unwrap();
+        copy_dir(&src_dir, &dst_dir, false, 1).await.unwrap();
+
+        // Leave the destination like a copy cut off midway: one file written
+        // partially in place, one only as far as its temporary file, and
+        // one already done, marked to tell whether it is copied again.
+        std::fs::write(dst_dir.join("Input.ini"), "b".repeat(400)).unwrap();
+        std::fs::remove_file(dst_dir.join("SaveGames/Slot1.sav")).unwrap();
+        std::fs::write(dst_dir.join("SaveGames/Slot1.sav.part"), "c".repeat(10)).unwrap();
+        let done_path = dst_dir.join("Game.ini");
+        std::fs::write(&done_path, "A".repeat(1000)).unwrap();
+        let src_modified = std::fs::metadata(src_dir.join("Game.ini"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        set_modified(&done_path, src_modified).await.unwrap();
+
+        copy_dir(&src_dir, &dst_dir, false, 1).await.unwrap();
+        let read = |path: &str| std::fs::read_to_string(dst_dir.join(path)).unwrap();
+        assert_eq!(read("Input.ini"), "b".repeat(1000));
+        assert_eq!(read("SaveGames/Slot1.sav"), "c".repeat(1000));
+        assert!(!dst_dir.join("SaveGames/Slot1.sav.part").exists());
+        // Matching size and modification time count as already copied.
+        assert_eq!(read("Game.ini"), "A".repeat(1000));
+    }
+}
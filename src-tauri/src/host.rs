@@ -0,0 +1,76 @@
+//! The app an install runs in, abstracted so installs can also run headless.
+
+use std::collections::HashMap;
+
+use semver::Version;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+/// Receives the events an install reports its progress with, such as
+/// `install-progress`, so it does not need a window to report to.
+pub(crate) trait ProgressSink: Send + Sync {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<(), tauri::Error>;
+}
+
+/// What the install core reads from the app it runs in besides reporting
+/// progress, so it can also run without one like [`HeadlessHost`].
+pub(crate) trait InstallHost: ProgressSink {
+    /// Configuration of `plugin` from `plugins` in the app config.
+    fn plugin_config(&self, plugin: &str) -> Option<&serde_json::Value>;
+
+    /// Version of the running launcher.
+    fn launcher_version(&self) -> &Version;
+}
+
+impl dyn InstallHost + '_ {
+    pub(crate) fn emit(&self, event: &str, payload: impl Serialize) -> Result<(), tauri::Error> {
+        self.emit_event(event, serde_json::to_value(payload)?)
+    }
+}
+
+impl ProgressSink for AppHandle {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<(), tauri::Error> {
+        Emitter::emit(self, event, payload)
+    }
+}
+
+impl InstallHost for AppHandle {
+    fn plugin_config(&self, plugin: &str) -> Option<&serde_json::Value> {
+        self.config().plugins.0.get(plugin)
+    }
+
+    fn launcher_version(&self) -> &Version {
+        &self.package_info().version
+    }
+}
+
+/// Runs installs without a window, for provisioning install directories from
+/// scripts and CI. Events are logged at debug level instead of emitted.
+pub(crate) struct HeadlessHost {
+    plugins: HashMap<String, serde_json::Value>,
+    version: Version,
+}
+
+impl HeadlessHost {
+    /// A host reading `plugins` as the app config would have them.
+    pub(crate) fn new(plugins: HashMap<String, serde_json::Value>, version: Version) -> Self {
+        Self { plugins, version }
+    }
+}
+
+impl ProgressSink for HeadlessHost {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<(), tauri::Error> {
+        tracing::debug!(event, %payload, "install event");
+        Ok(())
+    }
+}
+
+impl InstallHost for HeadlessHost {
+    fn plugin_config(&self, plugin: &str) -> Option<&serde_json::Value> {
+        self.plugins.get(plugin)
+    }
+
+    fn launcher_version(&self) -> &Version {
+        &self.version
+    }
+}
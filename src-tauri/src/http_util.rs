@@ -0,0 +1,350 @@
+use std::{future::Future, ops::RangeInclusive, time::Duration};
+
+use tauri::Url;
+use tauri_plugin_http::reqwest::{
+    self,
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, LOCATION, RANGE, USER_AGENT},
+    redirect, RequestBuilder, Response, StatusCode,
+};
+use tokio_util::bytes::Bytes;
+
+use crate::install::{InstallError, InstallOptions};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum RedirectError {
+    #[error("redirect loop detected")]
+    Loop,
+    #[error("exceeded {0} redirects")]
+    TooMany(usize),
+}
+
+/// HTTP client shared by all manifest and archive requests of an install.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    /// Follows redirects to other hosts, without the custom headers
+    /// and token that [`Self::client`] sends.
+    foreign_client: reqwest::Client,
+    max_retries: u32,
+    max_redirects: usize,
+    stall_timeout: Duration,
+    download_parallelism: usize,
+}
+
+impl HttpClient {
+    pub(crate) fn new(options: &InstallOptions) -> Result<Self, InstallError> {
+        let headers = get_default_headers(options)?;
+        let mut foreign_headers = HeaderMap::new();
+        if let Some(user_agent) = headers.get(USER_AGENT) {
+            foreign_headers.insert(USER_AGENT, user_agent.clone());
+        }
+        Ok(Self {
+            client: build_client(options, headers)?,
+            foreign_client: build_client(options, foreign_headers)?,
+            max_retries: options.max_retries(),
+            max_redirects: options.max_redirects(),
+            stall_timeout: options.stall_timeout(),
+            download_parallelism: options.download_parallelism(),
+        })
+    }
+
+    /// How long an archive download may go without receiving any bytes.
+    pub(crate) fn stall_timeout(&self) -> Duration {
+        self.stall_timeout
+    }
+
+    /// Byte ranges a large archive may be downloaded in at once.
+    pub(crate) fn download_parallelism(&self) -> usize {
+        self.download_parallelism
+    }
+
+    /// Sends a GET request, retrying transient failures until a response arrives.
+    pub(crate) async fn get(&self, url: &Url) -> Result<Response, InstallError> {
+        self.retry(|| self.send(url, |client, url| client.get(url)))
+            .await
+    }
+
+    /// Fetches a whole (small) response body, retrying transient failures.
+    pub(crate) async fn get_bytes(&self, url: &Url) -> Result<Bytes, InstallError> {
+        self.retry(|| async {
+            let response = self.send(url, |client, url| client.get(url)).await?;
+            Ok(response.bytes().await?)
+        })
+        .await
+    }
+
+    /// Sends a GET request for a byte range, retrying transient failures.
+    pub(crate) async fn get_range(
+        &self,
+        url: &Url,
+        range: RangeInclusive<u64>,
+    ) -> Result<Response, InstallError> {
+        let range = format!("bytes={}-{}", range.start(), range.end());
+        self.retry(|| self.send(url, |client, url| client.get(url).header(RANGE, &range)))
+            .await
+    }
+
+    /// Sends a GET request for the bytes from `start` on, retrying transient failures.
+    pub(crate) async fn get_from(&self, url: &Url, start: u64) -> Result<Response, InstallError> {
+        let range = format!("bytes={start}-");
+        self.retry(|| self.send(url, |client, url| client.get(url).header(RANGE, &range)))
+            .await
+    }
+
+    /// Sends a HEAD request once.
+    pub(crate) async fn head(&self, url: &Url) -> Result<Response, InstallError> {
+        self.send(url, |client, url| client.head(url)).await
+    }
+
+    /// Sends the request made by `request` to `url`, following redirects here
+    /// so the whole chain is limited and checked for loops, even where it
+    /// crosses hosts. Once it leaves the host of `url`, requests are sent from
+    /// [`Self::foreign_client`], so other hosts get none of the credentials
+    /// meant for the first one.
+    async fn send(
+        &self,
+        url: &Url,
+        request: impl Fn(&reqwest::Client, Url) -> RequestBuilder,
+    ) -> Result<Response, InstallError> {
+        let mut client = &self.client;
+        let mut visited = vec![url.clone()];
+        let mut response = request(client, url.clone()).send().await?;
+        while let Some(next_url) = get_redirect_url(&response) {
+            let source = if visited.contains(&next_url) {
+                Some(RedirectError::Loop)
+            } else if visited.len() > self.max_redirects {
+                Some(RedirectError::TooMany(self.max_redirects))
+            } else {
+                None
+            };
+            if let Some(source) = source {
+                return Err(InstallError::Redirect {
+                    url: next_url,
+                    source,
+                });
+            }
+            if next_url.host_str() != url.host_str() {
+                client = &self.foreign_client;
+            }
+            visited.push(next_url.clone());
+            response = request(client, next_url).send().await?;
+        }
+        Ok(response.error_for_status()?)
+    }
+
+    async fn retry<T, F, Fut>(&self, mut f: F) -> Result<T, InstallError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, InstallError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(err) if attempt < self.max_retries && is_retryable(&err) => {
+                    tokio::time::sleep(get_backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Where a redirect `response` points, if it is one.
+fn get_redirect_url(response: &Response) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    response.url().join(location).ok()
+}
+
+/// Builds a client sending `headers` with every request.
+/// Redirects are returned as they are, to be followed by [`HttpClient::send`].
+fn build_client(
+    options: &InstallOptions,
+    headers: HeaderMap,
+) -> Result<reqwest::Client, InstallError> {
+    let mut builder = reqwest::Client::builder()
+        .default_headers(headers)
+        .redirect(redirect::Policy::none())
+        .connect_timeout(options.connect_timeout())
+        .read_timeout(options.read_timeout())
+        .pool_max_idle_per_host(options.pool_max_idle_per_host())
+        .pool_idle_timeout(Duration::from_secs(90))
+        .tcp_keepalive(Duration::from_secs(60));
+    if let Some(timeout) = options.request_timeout() {
+        builder = builder.timeout(timeout);
+    }
+    builder = if options.http1_only {
+        builder.http1_only()
+    } else {
+        // Lets the few large archive streams use the available bandwidth.
+        builder.http2_adaptive_window(true)
+    };
+    Ok(builder.build()?)
+}
+
+fn get_backoff(attempt: u32) -> Duration {
+    Duration::from_millis(250 << attempt.min(6))
+}
+
+/// Whether a request may succeed when repeated. Client errors
+/// (bad URL, missing file, denied access) are never retried.
+fn is_retryable(err: &InstallError) -> bool {
+    let InstallError::Reqwest(err) = err else {
+        return false;
+    };
+    match err.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+    }
+}
+
+fn get_default_headers(options: &InstallOptions) -> Result<HeaderMap, InstallError> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in &options.headers {
+        let name =
+            HeaderName::try_from(name).map_err(|_| InstallError::InvalidHeader(name.clone()))?;
+        let value = HeaderValue::try_from(value)
+            .map_err(|_| InstallError::InvalidHeader(format!("invalid value for {name}")))?;
+        headers.insert(name, value);
+    }
+    if let Some(user_agent) = &options.user_agent {
+        let value = HeaderValue::try_from(user_agent)
+            .map_err(|_| InstallError::InvalidHeader(format!("invalid value for {USER_AGENT}")))?;
+        headers.insert(USER_AGENT, value);
+    }
+
+    if let Some(token) = &options.auth_token {
+        let name = match &options.auth_header {
+            Some(name) => {
+                HeaderName::try_from(name).map_err(|_| InstallError::InvalidHeader(name.clone()))?
+            }
+            None => AUTHORIZATION,
+        };
+        let value = if name == AUTHORIZATION {
+            format!("Bearer {token}")
+        } else {
+            token.clone()
+        };
+        let mut value = HeaderValue::try_from(value)
+            .map_err(|_| InstallError::InvalidHeader(name.to_string()))?;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Answers one request on `listener` with `response`, returning the
+    /// lowercased request headers.
+    async fn answer_once(listener: TcpListener, response: String) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut headers = Vec::new();
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            headers.push(line.trim_end().to_lowercase());
+        }
+        stream
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+        headers
+    }
+
+    #[tokio::test]
+    async fn credentials_are_not_sent_to_another_host() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let other = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let first_url = Url::parse(&format!(
+            "http://localhost:{}/manifest.json",
+            first.local_addr().unwrap().port()
+        ))
+        .unwrap();
+        let other_url = format!("http://{}/manifest.json", other.local_addr().unwrap());
+        let first_task = tokio::spawn(answer_once(
+            first,
+            format!("HTTP/1.1 302 Found\r\nLocation: {other_url}\r\nContent-Length: 0\r\n\r\n"),
+        ));
+        let other_task = tokio::spawn(answer_once(
+            other,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}".into(),
+        ));
+
+        let options = InstallOptions {
+            auth_token: Some("secret".into()),
+            headers: [("X-Pack-Key".to_string(), "key".to_string())].into(),
+            user_agent: Some("packwisely-test".into()),
+            ..Default::default()
+        };
+        let http = HttpClient::new(&options).unwrap();
+        assert_eq!(&http.get_bytes(&first_url).await.unwrap()[..], b"{}");
+
+        let first_headers = first_task.await.unwrap();
+        assert!(first_headers.contains(&"authorization: bearer secret".to_string()));
+        assert!(first_headers.contains(&"x-pack-key: key".to_string()));
+        let other_headers = other_task.await.unwrap();
+        assert!(
+            other_headers
+                .iter()
+                .all(|header| !header.starts_with("authorization")
+                    && !header.starts_with("x-pack-key")),
+            "{other_headers:?}"
+        );
+        assert!(other_headers.contains(&"user-agent: packwisely-test".to_string()));
+    }
+
+    #[tokio::test]
+    async fn redirect_loops_across_hosts_are_detected() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let other = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let first_url = format!(
+            "http://localhost:{}/manifest.json",
+            first.local_addr().unwrap().port()
+        );
+        let other_url = format!("http://{}/manifest.json", other.local_addr().unwrap());
+        let first_task = tokio::spawn(answer_once(
+            first,
+            format!("HTTP/1.1 302 Found\r\nLocation: {other_url}\r\nContent-Length: 0\r\n\r\n"),
+        ));
+        let other_task = tokio::spawn(answer_once(
+            other,
+            format!("HTTP/1.1 302 Found\r\nLocation: {first_url}\r\nContent-Length: 0\r\n\r\n"),
+        ));
+
+        let http = HttpClient::new(&InstallOptions::default()).unwrap();
+        let err = http
+            .get_bytes(&Url::parse(&first_url).unwrap())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                InstallError::Redirect {
+                    source: RedirectError::Loop,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        first_task.await.unwrap();
+        other_task.await.unwrap();
+    }
+}
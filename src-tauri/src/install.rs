@@ -1,35 +1,78 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{ErrorKind, Read, Seek, Write},
-    path::PathBuf,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
     sync::atomic,
-    time::Instant,
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use async_compat::CompatExt;
-use async_compression::tokio::bufread::ZstdDecoder;
+use async_compat::{Compat, CompatExt};
+use async_compression::{tokio::bufread::ZstdDecoder, zstd::DParameter};
+use async_stream::try_stream;
 use fast_rsync::sum_hash::{Blake3Hash, SumHash};
-use futures::StreamExt;
+use futures::{future::Either, pin_mut, Future, Stream, StreamExt, TryStreamExt};
 use memmap2::Mmap;
 use semver::Version;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tauri::{AppHandle, Emitter, Url};
-use tauri_plugin_http::reqwest::{self, IntoUrl, Response};
+use serde::{de::DeserializeOwned, ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use serde_with::{base64::Base64, serde_as};
+use tauri::Url;
+use tauri_plugin_http::reqwest::{self, Response};
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, ReadBuf},
 };
-use tokio_util::io::StreamReader;
+use tokio_util::{bytes::Bytes, io::StreamReader};
 
 use crate::{
-    file_util::{copy_dir, CopyError},
-    wine_util::get_wine_path,
-    PatchManifest,
+    cache::ArchiveCache,
+    delta_check::{check_delta, DeltaError},
+    file_util::{
+        append_extension, copy_dir, dir_size, hash_file, hash_file_as, is_case_insensitive,
+        link_file, map_or_read, remove_existing, visit_stream, visit_stream_concurrent,
+        BlockHasher, CopyError, FileHasher,
+    },
+    find_case_collision, find_duplicate_path,
+    host::InstallHost,
+    http_util::{HttpClient, RedirectError},
+    launch::{is_arch, is_os, needs_wine},
+    layout::{InstallLayout, LayoutError},
+    telemetry::{InstallTelemetry, TelemetryOperation},
+    wine_util::{
+        expand_native_vars, expand_wine_vars, get_wine_path, get_wine_prefix, to_host_path,
+    },
+    ArchiveCompression, FileManifest, HashAlgorithm, ManifestDelta, PatchManifest,
+    PatchManifestVersion, COMBINED_ARCHIVE_NAME,
 };
 
+#[serde_as]
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct ChannelManifest {
     name: String,
+    #[serde(default)]
+    update_policy: UpdatePolicy,
+    /// Hash of the channel's `versions.json`, checked before it is trusted.
+    #[serde_as(as = "Option<Base64>")]
+    versions_hash: Option<[u8; 32]>,
+    /// Serve pre-release versions like `1.2.0-rc1` as the latest version.
+    #[serde(default)]
+    allow_prerelease: bool,
+}
+
+/// How a channel moves to newer versions.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum UpdatePolicy {
+    /// Install the latest version on launch.
+    #[default]
+    Auto,
+    /// Report newer versions, installing them only with [`InstallOptions::confirm_update`].
+    Manual,
+    /// Stay on the pinned version, refusing anything newer.
+    Locked(Version),
 }
 impl ChannelManifest {
     fn join_url(&self, root_url: &Url) -> Result<Url, url::ParseError> {
@@ -41,6 +84,9 @@ impl ChannelManifest {
 struct VersionManifest {
     version: Version,
     platforms: Vec<PlatformManifest>,
+    /// Oldest launcher able to install this version.
+    #[serde(default)]
+    min_launcher_version: Option<Version>,
 }
 impl VersionManifest {
     fn join_url(&self, channel_url: &Url) -> Result<Url, url::ParseError> {
@@ -48,12 +94,43 @@ impl VersionManifest {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct PlatformManifest {
-    os: String,
-    arch: String,
-    exe_path: String,
+pub(crate) struct PlatformManifest {
+    pub(crate) os: String,
+    pub(crate) arch: String,
+    pub(crate) exe_path: String,
+    /// Extra arguments passed to the executable.
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+    /// Working directory relative to the install directory.
+    #[serde(default)]
+    pub(crate) cwd: Option<String>,
+    /// Environment variables merged into the launch environment.
+    #[serde(default)]
+    pub(crate) env: HashMap<String, String>,
+    /// Where the build keeps its saves, relative to the install directory or
+    /// absolute with `%VARIABLE%`s, like `%LOCALAPPDATA%\PackWisely\Saved`.
+    /// Defaults to `PackWisely/Saved` in the install directory.
+    #[serde(default)]
+    pub(crate) save_dir: Option<String>,
+    /// Hash of the platform's `manifest.json`, checked before it is trusted.
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) manifest_hash: Option<[u8; 32]>,
+    /// Version the platform's `manifest-delta.json` is relative to, if one is published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) manifest_delta_base: Option<Version>,
+    /// Hash of the platform's `manifest-delta.json`, checked before it is trusted.
+    /// Required for the delta to be used if [`Self::manifest_hash`] is set.
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) manifest_delta_hash: Option<[u8; 32]>,
+    /// Whether the platform is published as a single [`COMBINED_ARCHIVE_NAME`]
+    /// instead of a manifest and separate archives.
+    #[serde(default)]
+    pub(crate) combined: bool,
 }
 impl PlatformManifest {
     fn join_url(&self, version_url: &Url) -> Result<Url, url::ParseError> {
@@ -65,10 +142,18 @@ impl PlatformManifest {
 pub(crate) enum InstallError {
     #[error("missing root URL")]
     MissingRootUrl,
+    #[error("{path} and {other} differ only in case, and the install directory does not tell them apart")]
+    CaseCollision { path: String, other: String },
+    #[error("assets path must be a relative path separated by \"/\": {0}")]
+    InvalidAssetsPath(String),
     #[error("unknown release channel")]
     UnknownChannel,
     #[error("unknown version")]
     UnknownVersion,
+    #[error("channel is locked to version {0}")]
+    UpdateLocked(Version),
+    #[error("version requires launcher {required} or newer, but this is {current}; please update your launcher")]
+    LauncherOutdated { required: Version, current: Version },
     #[error("unsupported architecture")]
     UnsupportedArch,
     #[error("unsupported operating system")]
@@ -76,41 +161,153 @@ pub(crate) enum InstallError {
     #[error(transparent)]
     InvalidUrl(#[from] url::ParseError),
     #[error(transparent)]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
+    #[error("download interrupted: {0}")]
+    Download(std::io::Error),
+    #[error("downloaded archive is corrupt: {0}")]
+    Decompress(std::io::Error),
     #[error(transparent)]
-    Reqwest(#[from] tauri_plugin_http::reqwest::Error),
+    Reqwest(tauri_plugin_http::reqwest::Error),
+    #[error("failed to follow redirect to {url}: {source}")]
+    Redirect {
+        url: Url,
+        #[source]
+        source: RedirectError,
+    },
+    #[error("access denied: {0}")]
+    Unauthorized(tauri_plugin_http::reqwest::Error),
+    #[error("invalid HTTP header: {0}")]
+    InvalidHeader(String),
+    #[error("invalid local source path: {0}")]
+    InvalidLocalPath(String),
     #[error(transparent)]
     InvalidVersion(#[from] semver::Error),
     #[error("failed to create directory: {0}")]
     CreateDir(std::io::Error),
+    #[error("{0} is a file where a directory is expected, remove it and try again")]
+    FileInPlaceOfDir(PathBuf),
     #[error(transparent)]
     Tauri(#[from] tauri::Error),
     #[error("invalid archive path: {0}")]
     InvalidArchivePath(PathBuf),
+    #[error("executable path escapes the install directory: {0}")]
+    InvalidExePath(String),
+    #[error("working directory escapes the install directory: {0}")]
+    InvalidCwd(String),
     #[error("invalid patch in installed directory: {0}")]
     InvalidInstalledPatch(serde_json::Error),
     #[error("missing previous version")]
     MissingPreviousVersion,
+    #[error("no previous version to roll back to")]
+    NothingToRollBack,
+    #[error("channel is not installed")]
+    NotInstalled,
+    #[error("{0} is missing, repair the install")]
+    MissingInstalledFile(String),
+    #[error("previous version {0} is no longer retained, install it normally instead")]
+    PreviousVersionPruned(Version),
     #[error("unexpected file in archive: {0}")]
     UnexpectedArchiveFile(PathBuf),
-    #[error(transparent)]
-    DiffApplyError(#[from] fast_rsync::ApplyError),
-    #[error("wrong size: {expected} != {actual}")]
-    WrongSize { expected: u64, actual: u64 },
-    #[error("wrong hash: 0x{expected} != 0x{actual}")]
-    WrongHash { expected: String, actual: String },
+    #[error("file missing from archive: {0}")]
+    MissingArchiveFile(PathBuf),
+    #[error("unsupported {kind} entry in archive: {path}")]
+    UnsupportedArchiveEntry { path: PathBuf, kind: String },
+    #[error("failed to apply diff to {path}: {source}")]
+    DiffApplyFailed {
+        path: PathBuf,
+        source: fast_rsync::ApplyError,
+    },
+    #[error("malformed diff for {path}: {source}")]
+    MalformedDelta { path: PathBuf, source: DeltaError },
+    #[error("wrong size of {path}: {expected} != {actual}")]
+    WrongSize {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("block {block} of {path} does not match its hash, the download is corrupt")]
+    WrongBlockHash { path: PathBuf, block: usize },
+    #[error("wrong hash of {path}: 0x{expected} != 0x{actual}")]
+    WrongHash {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
     #[error(transparent)]
     Json(#[from] serde_json::Error),
+    #[error("malformed manifest: {reason}")]
+    MalformedManifest { reason: String },
     #[error(transparent)]
     CopyError(#[from] CopyError),
+    #[error(transparent)]
+    InvalidLayout(#[from] LayoutError),
+    #[error("failed to resolve save directory: {0}")]
+    UnresolvedSaveDir(String),
+    #[error("invalid configured hash: {0}")]
+    InvalidConfiguredHash(String),
+    #[error("server ignored range request for {0}")]
+    RangeNotHonored(String),
+}
+
+impl From<std::io::Error> for InstallError {
+    /// Tells download and decompression failures apart from other I/O errors
+    /// by the markers they were tagged with on the way out of their readers.
+    fn from(err: std::io::Error) -> Self {
+        if io_error_has::<DownloadReadError>(&err) {
+            InstallError::Download(err)
+        } else if io_error_has::<DecompressReadError>(&err) {
+            InstallError::Decompress(err)
+        } else {
+            InstallError::Io(err)
+        }
+    }
+}
+
+impl From<reqwest::Error> for InstallError {
+    fn from(err: reqwest::Error) -> Self {
+        use reqwest::StatusCode;
+
+        if matches!(
+            err.status(),
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        ) {
+            InstallError::Unauthorized(err)
+        } else {
+            InstallError::Reqwest(err)
+        }
+    }
 }
 
-fn get_root_url(app: &AppHandle) -> Result<Url, InstallError> {
+/// Path of the content tree on the updater endpoint host, unless
+/// `plugins.installer.assetsPath` overrides it.
+const DEFAULT_ASSETS_PATH: &str = "assets/PackWisely/";
+
+/// Resolves where game content is served from, in order: the install source,
+/// the root URL option, `plugins.installer.rootUrl` in the app config,
+/// then [`get_assets_path`] on the updater endpoint host.
+fn get_root_url(app: &dyn InstallHost, options: &InstallOptions) -> Result<Url, InstallError> {
+    match &options.source {
+        Some(InstallSource::Remote(root_url)) => return parse_root_url(root_url),
+        Some(InstallSource::Local(path)) => {
+            return Url::from_directory_path(path)
+                .map_err(|_| InstallError::InvalidLocalPath(path.to_string_lossy().into()));
+        }
+        None => {}
+    }
+
+    if let Some(root_url) = &options.root_url {
+        return parse_root_url(root_url);
+    }
+
+    let configured_root_url = app
+        .plugin_config("installer")
+        .and_then(|o| o.get("rootUrl").and_then(|o| o.as_str()));
+    if let Some(root_url) = configured_root_url {
+        return parse_root_url(root_url);
+    }
+
     let updater_endpoints = app
-        .config()
-        .plugins
-        .0
-        .get("updater")
+        .plugin_config("updater")
         .and_then(|o| o.get("endpoints").and_then(|o| o.as_array()));
 
     let first_endpoint =
@@ -120,460 +317,5480 @@ fn get_root_url(app: &AppHandle) -> Result<Url, InstallError> {
         .map(|input| Url::parse(input))
         .transpose()?
         .ok_or(InstallError::MissingRootUrl)?;
-    root_url.set_path("assets/PackWisely/");
+    root_url.set_path(&get_assets_path(app)?);
     Ok(root_url)
 }
 
-fn join_install_dir(
-    channel_dir: &PathBuf,
-    version: &Version,
-    platform_mf: &PlatformManifest,
-) -> PathBuf {
-    channel_dir.join(format!(
-        "{}/{}-{}",
-        version, platform_mf.os, platform_mf.arch
-    ))
+/// Reads `plugins.installer.assetsPath`, which has to be a relative
+/// `/`-separated path, and ends it with the slash URLs are joined onto.
+fn get_assets_path(app: &dyn InstallHost) -> Result<String, InstallError> {
+    let configured_path = app
+        .plugin_config("installer")
+        .and_then(|o| o.get("assetsPath").and_then(|o| o.as_str()));
+    let Some(path) = configured_path else {
+        return Ok(DEFAULT_ASSETS_PATH.into());
+    };
+
+    let path = path.strip_suffix('/').unwrap_or(path);
+    let is_valid = !path.is_empty()
+        && !path.contains(['\\', ':', '?', '#'])
+        && path
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+    if !is_valid {
+        return Err(InstallError::InvalidAssetsPath(path.into()));
+    }
+    Ok(format!("{path}/"))
+}
+
+/// Local content trees are addressed through `file://` URLs, which the
+/// fetch helpers read from the filesystem instead of over HTTP.
+fn to_local_path(url: &Url) -> Result<Option<PathBuf>, InstallError> {
+    if url.scheme() != "file" {
+        return Ok(None);
+    }
+    url.to_file_path()
+        .map(Some)
+        .map_err(|_| InstallError::InvalidLocalPath(url.to_string()))
+}
+
+fn parse_root_url(input: &str) -> Result<Url, InstallError> {
+    let mut root_url = Url::parse(input)?;
+    // Without a trailing slash, joins would replace the last path segment.
+    if !root_url.path().ends_with('/') {
+        root_url.set_path(&(root_url.path().to_string() + "/"));
+    }
+    Ok(root_url)
+}
+
+/// Refuses to install a version that declares a newer minimum launcher
+/// than the one running, which may not understand its manifest.
+fn check_launcher_version(
+    app: &dyn InstallHost,
+    required: Option<&Version>,
+) -> Result<(), InstallError> {
+    let current = app.launcher_version();
+    match required {
+        Some(required) if current < required => Err(InstallError::LauncherOutdated {
+            required: required.clone(),
+            current: current.clone(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Reads `plugins.installer.installLayout` from the app config,
+/// falling back to [`InstallLayout::default`].
+fn get_install_layout(app: &dyn InstallHost) -> Result<InstallLayout, InstallError> {
+    let configured_layout = app
+        .plugin_config("installer")
+        .and_then(|o| o.get("installLayout").and_then(|o| o.as_str()));
+    match configured_layout {
+        Some(template) => Ok(InstallLayout::parse(template)?),
+        None => Ok(InstallLayout::default()),
+    }
+}
+
+/// Where channels, versions and archives are read from.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum InstallSource {
+    /// Base URL of a published content tree.
+    Remote(String),
+    /// Directory containing `channels.json` and the channel trees, for offline installs.
+    Local(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct InstallOptions {
+    pub(crate) source: Option<InstallSource>,
+    /// Deltas larger than this are spilled to a temporary file next to
+    /// their destination instead of being held in memory. Read buffers are
+    /// shrunk to a quarter of it. This is not a budget for the whole install:
+    /// the source of each delta is still mapped, or read whole where it
+    /// cannot be mapped.
+    pub(crate) spill_threshold_bytes: Option<u64>,
+    /// Number of redirects followed before a request fails.
+    pub(crate) max_redirects: Option<usize>,
+    /// Token sent with every manifest and archive request.
+    pub(crate) auth_token: Option<String>,
+    /// Header carrying [`Self::auth_token`]. The token is sent as-is unless
+    /// this is `Authorization`, in which case it is sent as a bearer token.
+    pub(crate) auth_header: Option<String>,
+    /// Headers sent with every request, for proxies and CDNs requiring them.
+    /// [`Self::auth_token`] and [`Self::user_agent`] take precedence.
+    pub(crate) headers: HashMap<String, String>,
+    /// `User-Agent` sent with every request, for proxies allowing only some.
+    pub(crate) user_agent: Option<String>,
+    /// Base URL of the content tree, containing `channels.json`.
+    pub(crate) root_url: Option<String>,
+    /// Number of times a failed request is retried with exponential backoff.
+    pub(crate) max_retries: Option<u32>,
+    /// Files of at least this size are written through a resumable `.part` file.
+    pub(crate) resume_threshold_bytes: Option<u64>,
+    /// Keep the previous install directory after updating, enabling rollback.
+    pub(crate) keep_previous: bool,
+    /// Number of versions older than the installed one kept by
+    /// [`Self::keep_previous`]. Older ones are pruned after an update.
+    pub(crate) keep_versions: Option<usize>,
+    /// Directory caching downloaded archives across installs.
+    pub(crate) cache_dir: Option<PathBuf>,
+    /// Size cap of [`Self::cache_dir`], beyond which old archives are evicted.
+    pub(crate) cache_max_bytes: Option<u64>,
+    /// Install available updates on channels with [`UpdatePolicy::Manual`].
+    pub(crate) confirm_update: bool,
+    /// Reinstall the current version even if it is up to date, to repair it.
+    pub(crate) force_reinstall: bool,
+    /// Hash every file of an up-to-date install before reporting it as such,
+    /// repairing it if any file is broken. Otherwise only sizes are checked.
+    pub(crate) verify_installed: bool,
+    /// Seconds to wait for a connection to be established.
+    pub(crate) connect_timeout_secs: Option<u64>,
+    /// Seconds a whole request may take, including its body. Unbounded by
+    /// default, since archives can take arbitrarily long on slow connections.
+    pub(crate) request_timeout_secs: Option<u64>,
+    /// Seconds a single read of a response may wait for data.
+    pub(crate) read_timeout_secs: Option<u64>,
+    /// Seconds an archive download may go without receiving any bytes
+    /// before it is aborted, so a rerun can resume it.
+    pub(crate) stall_timeout_secs: Option<u64>,
+    /// Byte ranges a large archive is downloaded in at once, if the server
+    /// supports range requests. The whole archive is then downloaded before
+    /// extracting it, instead of extracting while streaming as with 1, the default.
+    pub(crate) download_parallelism: Option<usize>,
+    /// Idle connections kept open per host for reuse between requests.
+    pub(crate) pool_max_idle_per_host: Option<usize>,
+    /// Disable HTTP/2, which is otherwise negotiated with servers supporting it.
+    pub(crate) http1_only: bool,
+    /// Emit `file-verified` for every file that passes its size and hash check.
+    pub(crate) emit_file_events: bool,
+    /// Consider pre-release versions for the latest version, even on
+    /// channels that do not allow them.
+    pub(crate) allow_prerelease: bool,
+    /// Directories read at once when walking a directory tree, which speeds
+    /// up walks on network filesystems.
+    pub(crate) walk_concurrency: Option<usize>,
+    /// Emit `install-telemetry` once an install, update, repair or heal ends.
+    pub(crate) telemetry: bool,
+}
+
+impl InstallOptions {
+    pub(crate) fn max_retries(&self) -> u32 {
+        self.max_retries.unwrap_or(3)
+    }
+
+    fn keep_versions(&self) -> usize {
+        self.keep_versions.unwrap_or(1)
+    }
+
+    pub(crate) fn max_redirects(&self) -> usize {
+        self.max_redirects.unwrap_or(10)
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs.unwrap_or(15))
+    }
+
+    pub(crate) fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub(crate) fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs.unwrap_or(30))
+    }
+
+    pub(crate) fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout_secs.unwrap_or(60))
+    }
+
+    pub(crate) fn download_parallelism(&self) -> usize {
+        self.download_parallelism.unwrap_or(1).max(1)
+    }
+
+    pub(crate) fn walk_concurrency(&self) -> usize {
+        self.walk_concurrency.unwrap_or(1).max(1)
+    }
+
+    pub(crate) fn pool_max_idle_per_host(&self) -> usize {
+        self.pool_max_idle_per_host.unwrap_or(4)
+    }
+
+    fn read_buf_len(&self) -> usize {
+        const MAX_READ_BUF_LEN: usize = 1024 * 64;
+        const MIN_READ_BUF_LEN: usize = 1024 * 4;
+
+        self.spill_threshold_bytes
+            .map_or(MAX_READ_BUF_LEN, |threshold| {
+                (threshold / 4).clamp(MIN_READ_BUF_LEN as u64, MAX_READ_BUF_LEN as u64) as usize
+            })
+    }
+
+    fn cache_max_bytes(&self) -> u64 {
+        self.cache_max_bytes.unwrap_or(1024 * 1024 * 1024 * 4)
+    }
+
+    fn is_resumable(&self, len: u64) -> bool {
+        len >= self.resume_threshold_bytes.unwrap_or(1024 * 1024 * 256)
+    }
+
+    fn should_spill(&self, len: u64) -> bool {
+        self.spill_threshold_bytes
+            .is_some_and(|threshold| len > threshold)
+    }
+}
+
+/// Emitted with [`InstallOptions::emit_file_events`] for each file whose
+/// size and hash matched its manifest.
+#[derive(Debug, Clone, Serialize)]
+struct FileVerified<'a> {
+    path: &'a str,
+    hash: String,
+    hash_algorithm: HashAlgorithm,
+}
+
+fn emit_file_verified(
+    app: &dyn InstallHost,
+    path: &str,
+    hash: &[u8; 32],
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), tauri::Error> {
+    app.emit(
+        "file-verified",
+        FileVerified {
+            path,
+            hash: hex::encode(hash),
+            hash_algorithm,
+        },
+    )
+}
+
+/// Emitted when no native build exists and a Windows build is run through Wine.
+#[derive(Debug, Clone, Serialize)]
+struct PlatformFallback {
+    os: String,
+    arch: String,
+    native_os: String,
+}
+
+/// Emitted once [`do_install`] has picked what to install, before downloading it.
+#[derive(Debug, Clone, Serialize)]
+struct InstallResolved {
+    channel: String,
+    version: Version,
+    os: String,
+    arch: String,
+    /// Intact version currently installed on the channel, if any.
+    installed_version: Option<Version>,
+    /// Whether another version is replaced, rather than installed fresh.
+    update: bool,
+}
+
+pub(crate) struct InstalledPlatform {
+    pub(crate) install_dir: PathBuf,
+    pub(crate) platform: PlatformManifest,
+    pub(crate) summary: InstallSummary,
+}
+
+/// What an install did, collected from its progress once it is done.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct InstallSummary {
+    /// Compressed bytes downloaded, including manifests.
+    downloaded_bytes: u64,
+    /// Bytes written to the install directory.
+    written_bytes: u64,
+    /// Files of the patch written whole.
+    added_files: usize,
+    /// Files of the patch updated by a delta.
+    changed_files: usize,
+    /// Files of the previous version left out of the new one.
+    removed_files: usize,
+    /// Files that still failed verification after a repair.
+    broken_files: Vec<String>,
+    elapsed_secs: f64,
+}
+
+impl InstallSummary {
+    /// Summarizes an operation that fetched at most manifests, after it finished.
+    fn unchanged(progress: &InstallProgress) -> Self {
+        Self {
+            downloaded_bytes: progress.net.value,
+            elapsed_secs: progress.elapsed_secs.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
 }
 
 pub(crate) async fn do_install(
-    app: &AppHandle,
-    http: &reqwest::Client,
+    app: &dyn InstallHost,
+    http: &HttpClient,
     install_dir: PathBuf,
-) -> Result<PathBuf, InstallError> {
+    options: &InstallOptions,
+) -> Result<InstalledPlatform, InstallError> {
+    let started = Instant::now();
     let mut progress = InstallProgress::default();
+    let mut telemetry = InstallTelemetry::new(TelemetryOperation::Install);
 
-    let root_url = get_root_url(app)?;
+    let result = run_install(
+        app,
+        http,
+        install_dir,
+        options,
+        started,
+        &mut progress,
+        &mut telemetry,
+    )
+    .await;
+    if options.telemetry {
+        telemetry.downloaded_bytes = progress.net.value;
+        telemetry.written_bytes = progress.disk.value;
+        telemetry.elapsed_secs = started.elapsed().as_secs_f64();
+        if let Ok(installed) = &result {
+            telemetry.broken_files = installed.summary.broken_files.len();
+        }
+        telemetry.finish(app, &result);
+    }
+    result
+}
 
-    let channels = get_channels(app, http, &mut progress, &root_url).await?;
-    let channel_mf = channels.get(0).ok_or(InstallError::UnknownChannel)?;
-    let channel_url = channel_mf.join_url(&root_url)?;
+async fn run_install(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    options: &InstallOptions,
+    started: Instant,
+    progress: &mut InstallProgress,
+    telemetry: &mut InstallTelemetry,
+) -> Result<InstalledPlatform, InstallError> {
+    let root_url = get_root_url(app, options)?;
+    let layout = get_install_layout(app)?;
 
-    let channel_dir = install_dir.join(channel_mf.name.to_string() + "/");
-    let old_patch_mf = verify_channel_dir(app, &mut progress, &channel_dir).await?;
+    let target = resolve_target(app, http, progress, &root_url, None, None, options).await?;
+    let channel_mf = &target.channel;
+    let version_mf = &target.version;
+    let platform_mf = &target.platform;
+    let platform_url = &target.platform_url;
+    telemetry.channel = Some(channel_mf.name.clone());
+    telemetry.to_version = Some(version_mf.version.clone());
+    telemetry.os = Some(platform_mf.os.clone());
+    telemetry.arch = Some(platform_mf.arch.clone());
+    check_launcher_version(app, version_mf.min_launcher_version.as_ref())?;
 
-    let versions = get_versions(app, http, &mut progress, &root_url, channel_mf).await?;
-    let version_mf = versions.last().ok_or(InstallError::UnknownVersion)?;
-    let version_url = version_mf.join_url(&channel_url)?;
+    if needs_wine(platform_mf) {
+        app.emit(
+            "platform-fallback",
+            PlatformFallback {
+                os: platform_mf.os.clone(),
+                arch: platform_mf.arch.clone(),
+                native_os: std::env::consts::OS.into(),
+            },
+        )?;
+    }
 
-    let platforms = get_platforms(&version_mf)?;
-    let platform_mf = &platforms[0];
-    let platform_url = platform_mf.join_url(&version_url)?;
+    let channel_dir = install_dir.join(channel_mf.name.to_string() + "/");
+    check_dir_path(&channel_dir).await?;
+    let mut old_patch_mf = verify_channel_dir(app, progress, &channel_dir).await?;
+    let mut incomplete_install_dir = None;
+    if let Some(mf) = &old_patch_mf {
+        let old_install_dir =
+            install_dir.join(layout.render(&channel_mf.name, &mf.version, platform_mf));
+        if !is_install_complete(&old_install_dir, mf).await? {
+            progress.emit_phase(
+                app,
+                InstallPhase::FetchingManifests,
+                "Installed version is incomplete, reinstalling",
+            )?;
+            old_patch_mf = None;
+            incomplete_install_dir = Some(old_install_dir);
+        }
+    }
+    let installed_version = old_patch_mf.as_ref().map(|mf| mf.version.clone());
+    telemetry.from_version = installed_version.clone();
+    telemetry.operation = Some(match &installed_version {
+        None => TelemetryOperation::Install,
+        Some(installed) if installed == &version_mf.version => TelemetryOperation::Repair,
+        Some(_) => TelemetryOperation::Update,
+    });
+    app.emit(
+        "install-resolved",
+        InstallResolved {
+            channel: channel_mf.name.clone(),
+            version: version_mf.version.clone(),
+            os: platform_mf.os.clone(),
+            arch: platform_mf.arch.clone(),
+            update: installed_version
+                .as_ref()
+                .is_some_and(|installed| installed != &version_mf.version),
+            installed_version,
+        },
+    )?;
 
-    let new_install_dir = join_install_dir(&channel_dir, &version_mf.version, platform_mf);
+    let new_install_dir =
+        install_dir.join(layout.render(&channel_mf.name, &version_mf.version, platform_mf));
+    let mut force_repair = false;
     if let Some(mf) = &old_patch_mf {
-        if mf.version == version_mf.version {
-            return Ok(new_install_dir.join(platform_mf.exe_path.clone()));
+        if mf.version == version_mf.version && !options.force_reinstall {
+            // An up-to-date install is left untouched, and nothing but manifests is fetched.
+            let verified = match options.verify_installed {
+                true => {
+                    verify_files(
+                        app,
+                        progress,
+                        &new_install_dir,
+                        mf,
+                        options.emit_file_events,
+                        &mut None,
+                    )
+                    .await
+                }
+                false => Ok(()),
+            };
+            match verified {
+                Ok(()) => {
+                    telemetry.operation = None;
+                    progress.finish(app, "Already up to date", started)?;
+                    return Ok(InstalledPlatform {
+                        install_dir: new_install_dir,
+                        platform: platform_mf.clone(),
+                        summary: InstallSummary::unchanged(progress),
+                    });
+                }
+                Err(InstallError::WrongSize { .. } | InstallError::WrongHash { .. }) => {
+                    progress.emit_phase(
+                        app,
+                        InstallPhase::FetchingManifests,
+                        "Installed version is damaged, repairing",
+                    )?;
+                    force_repair = true;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if channel_mf.update_policy == UpdatePolicy::Manual
+            && !options.confirm_update
+            && !options.force_reinstall
+            && !force_repair
+        {
+            telemetry.operation = None;
+            progress.finish(app, "Update available", started)?;
+            return Ok(InstalledPlatform {
+                install_dir: install_dir.join(layout.render(
+                    &channel_mf.name,
+                    &mf.version,
+                    platform_mf,
+                )),
+                platform: platform_mf.clone(),
+                summary: InstallSummary::unchanged(progress),
+            });
         }
     }
-    let old_install_dir =
-        old_patch_mf.map(|mf| join_install_dir(&channel_dir, &mf.version, platform_mf));
+    let mut old_install_dir = old_patch_mf
+        .as_ref()
+        .filter(|mf| mf.version != version_mf.version)
+        .map(|mf| install_dir.join(layout.render(&channel_mf.name, &mf.version, platform_mf)));
 
+    check_dir_path(&new_install_dir).await?;
     tokio::fs::create_dir_all(&new_install_dir)
         .await
         .map_err(|e| InstallError::CreateDir(e))?;
 
-    let new_patch_mf = get_patch(app, http, &mut progress, &platform_url).await?;
-    install_patch(
+    let new_patch_mf = get_patch(
         app,
         http,
-        &mut progress,
-        &platform_url,
+        progress,
+        platform_url,
+        platform_mf,
+        old_patch_mf.as_ref(),
+    )
+    .await?;
+    check_launcher_version(app, new_patch_mf.min_launcher_version.as_ref())?;
+    if is_case_insensitive(&new_install_dir).await? {
+        let files = new_patch_mf
+            .new_files
+            .iter()
+            .chain(&new_patch_mf.diff_files);
+        if let Some((path, other)) = find_case_collision(files.map(|file| file.path.as_str())) {
+            return Err(InstallError::CaseCollision {
+                path: path.into(),
+                other: other.into(),
+            });
+        }
+    }
+
+    // A combined patch is unpacked first and then installed like a local split one.
+    let unpacked = match platform_mf.combined {
+        true => Some(
+            unpack_combined(
+                app,
+                http,
+                progress,
+                platform_url,
+                &new_patch_mf,
+                options.cache_dir.as_deref().unwrap_or(&channel_dir),
+            )
+            .await?,
+        ),
+        false => None,
+    };
+    let platform_url = unpacked
+        .as_ref()
+        .map_or(platform_url, |unpacked| &unpacked.url);
+
+    // Saves only move along with a version change, never from a diff source below.
+    // An update interrupted while copying saves has already cleaned up the old
+    // version, which then looks incomplete, but its saves still have to move.
+    let save_source_dir = old_install_dir.clone().or(incomplete_install_dir.clone());
+
+    // Reinstalling in place can still apply diffs against an intact retained previous version.
+    if let (None, Some(previous_version)) = (&old_install_dir, &new_patch_mf.previous_version) {
+        let previous_install_dir =
+            install_dir.join(layout.render(&channel_mf.name, previous_version, platform_mf));
+        if let Some(previous_mf) = read_version_manifest(&previous_install_dir).await? {
+            if is_install_complete(&previous_install_dir, &previous_mf.patch).await? {
+                old_install_dir = Some(previous_install_dir);
+            }
+        }
+    }
+    // Only a forced reinstall gets here with the installed version, to repair it.
+    let repair = old_patch_mf
+        .as_ref()
+        .is_some_and(|mf| mf.version == new_patch_mf.version);
+    // Neither an incomplete install nor one being repaired can be patched, so
+    // the versions before are installed again from the last one published
+    // without diffs.
+    if old_install_dir.is_none()
+        && (incomplete_install_dir.is_some() || repair)
+        && !new_patch_mf.diff_files.is_empty()
+    {
+        old_install_dir = Some(
+            install_diff_source(
+                app,
+                http,
+                progress,
+                &root_url,
+                channel_mf,
+                &layout,
+                &install_dir,
+                &new_patch_mf,
+                options,
+            )
+            .await?,
+        );
+    }
+    let broken_files = install_patch(
+        app,
+        http,
+        progress,
+        platform_url,
         old_install_dir,
         &new_install_dir,
         new_patch_mf.clone(),
+        options,
+        repair,
     )
     .await?;
+    let mut summary = InstallSummary {
+        downloaded_bytes: progress.net.value,
+        written_bytes: progress.disk.value,
+        added_files: new_patch_mf.new_files.len(),
+        changed_files: new_patch_mf.diff_files.len(),
+        removed_files: new_patch_mf.stale_files.len(),
+        broken_files,
+        elapsed_secs: 0.0,
+    };
 
-    let mut patch_mf_file = File::create(channel_dir.join("manifest.json")).await?;
-    patch_mf_file
-        .write_all(&serde_json::to_vec(&new_patch_mf)?)
+    if let Some(old_install_dir) = &save_source_dir {
+        carry_unlisted_files(
+            old_install_dir,
+            &new_install_dir,
+            platform_mf,
+            &new_patch_mf,
+            options,
+        )
+        .await?;
+        migrate_saves(
+            app,
+            progress,
+            old_install_dir,
+            &new_install_dir,
+            platform_mf,
+            options,
+        )
         .await?;
+    }
 
-    Ok(new_install_dir.join(platform_mf.exe_path.clone()))
-}
+    write_patch_manifest(&channel_dir.join("manifest.json"), &new_patch_mf).await?;
+    write_active_platform(&channel_dir, platform_mf).await?;
+    write_version_manifest(
+        &new_install_dir,
+        &VersionInstallManifest {
+            platform: platform_mf.clone(),
+            patch: new_patch_mf.clone(),
+        },
+    )
+    .await?;
 
-async fn get_channels(
-    app: &AppHandle,
-    http: &reqwest::Client,
-    progress: &mut InstallProgress,
-    root_url: &Url,
-) -> Result<Vec<ChannelManifest>, InstallError> {
-    progress.emit_msg(app, "Fetching channels")?;
-    let channels_url = root_url.join("channels.json")?;
-    let channels_json = progress.get_json(http, channels_url).await?;
-    Ok(channels_json)
+    if options.keep_previous {
+        let versions_dir = install_dir.join(layout.versions_dir(&channel_mf.name, platform_mf));
+        prune_versions(
+            &versions_dir,
+            &new_patch_mf.version,
+            options.keep_versions(),
+        )
+        .await?;
+    }
+
+    let message: String = match summary.broken_files.len() {
+        0 => "Installation finished".into(),
+        broken => format!("Repair finished, {broken} files are still broken"),
+    };
+    progress.finish(app, &message, started)?;
+    summary.elapsed_secs = progress.elapsed_secs.unwrap_or_default();
+    Ok(InstalledPlatform {
+        install_dir: new_install_dir,
+        platform: platform_mf.clone(),
+        summary,
+    })
 }
 
-async fn get_versions(
-    app: &AppHandle,
-    http: &reqwest::Client,
+/// Installs the versions that the diffs of `patch_mf` build on, starting from
+/// the newest one that is either intact or published without diffs, and
+/// returns the install directory of the version they apply to.
+#[allow(clippy::too_many_arguments)]
+async fn install_diff_source(
+    app: &dyn InstallHost,
+    http: &HttpClient,
     progress: &mut InstallProgress,
     root_url: &Url,
     channel_mf: &ChannelManifest,
-) -> Result<Vec<VersionManifest>, InstallError> {
-    progress.emit_msg(app, "Fetching versions")?;
-    let versions_url = channel_mf.join_url(root_url)?.join("versions.json")?;
-    let versions_json = progress.get_json(http, versions_url).await?;
-    Ok(versions_json)
-}
-
-fn get_platforms(version_mf: &VersionManifest) -> Result<Vec<PlatformManifest>, InstallError> {
-    let mut os_ok_list: Vec<_> = version_mf
-        .platforms
-        .iter()
-        .filter(|mf| mf.os == std::env::consts::OS)
-        .collect();
+    layout: &InstallLayout,
+    install_dir: &Path,
+    patch_mf: &PatchManifest,
+    options: &InstallOptions,
+) -> Result<PathBuf, InstallError> {
+    let mut chain = Vec::new();
+    let mut source_dir = None;
+    let mut previous_version = patch_mf.previous_version.clone();
+    while let Some(version) = previous_version.take() {
+        let target = resolve_target(
+            app,
+            http,
+            progress,
+            root_url,
+            Some(channel_mf.name.as_str()),
+            Some(&version),
+            options,
+        )
+        .await?;
+        let version_dir =
+            install_dir.join(layout.render(&channel_mf.name, &version, &target.platform));
+        if let Some(version_mf) = read_version_manifest(&version_dir).await? {
+            if is_install_complete(&version_dir, &version_mf.patch).await? {
+                source_dir = Some(version_dir);
+                break;
+            }
+        }
 
-    let wine_path = get_wine_path().ok();
-    if wine_path.is_some() {
-        // Append Wine-compatible entries after exact matches.
-        os_ok_list.extend(version_mf.platforms.iter().filter(|mf| mf.os == "windows"));
-    }
-    if os_ok_list.is_empty() {
-        return Err(InstallError::UnsupportedOS.into());
+        let version_patch_mf = get_patch(
+            app,
+            http,
+            progress,
+            &target.platform_url,
+            &target.platform,
+            None,
+        )
+        .await?;
+        // A version without diffs is installed from its raw archive alone.
+        if !version_patch_mf.diff_files.is_empty() {
+            previous_version = version_patch_mf.previous_version.clone();
+            if previous_version.is_none() {
+                return Err(InstallError::MissingPreviousVersion);
+            }
+        }
+        chain.push((target, version_dir, version_patch_mf));
     }
-
-    let arch_ok_list: Vec<_> = os_ok_list
-        .into_iter()
-        .filter(|mf| mf.arch == std::env::consts::ARCH)
-        .cloned()
-        .collect();
-    if arch_ok_list.is_empty() {
-        return Err(InstallError::UnsupportedArch.into());
+    let channel_dir = install_dir.join(channel_mf.name.to_string() + "/");
+    for (target, version_dir, version_patch_mf) in chain.into_iter().rev() {
+        check_dir_path(&version_dir).await?;
+        tokio::fs::create_dir_all(&version_dir)
+            .await
+            .map_err(InstallError::CreateDir)?;
+        let unpacked = match target.platform.combined {
+            true => Some(
+                unpack_combined(
+                    app,
+                    http,
+                    progress,
+                    &target.platform_url,
+                    &version_patch_mf,
+                    options.cache_dir.as_deref().unwrap_or(&channel_dir),
+                )
+                .await?,
+            ),
+            false => None,
+        };
+        let platform_url = unpacked
+            .as_ref()
+            .map_or(&target.platform_url, |unpacked| &unpacked.url);
+        install_patch(
+            app,
+            http,
+            progress,
+            platform_url,
+            source_dir.take(),
+            &version_dir,
+            version_patch_mf.clone(),
+            options,
+            false,
+        )
+        .await?;
+        write_version_manifest(
+            &version_dir,
+            &VersionInstallManifest {
+                platform: target.platform,
+                patch: version_patch_mf,
+            },
+        )
+        .await?;
+        source_dir = Some(version_dir);
     }
-    Ok(arch_ok_list)
+    source_dir.ok_or(InstallError::MissingPreviousVersion)
 }
 
-async fn get_patch(
-    app: &AppHandle,
-    http: &reqwest::Client,
-    progress: &mut InstallProgress,
-    platform_url: &Url,
-) -> Result<PatchManifest, InstallError> {
-    progress.emit_msg(app, "Fetching platform manifest")?;
-    let manifest_url = platform_url.join("manifest.json")?;
-    let manifest_json = progress.get_json(&http, manifest_url).await?;
-    Ok(manifest_json)
+struct ResolvedTarget {
+    channel: ChannelManifest,
+    version: VersionManifest,
+    latest_version: Version,
+    platform: PlatformManifest,
+    platform_url: Url,
 }
 
-async fn verify_channel_dir(
-    app: &AppHandle,
+/// Resolves the channel, version and platform to install.
+/// Picks the first channel and the latest version when not specified, skipping
+/// pre-releases unless the channel or `options` allow them.
+async fn resolve_target(
+    app: &dyn InstallHost,
+    http: &HttpClient,
     progress: &mut InstallProgress,
-    channel_dir: &PathBuf,
-) -> Result<Option<PatchManifest>, InstallError> {
-    progress.emit_msg(app, "Verifying install directory")?;
+    root_url: &Url,
+    channel: Option<&str>,
+    version: Option<&Version>,
+    options: &InstallOptions,
+) -> Result<ResolvedTarget, InstallError> {
+    let channels = get_channels(app, http, progress, root_url).await?;
+    let channel_mf = match channel {
+        Some(name) => channels.iter().find(|mf| mf.name == name),
+        None => channels.first(),
+    }
+    .ok_or(InstallError::UnknownChannel)?;
+    let channel_url = channel_mf.join_url(root_url)?;
 
-    match File::open(channel_dir.join("manifest.json")).await {
-        Ok(mut file) => {
-            let mut str = String::new();
-            file.read_to_string(&mut str).await?;
-            let patch_mf =
-                serde_json::from_str(&str).map_err(|e| InstallError::InvalidInstalledPatch(e))?;
-            Ok(Some(patch_mf))
-        }
-        Err(err) => {
-            if err.kind() == ErrorKind::NotFound {
-                Ok(None)
-            } else {
-                Err(err.into())
-            }
+    let versions = get_versions(app, http, progress, root_url, channel_mf).await?;
+    // Otherwise, pre-releases are only installed when asked for by version or pin.
+    let allow_prerelease = channel_mf.allow_prerelease || options.allow_prerelease;
+    let version_mf = match (version, &channel_mf.update_policy) {
+        (Some(version), UpdatePolicy::Locked(pinned)) if version > pinned => {
+            return Err(InstallError::UpdateLocked(pinned.clone()));
         }
+        (Some(version), _) => versions.iter().find(|mf| &mf.version == version),
+        (None, UpdatePolicy::Locked(pinned)) => versions.iter().find(|mf| &mf.version == pinned),
+        (None, _) => versions
+            .iter()
+            .rev()
+            .find(|mf| allow_prerelease || mf.version.pre.is_empty()),
     }
-}
-
-async fn install_patch(
-    app: &AppHandle,
-    http: &reqwest::Client,
-    progress: &mut InstallProgress,
-    platform_url: &Url,
-    old_install_dir: Option<PathBuf>,
-    new_install_dir: &PathBuf,
-    new_patch_mf: PatchManifest,
-) -> Result<(), InstallError> {
-    progress.disk.max = new_patch_mf
-        .new_files
+    .ok_or(InstallError::UnknownVersion)?;
+    let latest_version = versions
         .iter()
-        .chain(new_patch_mf.diff_files.iter())
-        .map(|file| file.len)
-        .sum();
+        .map(|mf| &mf.version)
+        .filter(|version| allow_prerelease || version.pre.is_empty())
+        .max()
+        .unwrap_or(&version_mf.version);
+    let version_url = version_mf.join_url(&channel_url)?;
 
-    progress.disk.known = true;
+    let platforms = get_platforms(version_mf)?;
+    let platform_mf = &platforms[0];
+    check_exe_path(platform_mf)?;
+    let platform_url = platform_mf.join_url(&version_url)?;
 
-    let mut read_buf = Box::new([0u8; 1024 * 64]);
-    let mut delta_buf = Vec::with_capacity(1024 * 64);
+    Ok(ResolvedTarget {
+        channel: channel_mf.clone(),
+        version: version_mf.clone(),
+        latest_version: latest_version.clone(),
+        platform: platform_mf.clone(),
+        platform_url,
+    })
+}
 
-    let mut emit_timestamp = Instant::now();
+/// What [`do_install`] would do for a channel under its [`UpdatePolicy`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum UpdateDecision {
+    UpToDate,
+    /// The target version is installed on the next launch.
+    Install,
+    /// A newer version needs [`InstallOptions::confirm_update`] to be installed.
+    UpdateAvailable,
+}
 
-    // Use atomic counter for Send-safety.
-    let response_net_counter = atomic::AtomicU64::new(0);
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct UpdateCheck {
+    policy: UpdatePolicy,
+    decision: UpdateDecision,
+    installed_version: Option<Version>,
+    /// Version an install moves to, which is the pinned one on locked channels.
+    target_version: Version,
+    latest_version: Version,
+}
 
-    let mut files_to_remove = Vec::new();
+pub(crate) async fn do_check_update(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    channel: Option<&str>,
+    options: &InstallOptions,
+) -> Result<UpdateCheck, InstallError> {
+    let mut progress = InstallProgress::default();
 
-    if !new_patch_mf.diff_files.is_empty() {
-        progress.emit_msg(app, "Updating existing files")?;
+    let root_url = get_root_url(app, options)?;
+    let layout = get_install_layout(app)?;
+    let target =
+        resolve_target(app, http, &mut progress, &root_url, channel, None, options).await?;
 
-        let old_install_dir = old_install_dir
-            .as_ref()
-            .ok_or(InstallError::MissingPreviousVersion)?;
+    let channel_dir = install_dir.join(target.channel.name.to_string() + "/");
+    let mut installed_version = None;
+    if let Some(mf) = verify_channel_dir(app, &mut progress, &channel_dir).await? {
+        let installed_dir =
+            install_dir.join(layout.render(&target.channel.name, &mf.version, &target.platform));
+        // An incomplete version is reinstalled, as if nothing was installed.
+        if is_install_complete(&installed_dir, &mf).await? {
+            installed_version = Some(mf.version);
+        }
+    }
 
-        let mut diff_set = HashMap::with_capacity(new_patch_mf.diff_files.len());
-        for file in new_patch_mf.diff_files.iter() {
-            diff_set.insert(file.path.as_str(), (file.len, &file.hash));
+    let decision = match &installed_version {
+        Some(version) if version == &target.version.version => UpdateDecision::UpToDate,
+        Some(_) if target.channel.update_policy == UpdatePolicy::Manual => {
+            UpdateDecision::UpdateAvailable
         }
+        _ => UpdateDecision::Install,
+    };
 
-        let diff_tar_url = platform_url.join("diff.tar.zst")?;
-        let diff_tar_response = http.get(diff_tar_url).send().await?;
+    Ok(UpdateCheck {
+        policy: target.channel.update_policy,
+        decision,
+        installed_version,
+        target_version: target.version.version,
+        latest_version: target.latest_version,
+    })
+}
 
-        progress.net.max += diff_tar_response.content_length().unwrap_or(0);
-        progress.net.known = true;
-        progress.emit(app)?;
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct UpdatePreview {
+    version: Version,
+    /// Total compressed bytes to download, if all archive sizes are known.
+    download_size: Option<u64>,
+    /// Total bytes written to disk once installed.
+    disk_size: u64,
+}
 
-        let response_stream =
-            StreamReader::new(diff_tar_response.bytes_stream().map(|chunk| match chunk {
-                Ok(bytes) => {
-                    response_net_counter.fetch_add(bytes.len() as u64, atomic::Ordering::Relaxed);
-                    Ok(bytes)
-                }
-                Err(error) => Err(std::io::Error::new(ErrorKind::Other, error)),
-            }));
-        let tar_stream = ZstdDecoder::new(response_stream).compat();
-        let archive = async_tar::Archive::new(tar_stream);
-        let mut entries = archive.entries()?;
+pub(crate) async fn do_preview_update(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    channel: Option<&str>,
+    version: Option<&Version>,
+    options: &InstallOptions,
+) -> Result<UpdatePreview, InstallError> {
+    let mut progress = InstallProgress::default();
 
-        while let Some(mut entry) = entries.next().await.transpose()? {
-            let relative_path = entry.path()?.into_owned();
-            let (dst_size, dst_hash) = *diff_set
-                .get(&relative_path.to_string_lossy().into_owned().as_str())
-                .ok_or(InstallError::UnexpectedArchiveFile((&relative_path).into()))?;
+    let root_url = get_root_url(app, options)?;
+    let target = resolve_target(
+        app,
+        http,
+        &mut progress,
+        &root_url,
+        channel,
+        version,
+        options,
+    )
+    .await?;
+    let patch_mf = get_patch(
+        app,
+        http,
+        &mut progress,
+        &target.platform_url,
+        &target.platform,
+        None,
+    )
+    .await?;
 
-            let src_path = old_install_dir.join(&relative_path);
-            let dst_path = new_install_dir.join(&relative_path);
-            tokio::fs::create_dir_all(
-                dst_path
-                    .parent()
-                    .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
-            )
-            .await
-            .map_err(|e| InstallError::CreateDir(e))?;
+    let mut download_size = Some(0);
+    if target.platform.combined {
+        let url = target.platform_url.join(COMBINED_ARCHIVE_NAME)?;
+        download_size = head_content_length(http, &url).await;
+    }
+    let archives = [
+        (
+            "diff",
+            !patch_mf.diff_files.is_empty(),
+            &patch_mf.diff_archive_parts,
+            patch_mf.diff_archive_size,
+        ),
+        (
+            "raw",
+            !patch_mf.new_files.is_empty(),
+            &patch_mf.raw_archive_parts,
+            patch_mf.raw_archive_size,
+        ),
+    ];
+    for (stem, needed, parts, recorded_size) in archives {
+        if !needed || target.platform.combined {
+            continue;
+        }
+        // Split archives record the size of every part.
+        let size = if parts.is_empty() {
+            let url = target
+                .platform_url
+                .join(&patch_mf.compression.archive_name(stem))?;
+            head_content_length(http, &url).await.or(recorded_size)
+        } else {
+            Some(parts.iter().sum())
+        };
+        download_size = download_size.zip(size).map(|(a, b)| a + b);
+    }
 
-            let mut dst_file = std::fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(dst_path)?;
-            dst_file.set_len(dst_size)?;
-            let mut dst_actual_hash = Blake3Hash::default();
+    let disk_size = patch_mf
+        .new_files
+        .iter()
+        .chain(patch_mf.diff_files.iter())
+        .map(|file| file.len)
+        .sum();
 
-            let src_file = File::open(&src_path).await?;
-            let src_mmap = unsafe { Mmap::map(&src_file) }?;
-            loop {
-                let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
-                if read == 0 {
-                    break;
-                }
-                delta_buf.extend_from_slice(&read_buf[..read]);
+    Ok(UpdatePreview {
+        version: target.version.version,
+        download_size,
+        disk_size,
+    })
+}
 
-                let next_timestamp = Instant::now();
-                if (next_timestamp - emit_timestamp).as_secs_f32() > 0.05 {
-                    emit_timestamp = next_timestamp;
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RemovalPreview {
+    version: Version,
+    /// Installed version the files are removed from, if any.
+    source_version: Option<Version>,
+    /// Paths relative to the install directory of `source_version`.
+    files: Vec<String>,
+}
 
-                    progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
-                    progress.emit(app)?;
-                }
-            }
+/// Lists the files an update to the target version would delete, without
+/// downloading archives or writing anything: the stale files of the patch
+/// and the sources of its diffs, as [`install_patch`] removes them.
+pub(crate) async fn do_preview_removals(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    channel: Option<&str>,
+    version: Option<&Version>,
+    options: &InstallOptions,
+) -> Result<RemovalPreview, InstallError> {
+    let mut progress = InstallProgress::default();
 
-            fast_rsync::apply_limited(&src_mmap, &delta_buf, &mut dst_file, dst_size as usize)?;
-            delta_buf.clear();
-            dst_file.flush()?;
+    let root_url = get_root_url(app, options)?;
+    let layout = get_install_layout(app)?;
+    let target = resolve_target(
+        app,
+        http,
+        &mut progress,
+        &root_url,
+        channel,
+        version,
+        options,
+    )
+    .await?;
 
-            let dst_actual_size = dst_file.stream_position()?;
-            progress.disk.value += dst_actual_size;
-            if dst_size != dst_actual_size {
-                return Err(InstallError::WrongSize {
-                    expected: dst_size,
-                    actual: dst_actual_size,
-                });
-            }
+    let channel_dir = install_dir.join(target.channel.name.to_string() + "/");
+    let mut installed_mf = None;
+    if let Some(mf) = read_installed_patch(&channel_dir).await? {
+        let installed_dir =
+            install_dir.join(layout.render(&target.channel.name, &mf.version, &target.platform));
+        if is_install_complete(&installed_dir, &mf).await? {
+            installed_mf = Some(mf);
+        }
+    }
+    let patch_mf = get_patch(
+        app,
+        http,
+        &mut progress,
+        &target.platform_url,
+        &target.platform,
+        installed_mf.as_ref(),
+    )
+    .await?;
 
-            dst_file.seek(std::io::SeekFrom::Start(0))?;
-            loop {
-                let len = dst_file.read(read_buf.as_mut())?;
-                if len == 0 {
-                    break;
-                }
-                dst_actual_hash.update(&read_buf[..len]);
-            }
-            let dst_actual_hash = dst_actual_hash.finish();
-            if dst_hash != &dst_actual_hash {
-                return Err(InstallError::WrongHash {
-                    expected: hex::encode(dst_hash),
-                    actual: hex::encode(dst_actual_hash),
-                });
+    // Mirrors how do_install picks the version that patches are applied to.
+    let mut source_version = installed_mf
+        .map(|mf| mf.version)
+        .filter(|installed| installed != &patch_mf.version);
+    if let (None, Some(previous_version)) = (&source_version, &patch_mf.previous_version) {
+        let previous_install_dir = install_dir.join(layout.render(
+            &target.channel.name,
+            previous_version,
+            &target.platform,
+        ));
+        if let Some(previous_mf) = read_version_manifest(&previous_install_dir).await? {
+            if is_install_complete(&previous_install_dir, &previous_mf.patch).await? {
+                source_version = Some(previous_version.clone());
             }
-            files_to_remove.push(src_path);
         }
-        progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
     }
 
-    if !new_patch_mf.new_files.is_empty() {
-        progress.emit_msg(app, "Downloading new files")?;
+    // Nothing is removed from a retained previous version, or without one.
+    let mut files = Vec::new();
+    if source_version.is_some() && !options.keep_previous {
+        files.extend(patch_mf.stale_files.iter().cloned());
+        files.extend(patch_mf.diff_files.iter().map(|file| file.path.clone()));
+        files.sort();
+        files.dedup();
+    }
 
-        let mut new_set = HashMap::with_capacity(new_patch_mf.new_files.len());
-        for file in new_patch_mf.new_files.iter() {
-            new_set.insert(file.path.as_str(), (file.len, &file.hash));
+    Ok(RemovalPreview {
+        version: target.version.version,
+        source_version,
+        files,
+    })
+}
+
+/// Reachability of an archive, probed by [`do_preflight_install`].
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ArchiveProbe {
+    url: String,
+    reachable: bool,
+    /// Size announced by the server.
+    size: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PreflightReport {
+    version: Version,
+    platform: PlatformManifest,
+    /// Version currently installed on the channel, if intact.
+    installed_version: Option<Version>,
+    /// Whether the diffs of the target version have an installed version to apply to.
+    chain_resolves: bool,
+    /// Total compressed bytes to download, if all archive sizes are known.
+    download_size: Option<u64>,
+    /// Total bytes written to disk once installed.
+    disk_size: u64,
+    archives: Vec<ArchiveProbe>,
+    /// Space available to the install directory, if it could be determined.
+    free_space: Option<u64>,
+}
+
+/// Checks that an install could go through without downloading or writing
+/// anything: the target resolves, its archives are reachable and the
+/// install directory has room for it.
+pub(crate) async fn do_preflight_install(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    channel: Option<&str>,
+    version: Option<&Version>,
+    options: &InstallOptions,
+) -> Result<PreflightReport, InstallError> {
+    let mut progress = InstallProgress::default();
+
+    let root_url = get_root_url(app, options)?;
+    let layout = get_install_layout(app)?;
+    let target = resolve_target(
+        app,
+        http,
+        &mut progress,
+        &root_url,
+        channel,
+        version,
+        options,
+    )
+    .await?;
+    let patch_mf = get_patch(
+        app,
+        http,
+        &mut progress,
+        &target.platform_url,
+        &target.platform,
+        None,
+    )
+    .await?;
+
+    let channel_dir = install_dir.join(target.channel.name.to_string() + "/");
+    let mut installed_version = None;
+    if let Some(installed_mf) = read_installed_patch(&channel_dir).await? {
+        let installed_dir = install_dir.join(layout.render(
+            &target.channel.name,
+            &installed_mf.version,
+            &target.platform,
+        ));
+        if is_install_complete(&installed_dir, &installed_mf).await? {
+            installed_version = Some(installed_mf.version);
         }
+    }
 
-        let raw_tar_url = platform_url.join("raw.tar.zst")?;
-        let raw_tar_response = http.get(raw_tar_url).send().await?;
+    let mut chain_resolves = patch_mf.diff_files.is_empty();
+    if let Some(previous_version) = &patch_mf.previous_version {
+        if installed_version.as_ref() == Some(previous_version) {
+            chain_resolves = true;
+        } else {
+            let previous_install_dir = install_dir.join(layout.render(
+                &target.channel.name,
+                previous_version,
+                &target.platform,
+            ));
+            if let Some(previous_mf) = read_version_manifest(&previous_install_dir).await? {
+                chain_resolves |=
+                    is_install_complete(&previous_install_dir, &previous_mf.patch).await?;
+            }
+        }
+    }
 
-        progress.net.max += raw_tar_response.content_length().unwrap_or(0);
-        progress.net.known = true;
-        progress.emit(app)?;
+    let mut archives = Vec::new();
+    let mut download_size = Some(0);
+    let archive_names = [
+        (
+            "diff",
+            !patch_mf.diff_files.is_empty(),
+            &patch_mf.diff_archive_parts,
+            patch_mf.diff_archive_size,
+        ),
+        (
+            "raw",
+            !patch_mf.new_files.is_empty(),
+            &patch_mf.raw_archive_parts,
+            patch_mf.raw_archive_size,
+        ),
+    ];
+    if target.platform.combined {
+        let probe = probe_archive(http, &target.platform_url.join(COMBINED_ARCHIVE_NAME)?).await;
+        download_size = probe.size;
+        archives.push(probe);
+    }
+    for (stem, needed, parts, recorded_size) in archive_names {
+        if !needed || target.platform.combined {
+            continue;
+        }
+        let url = target
+            .platform_url
+            .join(&patch_mf.compression.archive_name(stem))?;
+        if parts.is_empty() {
+            let probe = probe_archive(http, &url).await;
+            download_size = download_size
+                .zip(probe.size.or(recorded_size))
+                .map(|(a, b)| a + b);
+            archives.push(probe);
+            continue;
+        }
+        for (index, &part_len) in parts.iter().enumerate() {
+            let probe = probe_archive(http, &Url::parse(&format!("{url}.{index:03}"))?).await;
+            download_size = download_size
+                .zip(probe.size.or(Some(part_len)))
+                .map(|(a, b)| a + b);
+            archives.push(probe);
+        }
+    }
 
-        let response_stream =
-            StreamReader::new(raw_tar_response.bytes_stream().map(|chunk| match chunk {
-                Ok(bytes) => {
-                    response_net_counter.fetch_add(bytes.len() as u64, atomic::Ordering::Relaxed);
-                    Ok(bytes)
-                }
-                Err(error) => Err(std::io::Error::new(ErrorKind::Other, error)),
-            }));
-        let tar_stream = ZstdDecoder::new(response_stream).compat();
-        let archive = async_tar::Archive::new(tar_stream);
-        let mut entries = archive.entries()?;
+    let disk_size = patch_mf
+        .new_files
+        .iter()
+        .chain(patch_mf.diff_files.iter())
+        .map(|file| file.len)
+        .sum();
 
-        while let Some(mut entry) = entries.next().await.transpose()? {
-            let relative_path = entry.path()?.into_owned();
-            let (dst_size, dst_hash) = *new_set
-                .get(&relative_path.to_string_lossy().into_owned().as_str())
-                .ok_or(InstallError::UnexpectedArchiveFile((&relative_path).into()))?;
+    Ok(PreflightReport {
+        version: target.version.version,
+        platform: target.platform,
+        installed_version,
+        chain_resolves,
+        download_size,
+        disk_size,
+        archives,
+        free_space: get_free_space(&install_dir),
+    })
+}
 
-            let dst_path = new_install_dir.join(relative_path);
-            tokio::fs::create_dir_all(
-                dst_path
-                    .parent()
-                    .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
-            )
+async fn probe_archive(http: &HttpClient, url: &Url) -> ArchiveProbe {
+    let result = match to_local_path(url) {
+        Ok(Some(path)) => tokio::fs::metadata(path)
             .await
-            .map_err(|e| InstallError::CreateDir(e))?;
+            .map(|meta| Some(meta.len()))
+            .map_err(|err| err.to_string()),
+        Ok(None) => match http.head(url).await {
+            Ok(response) => Ok(response
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok()?.parse().ok())),
+            Err(err) => Err(err.to_string()),
+        },
+        Err(err) => Err(err.to_string()),
+    };
+    ArchiveProbe {
+        url: url.to_string(),
+        reachable: result.is_ok(),
+        size: result.as_ref().ok().copied().flatten(),
+        error: result.err(),
+    }
+}
 
-            let mut dst_file = File::create(dst_path).await?;
-            dst_file.set_len(dst_size).await?;
-            let mut dst_actual_hash = Blake3Hash::default();
-            loop {
-                let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
-                if read == 0 {
-                    break;
-                }
-                let mut split = &read_buf[..read];
-                dst_actual_hash.update(&split);
+/// Space available on the volume of `path`, which need not exist yet.
+fn get_free_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|dir| dir.exists())?;
+    fs4::available_space(existing).ok()
+}
 
-                let written = dst_file.write_buf(&mut split).await?;
-                progress.disk.value += written as u64;
+/// Returns the size announced by the server, or `None` if HEAD is not supported.
+async fn head_content_length(http: &HttpClient, url: &Url) -> Option<u64> {
+    if let Some(path) = to_local_path(url).ok()? {
+        return Some(tokio::fs::metadata(path).await.ok()?.len());
+    }
 
-                let next_timestamp = Instant::now();
-                if (next_timestamp - emit_timestamp).as_secs_f32() > 0.05 {
-                    emit_timestamp = next_timestamp;
+    let response = http.head(url).await.ok()?;
+    // `Response::content_length` reports the (empty) body size for HEAD requests.
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
 
-                    progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
-                    progress.emit(app)?;
-                }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) enum Severity {
+    /// Worth a look, but installs still work.
+    Warning,
+    /// Breaks installs of the affected platform.
+    Error,
+}
+
+/// A publishing mistake found by [`do_validate_release`].
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ReleaseProblem {
+    /// Manifest or archive the problem was found in.
+    url: String,
+    severity: Severity,
+    message: String,
+}
+
+impl ReleaseProblem {
+    fn new(url: &Url, message: impl ToString) -> Self {
+        Self {
+            url: url.to_string(),
+            severity: Severity::Error,
+            message: message.to_string(),
+        }
+    }
+
+    fn warning(url: &Url, message: impl ToString) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(url, message)
+        }
+    }
+}
+
+/// Outcome of [`do_validate_release`], meant to gate publishing in CI.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ReleaseReport {
+    /// Whether no error-level problem was found. Warnings do not fail a release.
+    passed: bool,
+    /// Platforms validated without any problem.
+    ok: usize,
+    warnings: usize,
+    errors: usize,
+    problems: Vec<ReleaseProblem>,
+}
+
+impl ReleaseReport {
+    fn new(ok: usize, problems: Vec<ReleaseProblem>) -> Self {
+        let errors = problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .count();
+        Self {
+            passed: errors == 0,
+            ok,
+            warnings: problems.len() - errors,
+            errors,
+            problems,
+        }
+    }
+}
+
+/// Walks every channel, version and platform of a content tree, checking that
+/// manifests are consistent, archives match their manifests and previous
+/// versions are published. Nothing is written to the install directory.
+pub(crate) async fn do_validate_release(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    options: &InstallOptions,
+) -> Result<ReleaseReport, InstallError> {
+    let started = Instant::now();
+    let mut progress = InstallProgress::default();
+    let mut problems = Vec::new();
+    let mut ok = 0;
+
+    let root_url = get_root_url(app, options)?;
+    // Combined patches are unpacked outside the install directory, which is left alone.
+    let staging_dir = options.cache_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let channels = get_channels(app, http, &mut progress, &root_url).await?;
+    for channel_mf in channels.iter() {
+        let channel_url = channel_mf.join_url(&root_url)?;
+        let versions = match get_versions(app, http, &mut progress, &root_url, channel_mf).await {
+            Ok(versions) => versions,
+            Err(err) => {
+                problems.push(ReleaseProblem::new(
+                    &channel_url.join("versions.json")?,
+                    err,
+                ));
+                continue;
             }
-            dst_file.flush().await?;
+        };
 
-            let dst_actual_size = dst_file.stream_position().await?;
-            if dst_size != dst_actual_size {
-                return Err(InstallError::WrongSize {
-                    expected: dst_size,
-                    actual: dst_actual_size,
-                });
+        if let UpdatePolicy::Locked(pinned) = &channel_mf.update_policy {
+            if !versions.iter().any(|mf| &mf.version == pinned) {
+                problems.push(ReleaseProblem::new(
+                    &channel_url,
+                    format!("channel is locked to unpublished version {pinned}"),
+                ));
             }
+        }
 
-            let dst_actual_hash = dst_actual_hash.finish();
-            if dst_hash != &dst_actual_hash {
-                return Err(InstallError::WrongHash {
-                    expected: hex::encode(dst_hash),
-                    actual: hex::encode(dst_actual_hash),
-                });
+        for version_mf in versions.iter() {
+            let version_url = version_mf.join_url(&channel_url)?;
+            for platform_mf in version_mf.platforms.iter() {
+                let platform_url = platform_mf.join_url(&version_url)?;
+                let platform_problems = validate_platform(
+                    app,
+                    http,
+                    &mut progress,
+                    &platform_url,
+                    &versions,
+                    version_mf,
+                    platform_mf,
+                    &staging_dir,
+                )
+                .await?;
+                if platform_problems.is_empty() {
+                    ok += 1;
+                }
+                problems.extend(platform_problems);
             }
         }
-        progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
     }
 
-    if let Some(old_install_dir) = old_install_dir.as_ref() {
-        progress.emit_msg(app, "Copying save files")?;
-        for save_dir in ["Config", "SaveGames"] {
-            let path = PathBuf::from("PackWisely/Saved/").join(save_dir);
-            copy_dir(&old_install_dir.join(&path), &new_install_dir.join(&path)).await?;
+    progress.finish(app, "Validation finished", started)?;
+    Ok(ReleaseReport::new(ok, problems))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn validate_platform(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    versions: &[VersionManifest],
+    version_mf: &VersionManifest,
+    platform_mf: &PlatformManifest,
+    staging_dir: &Path,
+) -> Result<Vec<ReleaseProblem>, InstallError> {
+    let mut problems = Vec::new();
+    let manifest_url = platform_url.join(match platform_mf.combined {
+        true => COMBINED_ARCHIVE_NAME,
+        false => "manifest.json",
+    })?;
+    let patch_mf = match get_patch(app, http, progress, platform_url, platform_mf, None).await {
+        Ok(patch_mf) => patch_mf,
+        Err(err) => {
+            problems.push(ReleaseProblem::new(&manifest_url, err));
+            return Ok(problems);
         }
+    };
+
+    if patch_mf.version != version_mf.version {
+        problems.push(ReleaseProblem::new(
+            &manifest_url,
+            format!(
+                "manifest is for version {} but listed as {}",
+                patch_mf.version, version_mf.version
+            ),
+        ));
     }
 
-    progress.emit_msg(app, "Removing old files")?;
-    if let Some(old_install_dir) = old_install_dir.as_ref() {
-        for file in new_patch_mf.stale_files.iter() {
-            tokio::fs::remove_file(&old_install_dir.join(file)).await?;
+    if let Some(previous_version) = &patch_mf.previous_version {
+        let is_published = versions.iter().any(|mf| {
+            &mf.version == previous_version
+                && mf
+                    .platforms
+                    .iter()
+                    .any(|other| other.os == platform_mf.os && other.arch == platform_mf.arch)
+        });
+        if !is_published {
+            problems.push(ReleaseProblem::new(
+                &manifest_url,
+                format!("previous version {previous_version} is not published for this platform"),
+            ));
         }
     }
-    for file in files_to_remove.iter() {
-        tokio::fs::remove_file(file).await?;
+
+    // A diff that outweighs the files it produces is cheaper to ship whole.
+    let diff_files_size: u64 = patch_mf.diff_files.iter().map(|file| file.len).sum();
+    if let Some(diff_archive_size) = patch_mf.diff_archive_size {
+        if diff_archive_size > diff_files_size {
+            problems.push(ReleaseProblem::warning(
+                &manifest_url,
+                format!(
+                    "diff archive ({diff_archive_size} bytes) is larger than \
+                     the files it updates ({diff_files_size} bytes)"
+                ),
+            ));
+        }
     }
 
-    progress.emit(app)?;
+    // The archives of a combined patch are checked once unpacked.
+    let unpacked = match platform_mf.combined {
+        true => {
+            match unpack_combined(app, http, progress, platform_url, &patch_mf, staging_dir).await {
+                Ok(unpacked) => Some(unpacked),
+                Err(err) => {
+                    problems.push(ReleaseProblem::new(&manifest_url, err));
+                    return Ok(problems);
+                }
+            }
+        }
+        false => None,
+    };
+    let archive_url = unpacked
+        .as_ref()
+        .map_or(platform_url, |unpacked| &unpacked.url);
 
-    Ok(())
+    let archives = [
+        (
+            "raw",
+            &patch_mf.new_files,
+            &patch_mf.raw_archive_parts,
+            patch_mf.raw_archive_hash.as_ref(),
+            true,
+        ),
+        (
+            "diff",
+            &patch_mf.diff_files,
+            &patch_mf.diff_archive_parts,
+            patch_mf.diff_archive_hash.as_ref(),
+            false,
+        ),
+    ];
+    for (stem, files, parts, hash, check_contents) in archives {
+        if files.is_empty() {
+            continue;
+        }
+        let url = archive_url.join(&patch_mf.compression.archive_name(stem))?;
+        let result = validate_archive(
+            app,
+            http,
+            progress,
+            &url,
+            &patch_mf,
+            files,
+            parts,
+            hash,
+            check_contents,
+        )
+        .await;
+        match result {
+            Ok(archive_problems) => problems.extend(
+                archive_problems
+                    .into_iter()
+                    .map(|message| ReleaseProblem::new(&url, message)),
+            ),
+            Err(err) => problems.push(ReleaseProblem::new(&url, err)),
+        }
+    }
+    Ok(problems)
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
-struct InstallProgress {
-    net: ProgressState,
-    disk: ProgressState,
-    message: String,
-}
+/// Checks an archive against the files of its manifest. Deltas cannot be
+/// verified without the previous version, so only raw files have their
+/// contents hashed when `check_contents` is set.
+#[allow(clippy::too_many_arguments)]
+async fn validate_archive(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    url: &Url,
+    patch_mf: &PatchManifest,
+    files: &[FileManifest],
+    parts: &[u64],
+    hash: Option<&[u8; 32]>,
+    check_contents: bool,
+) -> Result<Vec<String>, InstallError> {
+    let mut problems = Vec::new();
+    let net_counter = atomic::AtomicU64::new(0);
 
-impl InstallProgress {
-    fn emit(&self, app: &AppHandle) -> Result<(), tauri::Error> {
-        app.emit("install-progress", self)
+    let mut file_set = HashMap::with_capacity(files.len());
+    for file in files.iter() {
+        file_set.insert(
+            file.path.as_str(),
+            (file.len, &file.hash, file.hash_algorithm),
+        );
     }
 
-    fn emit_msg(&mut self, app: &AppHandle, message: &str) -> Result<(), tauri::Error> {
-        self.message = message.into();
-        self.emit(app)
-    }
+    // The archive is hashed as it is read for its entries, so it is only downloaded once.
+    let mut reader = BufReader::new(HashingReader {
+        inner: open_archive(
+            app,
+            http,
+            progress,
+            url.clone(),
+            parts,
+            None,
+            None,
+            &net_counter,
+        )
+        .await?,
+        hasher: Blake3Hash::default(),
+    });
+    let archive = async_tar::Archive::new(decompress(&mut reader, patch_mf));
+    let mut entries = archive.entries()?;
+    let mut read_buf = vec![0u8; 1024 * 64];
 
-    async fn get_and_send(
-        &mut self,
-        http: &reqwest::Client,
-        url: impl IntoUrl,
-    ) -> tauri_plugin_http::reqwest::Result<Response> {
-        let response = http.get(url).send().await?;
-        self.net.add_both(response.content_length().unwrap_or(0));
-        Ok(response)
+    while let Some(mut entry) = entries.next().await.transpose()? {
+        let relative_path: PathBuf = entry.path()?.into_owned().into();
+        if let Err(err) = check_archive_path(&relative_path) {
+            problems.push(err.to_string());
+            continue;
+        }
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = relative_path.to_string_lossy().into_owned();
+        let Some((len, expected_hash, hash_algorithm)) = file_set.remove(path.as_str()) else {
+            problems.push(InstallError::UnexpectedArchiveFile(relative_path).to_string());
+            continue;
+        };
+        if !check_contents {
+            continue;
+        }
+
+        let mut actual_len = 0;
+        let mut actual_hash = FileHasher::new(hash_algorithm);
+        loop {
+            let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
+            if read == 0 {
+                break;
+            }
+            actual_len += read as u64;
+            actual_hash.update(&read_buf[..read]);
+        }
+        let actual_hash = actual_hash.finish();
+        if actual_len != len {
+            let err = InstallError::WrongSize {
+                path: relative_path,
+                expected: len,
+                actual: actual_len,
+            };
+            problems.push(err.to_string());
+        } else if &actual_hash != expected_hash {
+            let err = InstallError::WrongHash {
+                path: relative_path,
+                expected: hex::encode(expected_hash),
+                actual: hex::encode(actual_hash),
+            };
+            problems.push(err.to_string());
+        }
     }
 
-    async fn get_json<T: DeserializeOwned>(
-        &mut self,
-        http: &reqwest::Client,
-        url: impl IntoUrl,
-    ) -> tauri_plugin_http::reqwest::Result<T> {
-        Ok(self.get_and_send(http, url).await?.json().await?)
+    drop(entries);
+
+    if let Some(hash) = hash {
+        // Whatever follows the end of the tar is part of the archive as well.
+        tokio::io::copy_buf(&mut reader, &mut tokio::io::sink()).await?;
+        let actual_hash = reader.into_inner().hasher.finish();
+        if hash != &actual_hash {
+            problems.push(format!(
+                "archive hash 0x{} does not match manifest hash 0x{}",
+                hex::encode(actual_hash),
+                hex::encode(hash)
+            ));
+        }
     }
+    problems.extend(
+        file_set
+            .into_keys()
+            .map(|path| InstallError::MissingArchiveFile(path.into()).to_string()),
+    );
+    Ok(problems)
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
-struct ProgressState {
-    value: u64,
-    max: u64,
-    known: bool,
+/// Hashes the bytes read through it with Blake3.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Blake3Hash,
 }
 
-impl ProgressState {
-    fn add(&mut self, value: u64, target: u64) {
-        self.value += value;
-        self.max += target;
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &poll {
+            this.hasher.update(&buf.filled()[filled..]);
+        }
+        poll
     }
+}
 
-    fn add_both(&mut self, value: u64) {
-        self.add(value, value);
+/// How a file changed from the previous version, as traced by [`do_trace_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) enum FileChange {
+    Added,
+    /// Shipped whole although the previous version had the file.
+    Replaced,
+    /// Patched by a delta that changes its contents.
+    Diffed,
+    /// Listed with a delta but the contents it already had.
+    Unchanged,
+    Removed,
+}
+
+/// The state of a file at one version, as traced by [`do_trace_file`].
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FileRevision {
+    version: Version,
+    change: FileChange,
+    /// Size of the file, or `None` once it is removed.
+    len: Option<u64>,
+    hash: Option<String>,
+    hash_algorithm: Option<HashAlgorithm>,
+}
+
+/// Follows one file through the patch manifests of a channel for this host,
+/// oldest version first, listing every version that has or removes it.
+/// Nothing is downloaded besides manifests.
+pub(crate) async fn do_trace_file(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    channel: Option<&str>,
+    path: &str,
+    options: &InstallOptions,
+) -> Result<Vec<FileRevision>, InstallError> {
+    let started = Instant::now();
+    let mut progress = InstallProgress::default();
+
+    let root_url = get_root_url(app, options)?;
+    let channels = get_channels(app, http, &mut progress, &root_url).await?;
+    let channel_mf = match channel {
+        Some(name) => channels.iter().find(|mf| mf.name == name),
+        None => channels.first(),
+    }
+    .ok_or(InstallError::UnknownChannel)?;
+    let channel_url = channel_mf.join_url(&root_url)?;
+    let versions = get_versions(app, http, &mut progress, &root_url, channel_mf).await?;
+
+    let mut history = Vec::new();
+    let mut previous: Option<FileManifest> = None;
+    for version_mf in versions.iter() {
+        let platforms = get_platforms(version_mf)?;
+        let platform_mf = &platforms[0];
+        let platform_url = platform_mf.join_url(&version_mf.join_url(&channel_url)?)?;
+        let patch_mf =
+            get_patch(app, http, &mut progress, &platform_url, platform_mf, None).await?;
+
+        let new_file = patch_mf.new_files.iter().find(|file| file.path == path);
+        let diff_file = patch_mf.diff_files.iter().find(|file| file.path == path);
+        let (change, file) = match (new_file, diff_file, &previous) {
+            (Some(file), _, None) => (FileChange::Added, file),
+            (Some(file), _, Some(_)) => (FileChange::Replaced, file),
+            (None, Some(file), Some(old_file))
+                if (file.len, file.hash, file.hash_algorithm)
+                    == (old_file.len, old_file.hash, old_file.hash_algorithm) =>
+            {
+                (FileChange::Unchanged, file)
+            }
+            (None, Some(file), _) => (FileChange::Diffed, file),
+            (None, None, Some(_)) => {
+                history.push(FileRevision {
+                    version: patch_mf.version.clone(),
+                    change: FileChange::Removed,
+                    len: None,
+                    hash: None,
+                    hash_algorithm: None,
+                });
+                previous = None;
+                continue;
+            }
+            (None, None, None) => continue,
+        };
+        history.push(FileRevision {
+            version: patch_mf.version.clone(),
+            change,
+            len: Some(file.len),
+            hash: Some(hex::encode(file.hash)),
+            hash_algorithm: Some(file.hash_algorithm),
+        });
+        previous = Some(file.clone());
+    }
+
+    progress.finish(app, "Trace finished", started)?;
+    Ok(history)
+}
+
+/// Manifest kept beside each version's install directory,
+/// so a retained version can be verified and restored later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VersionInstallManifest {
+    platform: PlatformManifest,
+    patch: PatchManifest,
+}
+
+fn join_version_manifest_path(install_dir: &Path) -> PathBuf {
+    append_extension(install_dir, "manifest.json")
+}
+
+/// The platform installed in a channel, needed to locate its install
+/// directories with a layout not rooted at the version.
+fn join_active_platform_path(channel_dir: &Path) -> PathBuf {
+    channel_dir.join("platform.json")
+}
+
+async fn write_active_platform(
+    channel_dir: &Path,
+    platform_mf: &PlatformManifest,
+) -> Result<(), InstallError> {
+    let mut platform_file = File::create(join_active_platform_path(channel_dir)).await?;
+    platform_file
+        .write_all(&serde_json::to_vec(platform_mf)?)
+        .await?;
+    Ok(())
+}
+
+/// Reads the platform installed for `version` of a channel.
+async fn read_installed_platform(
+    channel_dir: &Path,
+    version: &Version,
+) -> Result<Option<PlatformManifest>, InstallError> {
+    if let Some(platform_mf) = read_active_platform(channel_dir).await? {
+        return Ok(Some(platform_mf));
+    }
+    // Channels installed before the platform was recorded use the default layout.
+    let Some(install_dir) = find_version_install_dir(channel_dir, version).await? else {
+        return Ok(None);
+    };
+    Ok(read_version_manifest(&install_dir)
+        .await?
+        .map(|version_mf| version_mf.platform))
+}
+
+async fn read_active_platform(
+    channel_dir: &Path,
+) -> Result<Option<PlatformManifest>, InstallError> {
+    match tokio::fs::read(join_active_platform_path(channel_dir)).await {
+        Ok(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).map_err(InstallError::InvalidInstalledPatch)?,
+        )),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn write_patch_manifest(path: &Path, patch_mf: &PatchManifest) -> Result<(), InstallError> {
+    let mut patch_mf_file = File::create(path).await?;
+    patch_mf_file
+        .write_all(&serde_json::to_vec(patch_mf)?)
+        .await?;
+    Ok(())
+}
+
+async fn write_version_manifest(
+    install_dir: &Path,
+    version_mf: &VersionInstallManifest,
+) -> Result<(), InstallError> {
+    let mut version_mf_file = File::create(join_version_manifest_path(install_dir)).await?;
+    version_mf_file
+        .write_all(&serde_json::to_vec(version_mf)?)
+        .await?;
+    Ok(())
+}
+
+/// Reads the manifest of an installed version, or `None` if it is not installed.
+async fn read_version_manifest(
+    install_dir: &Path,
+) -> Result<Option<VersionInstallManifest>, InstallError> {
+    match tokio::fs::read(join_version_manifest_path(install_dir)).await {
+        Ok(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).map_err(InstallError::InvalidInstalledPatch)?,
+        )),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Switches a channel back to the version it was updated from, provided that
+/// version was retained by [`InstallOptions::keep_previous`] and is still intact.
+pub(crate) async fn do_rollback(
+    app: &dyn InstallHost,
+    install_dir: PathBuf,
+    channel: &str,
+) -> Result<InstalledPlatform, InstallError> {
+    let started = Instant::now();
+    let mut progress = InstallProgress::default();
+    let layout = get_install_layout(app)?;
+
+    let channel_dir = install_dir.join(channel.to_string() + "/");
+
+    let current_patch_mf = verify_channel_dir(app, &mut progress, &channel_dir)
+        .await?
+        .ok_or(InstallError::NothingToRollBack)?;
+    let previous_version = current_patch_mf
+        .previous_version
+        .ok_or(InstallError::NothingToRollBack)?;
+
+    let platform_mf = read_installed_platform(&channel_dir, &current_patch_mf.version)
+        .await?
+        .ok_or(InstallError::NothingToRollBack)?;
+
+    let previous_install_dir =
+        install_dir.join(layout.render(channel, &previous_version, &platform_mf));
+    let previous_mf = read_version_manifest(&previous_install_dir)
+        .await?
+        .ok_or_else(|| InstallError::PreviousVersionPruned(previous_version.clone()))?;
+    check_exe_path(&previous_mf.platform)?;
+
+    progress.emit_phase(app, InstallPhase::Verifying, "Verifying previous version")?;
+    verify_files(
+        app,
+        &mut progress,
+        &previous_install_dir,
+        &previous_mf.patch,
+        false,
+        &mut None,
+    )
+    .await?;
+
+    write_patch_manifest(&channel_dir.join("manifest.json"), &previous_mf.patch).await?;
+
+    progress.finish(app, "Rolled back", started)?;
+    Ok(InstalledPlatform {
+        install_dir: previous_install_dir,
+        platform: previous_mf.platform,
+        summary: InstallSummary::unchanged(&progress),
+    })
+}
+
+/// Hashes the executable and the files marked [`FileManifest::critical`] of an
+/// installed version, so a damaged install fails before the game is spawned
+/// instead of with an obscure crash. Everything else is left to a full verify.
+pub(crate) async fn verify_launch_files(
+    install_dir: &Path,
+    platform_mf: &PlatformManifest,
+) -> Result<(), InstallError> {
+    let exe_path = platform_mf.exe_path.replace('\\', "/");
+    let files: Vec<_> = match read_version_manifest(install_dir).await? {
+        Some(version_mf) => version_mf
+            .patch
+            .new_files
+            .into_iter()
+            .chain(version_mf.patch.diff_files)
+            .filter(|file| file.critical || file.path == exe_path)
+            .collect(),
+        None => vec![],
+    };
+
+    if !files.iter().any(|file| file.path == exe_path)
+        && !tokio::fs::try_exists(install_dir.join(&exe_path)).await?
+    {
+        return Err(InstallError::MissingInstalledFile(exe_path));
+    }
+    for file in files {
+        let path = install_dir.join(&file.path);
+        let len = match tokio::fs::metadata(&path).await {
+            Ok(meta) => meta.len(),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(InstallError::MissingInstalledFile(file.path));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if len != file.len {
+            return Err(InstallError::WrongSize {
+                path: file.path.into(),
+                expected: file.len,
+                actual: len,
+            });
+        }
+        let hash = hash_file_as(&path, file.hash_algorithm).await?;
+        if hash != file.hash {
+            return Err(InstallError::WrongHash {
+                path: file.path.into(),
+                expected: hex::encode(file.hash),
+                actual: hex::encode(hash),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Files found broken by [`do_heal`], before and after re-fetching them.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct HealSummary {
+    version: Version,
+    broken_before: Vec<String>,
+    healed: Vec<String>,
+    /// Files still broken, including diffed files, which cannot be
+    /// re-fetched on their own and need a reinstall.
+    broken_after: Vec<String>,
+    downloaded_bytes: u64,
+    elapsed_secs: f64,
+}
+
+/// Verifies the installed version of a channel and re-fetches the files that
+/// fail, taking them from the raw archive of that same version.
+pub(crate) async fn do_heal(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    channel: &str,
+    options: &InstallOptions,
+) -> Result<HealSummary, InstallError> {
+    let started = Instant::now();
+    let mut progress = InstallProgress::default();
+    let mut telemetry = InstallTelemetry::new(TelemetryOperation::Heal);
+    telemetry.channel = Some(channel.into());
+
+    let result = run_heal(
+        app,
+        http,
+        install_dir,
+        channel,
+        options,
+        started,
+        &mut progress,
+        &mut telemetry,
+    )
+    .await;
+    if options.telemetry {
+        telemetry.downloaded_bytes = progress.net.value;
+        telemetry.written_bytes = progress.disk.value;
+        telemetry.elapsed_secs = started.elapsed().as_secs_f64();
+        if let Ok(summary) = &result {
+            telemetry.broken_files = summary.broken_after.len();
+        }
+        telemetry.finish(app, &result);
+    }
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_heal(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    install_dir: PathBuf,
+    channel: &str,
+    options: &InstallOptions,
+    started: Instant,
+    progress: &mut InstallProgress,
+    telemetry: &mut InstallTelemetry,
+) -> Result<HealSummary, InstallError> {
+    let layout = get_install_layout(app)?;
+
+    let channel_dir = install_dir.join(channel.to_string() + "/");
+    let patch_mf = verify_channel_dir(app, progress, &channel_dir)
+        .await?
+        .ok_or(InstallError::NotInstalled)?;
+    let platform_mf = read_installed_platform(&channel_dir, &patch_mf.version)
+        .await?
+        .ok_or(InstallError::NotInstalled)?;
+    let version_install_dir =
+        install_dir.join(layout.render(channel, &patch_mf.version, &platform_mf));
+    telemetry.from_version = Some(patch_mf.version.clone());
+    telemetry.to_version = Some(patch_mf.version.clone());
+    telemetry.os = Some(platform_mf.os.clone());
+    telemetry.arch = Some(platform_mf.arch.clone());
+
+    let mut broken_before = Some(Vec::new());
+    verify_files(
+        app,
+        progress,
+        &version_install_dir,
+        &patch_mf,
+        options.emit_file_events,
+        &mut broken_before,
+    )
+    .await?;
+    let broken_before = broken_before.unwrap_or_default();
+
+    let raw_files: Vec<_> = patch_mf
+        .new_files
+        .iter()
+        .filter(|file| broken_before.contains(&file.path))
+        .cloned()
+        .collect();
+    // Diffs only apply to the previous version, so diffed files cannot be fetched alone.
+    let mut broken_after: Vec<_> = patch_mf
+        .diff_files
+        .iter()
+        .filter(|file| broken_before.contains(&file.path))
+        .map(|file| file.path.clone())
+        .collect();
+    if !raw_files.is_empty() {
+        let root_url = get_root_url(app, options)?;
+        let target = resolve_target(
+            app,
+            http,
+            progress,
+            &root_url,
+            Some(channel),
+            Some(&patch_mf.version),
+            options,
+        )
+        .await?;
+        let unpacked = match target.platform.combined {
+            true => {
+                let staging_dir = options.cache_dir.as_deref().unwrap_or(&channel_dir);
+                Some(
+                    unpack_combined(
+                        app,
+                        http,
+                        progress,
+                        &target.platform_url,
+                        &patch_mf,
+                        staging_dir,
+                    )
+                    .await?,
+                )
+            }
+            false => None,
+        };
+        let platform_url = unpacked
+            .as_ref()
+            .map_or(&target.platform_url, |unpacked| &unpacked.url);
+        let mismatches = refetch_raw_files(
+            app,
+            http,
+            progress,
+            platform_url,
+            &version_install_dir,
+            &patch_mf,
+            &raw_files,
+            false,
+            options,
+        )
+        .await?;
+        broken_after.extend(mismatches.into_keys());
+    }
+    let healed = broken_before
+        .iter()
+        .filter(|path| !broken_after.contains(path))
+        .cloned()
+        .collect();
+
+    let message: String = match broken_after.len() {
+        0 if broken_before.is_empty() => "No broken files found".into(),
+        0 => format!("Healed {} files", broken_before.len()),
+        broken => format!("{broken} files are still broken, reinstall to repair them"),
+    };
+    progress.finish(app, &message, started)?;
+    Ok(HealSummary {
+        version: patch_mf.version,
+        broken_before,
+        healed,
+        broken_after,
+        downloaded_bytes: progress.net.value,
+        elapsed_secs: progress.elapsed_secs.unwrap_or_default(),
+    })
+}
+
+/// Extracts only `files` from the raw archive of a patch, overwriting them in
+/// `install_dir`. The other entries are streamed past without being written,
+/// except directories and links with `extract_special`, for an install that
+/// stopped before reaching them.
+///
+/// Returns the size or hash mismatch of every file that was written wrong,
+/// keyed by its manifest path.
+#[allow(clippy::too_many_arguments)]
+async fn refetch_raw_files(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    install_dir: &Path,
+    patch_mf: &PatchManifest,
+    files: &[FileManifest],
+    extract_special: bool,
+    options: &InstallOptions,
+) -> Result<HashMap<String, InstallError>, InstallError> {
+    progress.emit_phase(
+        app,
+        InstallPhase::DownloadingRaw,
+        "Re-fetching broken files",
+    )?;
+    progress.disk.max += files.iter().map(|file| file.len).sum::<u64>();
+    progress.disk.known = true;
+    progress.begin_files(files.len());
+
+    let mut wanted: HashMap<_, _> = files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let cache = match &options.cache_dir {
+        Some(dir) => Some(ArchiveCache::open(dir.clone(), options.cache_max_bytes()).await?),
+        None => None,
+    };
+    let net_counter = atomic::AtomicU64::new(0);
+    let reader = open_archive(
+        app,
+        http,
+        progress,
+        platform_url.join(&patch_mf.compression.archive_name("raw"))?,
+        &patch_mf.raw_archive_parts,
+        patch_mf.raw_archive_hash.as_ref(),
+        cache.as_ref(),
+        &net_counter,
+    )
+    .await?;
+    let archive = async_tar::Archive::new(decompress(reader, patch_mf));
+    let mut entries = archive.entries()?;
+    let mut read_buf = vec![0u8; options.read_buf_len()];
+    let mut emit_throttle = EmitThrottle::new();
+    let mut mismatches = HashMap::new();
+
+    while let Some(mut entry) = entries.next().await.transpose()? {
+        if wanted.is_empty() && !extract_special {
+            break;
+        }
+        let relative_path: PathBuf = entry.path()?.into_owned().into();
+        check_archive_path(&relative_path)?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() {
+            if extract_special {
+                let link_name = entry.link_name()?.map(|path| path.into_owned().into());
+                extract_special_entry(install_dir, &relative_path, entry_type, link_name).await?;
+            }
+            continue;
+        }
+        let Some(file) = wanted.remove(relative_path.to_string_lossy().as_ref()) else {
+            continue;
+        };
+        progress.begin_file(&file.path);
+
+        let dst_path = install_dir.join(&relative_path);
+        tokio::fs::create_dir_all(
+            dst_path
+                .parent()
+                .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
+        )
+        .await
+        .map_err(InstallError::CreateDir)?;
+        remove_existing(&dst_path).await?;
+        let mut dst_file = File::create(&dst_path).await?;
+        let mut actual_hash = FileHasher::new(file.hash_algorithm);
+        let mut actual_size = 0;
+        loop {
+            let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
+            if read == 0 {
+                break;
+            }
+            actual_hash.update(&read_buf[..read]);
+            actual_size += read as u64;
+            dst_file.write_all(&read_buf[..read]).await?;
+            progress.disk.value += read as u64;
+
+            if emit_throttle.ready() {
+                progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+                progress.emit(app)?;
+            }
+        }
+        dst_file.flush().await?;
+
+        let actual_hash = actual_hash.finish();
+        if actual_size != file.len {
+            let err = InstallError::WrongSize {
+                path: relative_path,
+                expected: file.len,
+                actual: actual_size,
+            };
+            mismatches.insert(file.path.clone(), err);
+        } else if actual_hash != file.hash {
+            let err = InstallError::WrongHash {
+                path: relative_path,
+                expected: hex::encode(file.hash),
+                actual: hex::encode(actual_hash),
+            };
+            mismatches.insert(file.path.clone(), err);
+        } else if options.emit_file_events {
+            emit_file_verified(app, &file.path, &file.hash, file.hash_algorithm)?;
+        }
+    }
+    progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+    check_archive_complete(wanted)?;
+    progress.emit(app)?;
+    Ok(mismatches)
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum VersionRole {
+    /// The version launched from the channel.
+    Active,
+    /// The version the active one was updated from, available for rollback.
+    Previous,
+    /// An older version kept by [`InstallOptions::keep_previous`].
+    Retained,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct VersionSize {
+    channel: String,
+    version: Version,
+    role: VersionRole,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct InstallSize {
+    versions: Vec<VersionSize>,
+    total: u64,
+}
+
+/// Sums the disk usage of every installed version of a channel,
+/// or of all installed channels.
+pub(crate) async fn do_get_install_size(
+    app: &dyn InstallHost,
+    install_dir: PathBuf,
+    channel: Option<&str>,
+) -> Result<InstallSize, InstallError> {
+    let layout = get_install_layout(app)?;
+
+    let channels = match channel {
+        Some(channel) => vec![channel.to_string()],
+        None => list_installed_channels(&install_dir).await?,
+    };
+
+    let mut versions = Vec::new();
+    for channel in channels {
+        let Some((patch_mf, versions_dir)) =
+            read_channel_versions(&install_dir, &layout, &channel).await?
+        else {
+            continue;
+        };
+
+        let mut dir = tokio::fs::read_dir(&versions_dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            let version = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| Version::parse(name).ok());
+            let Some(version) = version else {
+                continue;
+            };
+
+            let role = if version == patch_mf.version {
+                VersionRole::Active
+            } else if Some(&version) == patch_mf.previous_version.as_ref() {
+                VersionRole::Previous
+            } else {
+                VersionRole::Retained
+            };
+            versions.push(VersionSize {
+                channel: channel.clone(),
+                version,
+                role,
+                size: dir_size(&entry.path()).await?,
+            });
+        }
+    }
+
+    let total = versions.iter().map(|version| version.size).sum();
+    Ok(InstallSize { versions, total })
+}
+
+/// Lists the channels with an installed version below `install_dir`.
+async fn list_installed_channels(install_dir: &Path) -> Result<Vec<String>, InstallError> {
+    let mut channels = Vec::new();
+    let mut dir = match tokio::fs::read_dir(install_dir).await {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(channels),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = dir.next_entry().await? {
+        if tokio::fs::try_exists(entry.path().join("manifest.json")).await? {
+            channels.extend(entry.file_name().to_str().map(String::from));
+        }
+    }
+    Ok(channels)
+}
+
+/// Reads the installed patch of a channel together with the directory
+/// holding its versions, or `None` if the channel is not installed.
+async fn read_channel_versions(
+    install_dir: &Path,
+    layout: &InstallLayout,
+    channel: &str,
+) -> Result<Option<(PatchManifest, PathBuf)>, InstallError> {
+    let channel_dir = install_dir.join(channel.to_string() + "/");
+    let Some(patch_mf) = read_installed_patch(&channel_dir).await? else {
+        return Ok(None);
+    };
+    // Channels installed before the platform was recorded use the default layout.
+    let versions_dir = match read_active_platform(&channel_dir).await? {
+        Some(platform_mf) => install_dir.join(layout.versions_dir(channel, &platform_mf)),
+        None => channel_dir,
+    };
+    Ok(Some((patch_mf, versions_dir)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum PrunableKind {
+    /// A version beyond the [`InstallOptions::keep_versions`] newest.
+    RetainedVersion,
+    /// A temporary file left behind by an interrupted install.
+    PartialFile,
+    /// A cached archive that would be evicted to bring the cache under its size cap.
+    CachedArchive,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PrunableItem {
+    path: PathBuf,
+    kind: PrunableKind,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct StorageCleanup {
+    items: Vec<PrunableItem>,
+    /// Bytes freed by removing every item.
+    reclaimable: u64,
+    /// Whether the items were removed, rather than only reported.
+    removed: bool,
+}
+
+/// Finds data that can be removed without affecting any installed channel
+/// and removes it if `confirm` is set.
+///
+/// Partial files of an install that is still running are indistinguishable
+/// from leftovers, so this must not run alongside an install.
+pub(crate) async fn do_cleanup_storage(
+    app: &dyn InstallHost,
+    install_dir: PathBuf,
+    options: &InstallOptions,
+    confirm: bool,
+) -> Result<StorageCleanup, InstallError> {
+    let layout = get_install_layout(app)?;
+    let mut items = Vec::new();
+
+    for channel in list_installed_channels(&install_dir).await? {
+        let Some((patch_mf, versions_dir)) =
+            read_channel_versions(&install_dir, &layout, &channel).await?
+        else {
+            continue;
+        };
+        for path in
+            prunable_versions(&versions_dir, &patch_mf.version, options.keep_versions()).await?
+        {
+            items.push(PrunableItem {
+                size: dir_size(&path).await?,
+                path,
+                kind: PrunableKind::RetainedVersion,
+            });
+        }
+    }
+
+    if tokio::fs::try_exists(&install_dir).await? {
+        let entries = visit_stream(&install_dir);
+        pin_mut!(entries);
+        while let Some((file_type, entry)) = entries.next().await.transpose()? {
+            let path = entry.path();
+            let is_partial = path
+                .extension()
+                .is_some_and(|ext| ext == "part" || ext == "delta");
+            // Files inside versions about to be pruned are counted with them.
+            if file_type.is_file()
+                && is_partial
+                && !items.iter().any(|item| path.starts_with(&item.path))
+            {
+                items.push(PrunableItem {
+                    size: entry.metadata().await?.len(),
+                    path,
+                    kind: PrunableKind::PartialFile,
+                });
+            }
+        }
+    }
+
+    let mut dir = tokio::fs::read_dir(std::env::temp_dir()).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let is_temp_archive = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with("packwisely-") && name.ends_with(".part"));
+        if is_temp_archive && entry.file_type().await?.is_file() {
+            items.push(PrunableItem {
+                size: entry.metadata().await?.len(),
+                path: entry.path(),
+                kind: PrunableKind::PartialFile,
+            });
+        }
+    }
+
+    if let Some(cache_dir) = &options.cache_dir {
+        if tokio::fs::try_exists(cache_dir).await? {
+            let cache = ArchiveCache::open(cache_dir.clone(), options.cache_max_bytes()).await?;
+            for (path, size) in cache.temp_files().await? {
+                items.push(PrunableItem {
+                    path,
+                    kind: PrunableKind::PartialFile,
+                    size,
+                });
+            }
+            for (path, size) in cache.over_cap(None).await? {
+                if !items.iter().any(|item| item.path == path) {
+                    items.push(PrunableItem {
+                        path,
+                        kind: PrunableKind::CachedArchive,
+                        size,
+                    });
+                }
+            }
+        }
+    }
+
+    if confirm {
+        for item in &items {
+            match item.kind {
+                PrunableKind::RetainedVersion => remove_version(&item.path).await?,
+                PrunableKind::PartialFile | PrunableKind::CachedArchive => {
+                    remove_existing(&item.path).await?
+                }
+            }
+        }
+    }
+
+    let reclaimable = items.iter().map(|item| item.size).sum();
+    Ok(StorageCleanup {
+        items,
+        reclaimable,
+        removed: confirm,
+    })
+}
+
+/// Finds the platform directory of an installed version by its manifest.
+async fn find_version_install_dir(
+    channel_dir: &Path,
+    version: &Version,
+) -> Result<Option<PathBuf>, InstallError> {
+    let version_dir = channel_dir.join(version.to_string());
+    let mut dir = match tokio::fs::read_dir(&version_dir).await {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = dir.next_entry().await? {
+        if entry.file_type().await?.is_dir()
+            && read_version_manifest(&entry.path()).await?.is_some()
+        {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks the size and hash of every file of a version against its manifest.
+/// When `broken_files` collects them, mismatching and missing files are
+/// recorded there instead of failing the check.
+async fn verify_files(
+    app: &dyn InstallHost,
+    progress: &mut InstallProgress,
+    install_dir: &Path,
+    patch_mf: &PatchManifest,
+    emit_file_events: bool,
+    broken_files: &mut Option<Vec<String>>,
+) -> Result<(), InstallError> {
+    let files: Vec<_> = patch_mf
+        .new_files
+        .iter()
+        .chain(patch_mf.diff_files.iter())
+        .collect();
+
+    progress.disk.value = 0;
+    progress.disk.max = files.iter().map(|file| file.len).sum();
+    progress.disk.known = true;
+    progress.begin_files(files.len());
+    progress.emit(app)?;
+
+    let mut read_buf = vec![0u8; 1024 * 64];
+    let mut emit_throttle = EmitThrottle::new();
+
+    for file in files {
+        progress.begin_file(&file.path);
+        let mut src_file = match File::open(install_dir.join(&file.path)).await {
+            Ok(src_file) => src_file,
+            Err(err) if err.kind() == ErrorKind::NotFound && broken_files.is_some() => {
+                let err = InstallError::WrongSize {
+                    path: (&file.path).into(),
+                    expected: file.len,
+                    actual: 0,
+                };
+                record_broken(broken_files, err)?;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let mut actual_hash = FileHasher::new(file.hash_algorithm);
+        let mut actual_size = 0;
+        loop {
+            let read = src_file.read(&mut read_buf).await?;
+            if read == 0 {
+                break;
+            }
+            actual_hash.update(&read_buf[..read]);
+            actual_size += read as u64;
+
+            progress.disk.value += read as u64;
+            if emit_throttle.ready() {
+                progress.emit(app)?;
+            }
+        }
+
+        if actual_size != file.len {
+            let err = InstallError::WrongSize {
+                path: (&file.path).into(),
+                expected: file.len,
+                actual: actual_size,
+            };
+            record_broken(broken_files, err)?;
+            continue;
+        }
+        let actual_hash = actual_hash.finish();
+        if file.hash != actual_hash {
+            let err = InstallError::WrongHash {
+                path: (&file.path).into(),
+                expected: hex::encode(file.hash),
+                actual: hex::encode(actual_hash),
+            };
+            record_broken(broken_files, err)?;
+            continue;
+        }
+        if emit_file_events {
+            emit_file_verified(app, &file.path, &file.hash, file.hash_algorithm)?;
+        }
+    }
+
+    progress.disk.complete();
+    progress.emit(app)?;
+    Ok(())
+}
+
+/// Removes retained version directories older than `active`, keeping the newest `keep`.
+async fn prune_versions(
+    versions_dir: &Path,
+    active: &Version,
+    keep: usize,
+) -> Result<(), InstallError> {
+    for path in prunable_versions(versions_dir, active, keep).await? {
+        remove_version(&path).await?;
+    }
+    Ok(())
+}
+
+/// Lists the version directories older than `active`, except for the `keep` newest.
+async fn prunable_versions(
+    versions_dir: &Path,
+    active: &Version,
+    keep: usize,
+) -> Result<Vec<PathBuf>, InstallError> {
+    let mut versions = Vec::new();
+    let mut dir = tokio::fs::read_dir(versions_dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let version = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Version::parse(name).ok());
+        if let Some(version) = version.filter(|version| version < active) {
+            versions.push((version, entry.path()));
+        }
+    }
+
+    versions.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(versions
+        .into_iter()
+        .skip(keep)
+        .map(|(_, path)| path)
+        .collect())
+}
+
+async fn remove_version(path: &Path) -> Result<(), InstallError> {
+    // Layouts ending in the version keep its manifest beside the directory.
+    match tokio::fs::remove_file(join_version_manifest_path(path)).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    tokio::fs::remove_dir_all(path).await?;
+    Ok(())
+}
+
+async fn get_channels(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    root_url: &Url,
+) -> Result<Vec<ChannelManifest>, InstallError> {
+    progress.emit_phase(app, InstallPhase::FetchingManifests, "Fetching channels")?;
+    let channels_url = root_url.join("channels.json")?;
+    let channels_hash = get_channels_hash(app)?;
+    let channels_json: Vec<ChannelManifest> = progress
+        .get_json(http, channels_url, channels_hash.as_ref())
+        .await?;
+    if channels_json.is_empty() {
+        return Err(malformed("channels.json lists no channels"));
+    }
+    if channels_json.iter().any(|mf| mf.name.is_empty()) {
+        return Err(malformed("channels.json contains a channel without a name"));
+    }
+    if let Some(mf) = channels_json.iter().find(|mf| !is_path_segment(&mf.name)) {
+        return Err(malformed(format!(
+            "channel name \"{}\" is not a single directory name",
+            mf.name
+        )));
+    }
+    Ok(channels_json)
+}
+
+/// Whether `name` stays a single directory when joined onto a path, which
+/// names of channels and platforms have to as they are installed by them.
+fn is_path_segment(name: &str) -> bool {
+    name != "." && name != ".." && !name.contains(['/', '\\', ':', '\0'])
+}
+
+/// Reads `plugins.installer.channelsHash`, the hex-encoded hash pinning
+/// `channels.json`, which in turn pins the hashes of everything below it.
+fn get_channels_hash(app: &dyn InstallHost) -> Result<Option<[u8; 32]>, InstallError> {
+    let configured_hash = app
+        .plugin_config("installer")
+        .and_then(|o| o.get("channelsHash").and_then(|o| o.as_str()));
+    configured_hash
+        .map(|input| {
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(input, &mut hash)
+                .map_err(|_| InstallError::InvalidConfiguredHash(input.into()))?;
+            Ok(hash)
+        })
+        .transpose()
+}
+
+async fn get_versions(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    root_url: &Url,
+    channel_mf: &ChannelManifest,
+) -> Result<Vec<VersionManifest>, InstallError> {
+    progress.emit_phase(app, InstallPhase::FetchingManifests, "Fetching versions")?;
+    let versions_url = channel_mf.join_url(root_url)?.join("versions.json")?;
+    let mut versions_json: Vec<VersionManifest> = progress
+        .get_json(http, versions_url, channel_mf.versions_hash.as_ref())
+        .await?;
+    // Callers take the last entry as the newest, whatever order the file is in.
+    versions_json.sort_by(|a, b| a.version.cmp(&b.version));
+    if versions_json.is_empty() {
+        return Err(malformed(format!(
+            "versions.json of channel \"{}\" lists no versions",
+            channel_mf.name
+        )));
+    }
+    for version_mf in versions_json.iter() {
+        if version_mf.platforms.is_empty() {
+            return Err(malformed(format!(
+                "version {} has no platforms",
+                version_mf.version
+            )));
+        }
+        for platform_mf in version_mf.platforms.iter() {
+            if platform_mf.os.is_empty() || platform_mf.arch.is_empty() {
+                return Err(malformed(format!(
+                    "version {} has a platform without os or arch",
+                    version_mf.version
+                )));
+            }
+            if !is_path_segment(&platform_mf.os) || !is_path_segment(&platform_mf.arch) {
+                return Err(malformed(format!(
+                    "platform {}/{} of version {} is not named by single directory names",
+                    platform_mf.os, platform_mf.arch, version_mf.version
+                )));
+            }
+            if platform_mf.exe_path.is_empty() {
+                return Err(malformed(format!(
+                    "platform {}/{} of version {} has an empty exePath",
+                    platform_mf.os, platform_mf.arch, version_mf.version
+                )));
+            }
+        }
+    }
+    Ok(versions_json)
+}
+
+fn get_platforms(version_mf: &VersionManifest) -> Result<Vec<PlatformManifest>, InstallError> {
+    let mut os_ok_list: Vec<_> = version_mf
+        .platforms
+        .iter()
+        .filter(|mf| is_os(mf, std::env::consts::OS))
+        .collect();
+
+    let wine_path = get_wine_path().ok();
+    if wine_path.is_some() {
+        // Append Wine-compatible entries after exact matches.
+        os_ok_list.extend(
+            version_mf
+                .platforms
+                .iter()
+                .filter(|mf| is_os(mf, "windows")),
+        );
+    }
+    if os_ok_list.is_empty() {
+        return Err(InstallError::UnsupportedOS.into());
+    }
+
+    let arch_ok_list: Vec<_> = os_ok_list
+        .into_iter()
+        .filter(|mf| is_arch(mf, std::env::consts::ARCH))
+        .cloned()
+        .collect();
+    if arch_ok_list.is_empty() {
+        return Err(InstallError::UnsupportedArch.into());
+    }
+    Ok(arch_ok_list)
+}
+
+/// Fetches the patch manifest of a platform. With the `installed` manifest
+/// as its base, a published manifest delta is fetched instead, falling back
+/// to the full manifest if there is none or it cannot be used.
+async fn get_patch(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    platform_mf: &PlatformManifest,
+    installed: Option<&PatchManifest>,
+) -> Result<PatchManifest, InstallError> {
+    progress.emit_phase(
+        app,
+        InstallPhase::FetchingManifests,
+        "Fetching platform manifest",
+    )?;
+
+    if platform_mf.combined {
+        let patch_mf = get_combined_manifest(
+            http,
+            progress,
+            platform_url,
+            platform_mf.manifest_hash.as_ref(),
+        )
+        .await?;
+        validate_patch(&patch_mf)?;
+        return Ok(patch_mf);
+    }
+
+    // A rebuilt manifest cannot be checked against a pinned manifest hash,
+    // so the delta is only trusted if it is pinned as well.
+    let delta_pinned =
+        platform_mf.manifest_hash.is_none() || platform_mf.manifest_delta_hash.is_some();
+    let delta_base = installed
+        .filter(|base| platform_mf.manifest_delta_base.as_ref() == Some(&base.version))
+        .filter(|_| delta_pinned);
+    if let Some(base) = delta_base {
+        let delta_url = platform_url.join("manifest-delta.json")?;
+        let delta = progress
+            .get_json(http, delta_url, platform_mf.manifest_delta_hash.as_ref())
+            .await;
+        match delta.and_then(|delta| apply_manifest_delta(base, delta)) {
+            Ok(patch_mf) => return Ok(patch_mf),
+            Err(err) => {
+                progress.message = format!("Fetching full manifest, delta is unusable: {err}");
+                progress.emit(app)?;
+            }
+        }
+    }
+
+    let manifest_url = platform_url.join("manifest.json")?;
+    let manifest_json = progress
+        .get_json(http, manifest_url, platform_mf.manifest_hash.as_ref())
+        .await?;
+    validate_patch(&manifest_json)?;
+    Ok(manifest_json)
+}
+
+/// Reads the manifest at the head of a [`COMBINED_ARCHIVE_NAME`],
+/// downloading no further than that first entry.
+async fn get_combined_manifest(
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    expected_hash: Option<&[u8; 32]>,
+) -> Result<PatchManifest, InstallError> {
+    let url = platform_url.join(COMBINED_ARCHIVE_NAME)?;
+    let reader: Pin<Box<dyn AsyncRead + Send>> = match to_local_path(&url)? {
+        Some(path) => Box::pin(File::open(path).await?),
+        None => {
+            let response = http.get(&url).await?;
+            Box::pin(StreamReader::new(stall_guard(
+                response.bytes_stream(),
+                http.stall_timeout(),
+            )))
+        }
+    };
+    let archive = async_tar::Archive::new(reader.compat());
+    let mut entries = archive.entries()?;
+
+    let mut entry = match entries.next().await.transpose()? {
+        Some(entry) if entry.path()?.to_str() == Some("manifest.json") => entry,
+        _ => {
+            return Err(malformed(format!(
+                "{COMBINED_ARCHIVE_NAME} does not start with manifest.json"
+            )))
+        }
+    };
+    let mut manifest_json = Vec::new();
+    futures::AsyncReadExt::read_to_end(&mut entry, &mut manifest_json).await?;
+    progress.net.add_both(manifest_json.len() as u64);
+
+    check_document_hash(&url, &manifest_json, expected_hash)?;
+    Ok(serde_json::from_slice(&manifest_json)?)
+}
+
+/// Rebuilds a patch manifest from the manifest of its previous version.
+fn apply_manifest_delta(
+    base: &PatchManifest,
+    delta: ManifestDelta,
+) -> Result<PatchManifest, InstallError> {
+    let mut patch_mf = delta.manifest;
+    if delta.base_version != base.version
+        || patch_mf.previous_version.as_ref() != Some(&base.version)
+    {
+        return Err(malformed(format!(
+            "manifest delta is relative to {}, not the installed {}",
+            delta.base_version, base.version
+        )));
+    }
+
+    let mut changed: HashMap<_, _> = std::mem::take(&mut patch_mf.diff_files)
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+    let removed: HashSet<_> = delta.removed_files.iter().map(String::as_str).collect();
+    for file in base.new_files.iter().chain(base.diff_files.iter()) {
+        if removed.contains(file.path.as_str()) {
+            continue;
+        }
+        patch_mf
+            .diff_files
+            .push(changed.remove(&file.path).unwrap_or_else(|| file.clone()));
+    }
+    if let Some(path) = changed.into_keys().next() {
+        return Err(malformed(format!(
+            "manifest delta changes {path}, which the installed version lacks"
+        )));
+    }
+
+    validate_patch(&patch_mf)?;
+    Ok(patch_mf)
+}
+
+fn validate_patch(patch_mf: &PatchManifest) -> Result<(), InstallError> {
+    match patch_mf.manifest_version {
+        PatchManifestVersion::V1 => {
+            let mut files = patch_mf.new_files.iter().chain(patch_mf.diff_files.iter());
+            if files.any(|file| file.path.is_empty()) {
+                return Err(malformed("patch manifest contains a file without a path"));
+            }
+            if patch_mf.stale_files.iter().any(|path| path.is_empty()) {
+                return Err(malformed("patch manifest contains an empty stale path"));
+            }
+            let files = patch_mf.new_files.iter().chain(patch_mf.diff_files.iter());
+            if let Some(path) = find_duplicate_path(files.map(|file| file.path.as_str())) {
+                return Err(malformed(format!(
+                    "patch manifest lists {path} more than once"
+                )));
+            }
+            for file in patch_mf.new_files.iter().chain(patch_mf.diff_files.iter()) {
+                if file.block_hashes.is_empty() {
+                    continue;
+                }
+                let expected_blocks = patch_mf
+                    .hash_block_len
+                    .filter(|&block_len| block_len > 0)
+                    .map(|block_len| file.len.div_ceil(block_len));
+                if expected_blocks != Some(file.block_hashes.len() as u64) {
+                    return Err(malformed(format!(
+                        "block hashes of {} do not cover the file",
+                        file.path
+                    )));
+                }
+            }
+
+            match &patch_mf.previous_version {
+                None if !patch_mf.diff_files.is_empty() => {
+                    return Err(malformed(
+                        "patch manifest has diff files but no previous version",
+                    ));
+                }
+                Some(previous_version) if previous_version >= &patch_mf.version => {
+                    return Err(malformed(format!(
+                        "previous version {} is not older than {}",
+                        previous_version, patch_mf.version
+                    )));
+                }
+                _ => {}
+            }
+
+            let split_archives = [
+                (
+                    "raw",
+                    &patch_mf.raw_archive_parts,
+                    patch_mf.raw_archive_size,
+                ),
+                (
+                    "diff",
+                    &patch_mf.diff_archive_parts,
+                    patch_mf.diff_archive_size,
+                ),
+            ];
+            for (stem, parts, size) in split_archives {
+                if !parts.is_empty() && size.is_some_and(|size| size != parts.iter().sum::<u64>()) {
+                    return Err(malformed(format!(
+                        "{stem} archive parts do not add up to its size"
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn malformed(reason: impl Into<String>) -> InstallError {
+    InstallError::MalformedManifest {
+        reason: reason.into(),
+    }
+}
+
+async fn verify_channel_dir(
+    app: &dyn InstallHost,
+    progress: &mut InstallProgress,
+    channel_dir: &PathBuf,
+) -> Result<Option<PatchManifest>, InstallError> {
+    progress.emit_phase(app, InstallPhase::Verifying, "Verifying install directory")?;
+    read_installed_patch(channel_dir).await
+}
+
+/// Reads the manifest of the version a channel is on, or `None` if it is not installed.
+async fn read_installed_patch(channel_dir: &Path) -> Result<Option<PatchManifest>, InstallError> {
+    match File::open(channel_dir.join("manifest.json")).await {
+        Ok(mut file) => {
+            let mut str = String::new();
+            file.read_to_string(&mut str).await?;
+            let patch_mf =
+                serde_json::from_str(&str).map_err(|e| InstallError::InvalidInstalledPatch(e))?;
+            Ok(Some(patch_mf))
+        }
+        Err(err) => {
+            if err.kind() == ErrorKind::NotFound {
+                Ok(None)
+            } else {
+                Err(err.into())
+            }
+        }
+    }
+}
+
+/// Copies the saves of the version being updated from into the new version.
+async fn migrate_saves(
+    app: &dyn InstallHost,
+    progress: &mut InstallProgress,
+    old_install_dir: &Path,
+    new_install_dir: &Path,
+    platform_mf: &PlatformManifest,
+    options: &InstallOptions,
+) -> Result<(), InstallError> {
+    let old_save_dir = resolve_save_dir(old_install_dir, platform_mf)?;
+    let new_save_dir = resolve_save_dir(new_install_dir, platform_mf)?;
+    if old_save_dir == new_save_dir {
+        // Saves outside the install directory are shared between versions.
+        return Ok(());
+    }
+
+    progress.emit_phase(app, InstallPhase::CopyingSaves, "Copying save files")?;
+    // Linked saves share their contents with the old install,
+    // so they are only used when the old install is not retained.
+    let hard_link = !options.keep_previous;
+    for save_dir in ["Config", "SaveGames"] {
+        copy_dir(
+            &old_save_dir.join(save_dir),
+            &new_save_dir.join(save_dir),
+            hard_link,
+            options.walk_concurrency(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Carries over files of the version being updated from that the new version
+/// neither lists nor removes as stale, such as files the patch protected from
+/// removal, as they would have stayed in place in a shared install directory.
+/// Saves are left to [`migrate_saves`].
+async fn carry_unlisted_files(
+    old_install_dir: &Path,
+    new_install_dir: &Path,
+    platform_mf: &PlatformManifest,
+    new_patch_mf: &PatchManifest,
+    options: &InstallOptions,
+) -> Result<(), InstallError> {
+    if old_install_dir == new_install_dir || !tokio::fs::try_exists(old_install_dir).await? {
+        return Ok(());
+    }
+    let old_save_dir = resolve_save_dir(old_install_dir, platform_mf)?;
+    let listed: HashSet<&str> = new_patch_mf
+        .new_files
+        .iter()
+        .chain(&new_patch_mf.diff_files)
+        .map(|file| file.path.as_str())
+        .chain(new_patch_mf.stale_files.iter().map(String::as_str))
+        .collect();
+    // Hard links share their contents with the old install,
+    // so they are only used when the old install is not retained.
+    let hard_link = !options.keep_previous;
+
+    let entries = visit_stream_concurrent(old_install_dir, options.walk_concurrency());
+    pin_mut!(entries);
+    while let Some((file_type, entry)) = entries.next().await.transpose()? {
+        let src_path = entry.path();
+        if file_type.is_dir() || src_path.starts_with(&old_save_dir) {
+            continue;
+        }
+        let relative_path = src_path
+            .strip_prefix(old_install_dir)
+            .map_err(CopyError::from)?;
+        let archive_path = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let dst_path = new_install_dir.join(relative_path);
+        if listed.contains(archive_path.as_str()) || tokio::fs::try_exists(&dst_path).await? {
+            continue;
+        }
+
+        if let Some(parent) = dst_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if !link_file(&src_path, &dst_path, hard_link).await? {
+            tokio::fs::copy(&src_path, &dst_path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Host directory holding the saves of a platform installed at `install_dir`.
+///
+/// [`PlatformManifest::save_dir`] may point into the user profile with
+/// `%VARIABLE%`s, which for Wine builds resolve inside the Wine prefix.
+fn resolve_save_dir(
+    install_dir: &Path,
+    platform_mf: &PlatformManifest,
+) -> Result<PathBuf, InstallError> {
+    let Some(save_dir) = &platform_mf.save_dir else {
+        return Ok(install_dir.join("PackWisely/Saved"));
+    };
+    let unresolved = || InstallError::UnresolvedSaveDir(save_dir.clone());
+
+    if needs_wine(platform_mf) {
+        let prefix = get_wine_prefix(&platform_mf.env).ok_or_else(unresolved)?;
+        let save_dir = expand_wine_vars(save_dir).ok_or_else(unresolved)?;
+        return match to_host_path(&prefix, &save_dir) {
+            Some(path) => Ok(path),
+            None => Ok(install_dir.join(save_dir.replace('\\', "/"))),
+        };
+    }
+
+    let save_dir = expand_native_vars(save_dir).ok_or_else(unresolved)?;
+    // Absolute paths replace the install directory when joined.
+    Ok(install_dir.join(save_dir))
+}
+
+/// Cheaply checks that every file of an installed version exists with its
+/// expected size, catching installs interrupted before they were complete.
+async fn is_install_complete(
+    install_dir: &Path,
+    patch_mf: &PatchManifest,
+) -> Result<bool, InstallError> {
+    for file in patch_mf.new_files.iter().chain(patch_mf.diff_files.iter()) {
+        match tokio::fs::metadata(install_dir.join(&file.path)).await {
+            Ok(metadata) if metadata.len() == file.len => {}
+            Ok(_) => return Ok(false),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn install_patch(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    old_install_dir: Option<PathBuf>,
+    new_install_dir: &PathBuf,
+    new_patch_mf: PatchManifest,
+    options: &InstallOptions,
+    repair: bool,
+) -> Result<Vec<String>, InstallError> {
+    // Repairing carries on past files that fail verification and reports them.
+    let mut broken_files = repair.then(Vec::new);
+    progress.disk.max = new_patch_mf
+        .new_files
+        .iter()
+        .chain(new_patch_mf.diff_files.iter())
+        .map(|file| file.len)
+        .sum();
+
+    progress.disk.known = true;
+    progress.begin_files(new_patch_mf.new_files.len() + new_patch_mf.diff_files.len());
+
+    let mut read_buf = vec![0u8; options.read_buf_len()];
+    let mut delta_buf = Vec::with_capacity(read_buf.len());
+    let mut part_buf = Vec::with_capacity(read_buf.len());
+
+    let mut emit_throttle = EmitThrottle::new();
+
+    // Use atomic counters for Send-safety.
+    let response_net_counter = atomic::AtomicU64::new(0);
+    let decompress_counter = atomic::AtomicU64::new(0);
+
+    progress.decompress.value = 0;
+    progress.decompress.max = 0;
+    progress.decompress.known = true;
+    if !new_patch_mf.diff_files.is_empty() {
+        progress.decompress.add_max(new_patch_mf.diff_tar_size);
+    }
+    if !new_patch_mf.new_files.is_empty() {
+        progress.decompress.add_max(new_patch_mf.raw_tar_size);
+    }
+
+    let mut files_to_remove = Vec::new();
+
+    let cache = match &options.cache_dir {
+        Some(dir) => Some(ArchiveCache::open(dir.clone(), options.cache_max_bytes()).await?),
+        None => None,
+    };
+
+    if !new_patch_mf.diff_files.is_empty() {
+        progress.emit_phase(
+            app,
+            InstallPhase::DownloadingDiff,
+            "Updating existing files",
+        )?;
+
+        let old_install_dir = old_install_dir
+            .as_ref()
+            .ok_or(InstallError::MissingPreviousVersion)?;
+
+        // Files the old version already has verbatim are linked instead of patched.
+        let old_files: HashMap<_, _> = match read_version_manifest(old_install_dir).await? {
+            Some(old_mf) => old_mf
+                .patch
+                .new_files
+                .into_iter()
+                .chain(old_mf.patch.diff_files)
+                .map(|file| (file.path, (file.len, file.hash, file.hash_algorithm)))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut diff_set = HashMap::with_capacity(new_patch_mf.diff_files.len());
+        for file in new_patch_mf.diff_files.iter() {
+            diff_set.insert(
+                file.path.as_str(),
+                (file.len, &file.hash, file.hash_algorithm),
+            );
+        }
+
+        let diff_tar_reader = open_archive(
+            app,
+            http,
+            progress,
+            platform_url.join(&new_patch_mf.compression.archive_name("diff"))?,
+            &new_patch_mf.diff_archive_parts,
+            new_patch_mf.diff_archive_hash.as_ref(),
+            cache.as_ref(),
+            &response_net_counter,
+        )
+        .await?;
+        let tar_stream = CountingReader::new(
+            decompress(diff_tar_reader, &new_patch_mf),
+            &decompress_counter,
+        );
+        let archive = async_tar::Archive::new(tar_stream);
+        let mut entries = archive.entries()?;
+
+        while let Some(mut entry) = entries.next().await.transpose()? {
+            let relative_path: PathBuf = entry.path()?.into_owned().into();
+            check_archive_path(&relative_path)?;
+
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_file() {
+                let link_name = entry.link_name()?.map(|path| path.into_owned().into());
+                extract_special_entry(new_install_dir, &relative_path, entry_type, link_name)
+                    .await?;
+                continue;
+            }
+
+            // Taking each file out also rejects duplicate entries.
+            let (dst_size, dst_hash, dst_hash_algorithm) = diff_set
+                .remove(relative_path.to_string_lossy().as_ref())
+                .ok_or(InstallError::UnexpectedArchiveFile((&relative_path).into()))?;
+            progress.begin_file(&relative_path.to_string_lossy());
+
+            let src_path = old_install_dir.join(&relative_path);
+            let dst_path = new_install_dir.join(&relative_path);
+            tokio::fs::create_dir_all(
+                dst_path
+                    .parent()
+                    .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
+            )
+            .await
+            .map_err(|e| InstallError::CreateDir(e))?;
+
+            let path = relative_path.to_string_lossy();
+            let is_unchanged =
+                old_files.get(path.as_ref()) == Some(&(dst_size, *dst_hash, dst_hash_algorithm));
+            // A hard link would let writes to the new file reach a retained old one.
+            if is_unchanged && link_file(&src_path, &dst_path, !options.keep_previous).await? {
+                // The old file may have been modified since it was installed.
+                if &hash_file_as(&dst_path, dst_hash_algorithm).await? == dst_hash {
+                    if options.emit_file_events {
+                        emit_file_verified(app, &path, dst_hash, dst_hash_algorithm)?;
+                    }
+                    progress.disk.value += dst_size;
+                    files_to_remove.push(src_path);
+                    continue;
+                }
+                tokio::fs::remove_file(&dst_path).await?;
+            }
+
+            let mut delta_spill = if options.should_spill(entry.header().size()?) {
+                Some(DeltaSpill::create(append_extension(&dst_path, "delta"))?)
+            } else {
+                None
+            };
+
+            remove_existing(&dst_path).await?;
+            let mut dst_file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(dst_path)?;
+            dst_file.set_len(dst_size)?;
+            let mut dst_actual_hash = FileHasher::new(dst_hash_algorithm);
+
+            let src_buf = map_or_read(File::open(&src_path).await?).await?;
+            loop {
+                let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
+                if read == 0 {
+                    break;
+                }
+                match &mut delta_spill {
+                    Some(spill) => spill.file().write_all(&read_buf[..read])?,
+                    None => delta_buf.extend_from_slice(&read_buf[..read]),
+                }
+
+                if emit_throttle.ready() {
+                    progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
+                    progress.decompress.value +=
+                        decompress_counter.swap(0, atomic::Ordering::Relaxed);
+                    progress.emit(app)?;
+                }
+            }
+
+            // Dropping the spill removes it, also when applying fails.
+            if let Some(mut spill) = delta_spill.take() {
+                let spill_file = spill.file();
+                spill_file.flush()?;
+                {
+                    let spill_mmap = unsafe { Mmap::map(&*spill_file) }?;
+                    check_delta(&spill_mmap, src_buf.len() as u64, dst_size).map_err(|source| {
+                        InstallError::MalformedDelta {
+                            path: relative_path.clone(),
+                            source,
+                        }
+                    })?;
+                    fast_rsync::apply_limited(
+                        &src_buf,
+                        &spill_mmap,
+                        &mut dst_file,
+                        dst_size as usize,
+                    )
+                    .map_err(|source| InstallError::DiffApplyFailed {
+                        path: relative_path.clone(),
+                        source,
+                    })?;
+                }
+            } else {
+                check_delta(&delta_buf, src_buf.len() as u64, dst_size).map_err(|source| {
+                    InstallError::MalformedDelta {
+                        path: relative_path.clone(),
+                        source,
+                    }
+                })?;
+                fast_rsync::apply_limited(&src_buf, &delta_buf, &mut dst_file, dst_size as usize)
+                    .map_err(|source| InstallError::DiffApplyFailed {
+                    path: relative_path.clone(),
+                    source,
+                })?;
+                delta_buf.clear();
+            }
+            dst_file.flush()?;
+
+            let dst_actual_size = dst_file.stream_position()?;
+            progress.disk.value += dst_actual_size;
+            if dst_size != dst_actual_size {
+                let err = InstallError::WrongSize {
+                    path: relative_path.clone(),
+                    expected: dst_size,
+                    actual: dst_actual_size,
+                };
+                record_broken(&mut broken_files, err)?;
+                continue;
+            }
+
+            dst_file.seek(std::io::SeekFrom::Start(0))?;
+            loop {
+                let len = dst_file.read(read_buf.as_mut())?;
+                if len == 0 {
+                    break;
+                }
+                dst_actual_hash.update(&read_buf[..len]);
+            }
+            let dst_actual_hash = dst_actual_hash.finish();
+            if dst_hash != &dst_actual_hash {
+                let err = InstallError::WrongHash {
+                    path: relative_path.clone(),
+                    expected: hex::encode(dst_hash),
+                    actual: hex::encode(dst_actual_hash),
+                };
+                record_broken(&mut broken_files, err)?;
+                continue;
+            }
+            if options.emit_file_events {
+                emit_file_verified(app, &path, dst_hash, dst_hash_algorithm)?;
+            }
+            files_to_remove.push(src_path);
+        }
+        check_archive_complete(diff_set)?;
+        progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
+        progress.decompress.value += decompress_counter.swap(0, atomic::Ordering::Relaxed);
+    }
+
+    if !new_patch_mf.new_files.is_empty() {
+        progress.emit_phase(app, InstallPhase::DownloadingRaw, "Downloading new files")?;
+
+        let mut new_set = HashMap::with_capacity(new_patch_mf.new_files.len());
+        for file in new_patch_mf.new_files.iter() {
+            new_set.insert(
+                file.path.as_str(),
+                (
+                    file.len,
+                    &file.hash,
+                    file.hash_algorithm,
+                    &file.block_hashes,
+                ),
+            );
+        }
+
+        let raw_tar_reader = open_archive(
+            app,
+            http,
+            progress,
+            platform_url.join(&new_patch_mf.compression.archive_name("raw"))?,
+            &new_patch_mf.raw_archive_parts,
+            new_patch_mf.raw_archive_hash.as_ref(),
+            cache.as_ref(),
+            &response_net_counter,
+        )
+        .await?;
+        let tar_stream = CountingReader::new(
+            decompress(raw_tar_reader, &new_patch_mf),
+            &decompress_counter,
+        );
+        let archive = async_tar::Archive::new(tar_stream);
+        let mut entries = archive.entries()?;
+        let mut failed_files = HashMap::new();
+        let mut aborted = false;
+
+        while let Some(mut entry) = entries.next().await.transpose()? {
+            let relative_path: PathBuf = entry.path()?.into_owned().into();
+            check_archive_path(&relative_path)?;
+
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_file() {
+                let link_name = entry.link_name()?.map(|path| path.into_owned().into());
+                extract_special_entry(new_install_dir, &relative_path, entry_type, link_name)
+                    .await?;
+                continue;
+            }
+
+            // Taking each file out also rejects duplicate entries.
+            let (dst_size, dst_hash, dst_hash_algorithm, dst_block_hashes) = new_set
+                .remove(relative_path.to_string_lossy().as_ref())
+                .ok_or(InstallError::UnexpectedArchiveFile((&relative_path).into()))?;
+            progress.begin_file(&relative_path.to_string_lossy());
+
+            let dst_path = new_install_dir.join(&relative_path);
+            tokio::fs::create_dir_all(
+                dst_path
+                    .parent()
+                    .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
+            )
+            .await
+            .map_err(|e| InstallError::CreateDir(e))?;
+
+            // Large files are written to a `.part` file that survives an interrupted
+            // install. On resume, the leading bytes already on disk are compared
+            // against the stream and only rewritten from the first mismatch.
+            let part_path = options
+                .is_resumable(dst_size)
+                .then(|| append_extension(&dst_path, "part"));
+
+            let mut dst_file = match &part_path {
+                Some(part_path) => {
+                    File::options()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(part_path)
+                        .await?
+                }
+                None => {
+                    remove_existing(&dst_path).await?;
+                    File::create(&dst_path).await?
+                }
+            };
+            let mut resume_len = dst_file.metadata().await?.len().min(dst_size);
+            dst_file.set_len(dst_size).await?;
+
+            let mut dst_actual_hash = FileHasher::new(dst_hash_algorithm);
+            // Block hashes catch corruption without waiting for the whole file.
+            let mut dst_blocks = new_patch_mf
+                .hash_block_len
+                .filter(|_| !dst_block_hashes.is_empty())
+                .map(BlockHasher::new);
+            let mut checked_blocks = 0;
+            let mut block_err = None;
+            loop {
+                let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
+                if read == 0 {
+                    break;
+                }
+                let mut split = &read_buf[..read];
+                dst_actual_hash.update(split);
+                if let Some(blocks) = &mut dst_blocks {
+                    blocks.update(split);
+                    let completed = blocks.completed();
+                    let mismatch = (checked_blocks..completed.len())
+                        .find(|&block| dst_block_hashes.get(block) != Some(&completed[block]));
+                    checked_blocks = completed.len();
+                    if let Some(block) = mismatch {
+                        // The rest of the entry is skipped over without being written.
+                        block_err = Some(InstallError::WrongBlockHash {
+                            path: relative_path.clone(),
+                            block,
+                        });
+                        break;
+                    }
+                }
+
+                if resume_len > 0 {
+                    let compare_len = split.len().min(resume_len as usize);
+                    part_buf.resize(compare_len, 0);
+                    dst_file.read_exact(&mut part_buf).await?;
+
+                    if part_buf[..] == split[..compare_len] {
+                        resume_len -= compare_len as u64;
+                        split = &split[compare_len..];
+                    } else {
+                        dst_file
+                            .seek(std::io::SeekFrom::Current(-(compare_len as i64)))
+                            .await?;
+                        resume_len = 0;
+                    }
+                }
+                dst_file.write_all(split).await?;
+                progress.disk.value += read as u64;
+
+                if emit_throttle.ready() {
+                    progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
+                    progress.decompress.value +=
+                        decompress_counter.swap(0, atomic::Ordering::Relaxed);
+                    progress.emit(app)?;
+                }
+            }
+            dst_file.flush().await?;
+
+            let dst_actual_size = dst_file.stream_position().await?;
+            let dst_actual_hash = dst_actual_hash.finish();
+            let err = if block_err.is_some() {
+                block_err
+            } else if dst_size != dst_actual_size {
+                Some(InstallError::WrongSize {
+                    path: relative_path.clone(),
+                    expected: dst_size,
+                    actual: dst_actual_size,
+                })
+            } else if dst_hash != &dst_actual_hash {
+                Some(InstallError::WrongHash {
+                    path: relative_path.clone(),
+                    expected: hex::encode(dst_hash),
+                    actual: hex::encode(dst_actual_hash),
+                })
+            } else {
+                None
+            };
+            if let Some(err) = err {
+                if let Some(part_path) = &part_path {
+                    // Corrupt leftovers must not be trusted on the next attempt.
+                    drop(dst_file);
+                    tokio::fs::remove_file(part_path).await?;
+                }
+                // Corruption in transit is fetched again right away, instead of
+                // streaming the rest of the archive first, if it can be retried.
+                let abort =
+                    matches!(err, InstallError::WrongBlockHash { .. }) && options.max_retries() > 0;
+                failed_files.insert(relative_path.to_string_lossy().into_owned(), err);
+                if abort {
+                    aborted = true;
+                    break;
+                }
+                continue;
+            }
+
+            if let Some(part_path) = &part_path {
+                drop(dst_file);
+                tokio::fs::rename(part_path, &dst_path).await?;
+            }
+            if options.emit_file_events {
+                emit_file_verified(
+                    app,
+                    &relative_path.to_string_lossy(),
+                    dst_hash,
+                    dst_hash_algorithm,
+                )?;
+            }
+        }
+        drop(entries);
+        progress.net.value += response_net_counter.swap(0, atomic::Ordering::Relaxed);
+        progress.decompress.value += decompress_counter.swap(0, atomic::Ordering::Relaxed);
+        if aborted {
+            // The files after the corrupt one were never reached.
+            for path in new_set.into_keys() {
+                failed_files.insert(path.into(), InstallError::MissingArchiveFile(path.into()));
+            }
+        } else {
+            check_archive_complete(new_set)?;
+        }
+
+        // Files written wrong are fetched again on their own before giving up on them.
+        // Directories and links after a corrupt file were not reached either.
+        let mut extract_special = aborted;
+        for _ in 0..options.max_retries() {
+            if failed_files.is_empty() {
+                break;
+            }
+            let files: Vec<_> = new_patch_mf
+                .new_files
+                .iter()
+                .filter(|file| failed_files.contains_key(&file.path))
+                .cloned()
+                .collect();
+            failed_files = refetch_raw_files(
+                app,
+                http,
+                progress,
+                platform_url,
+                new_install_dir,
+                &new_patch_mf,
+                &files,
+                extract_special,
+                options,
+            )
+            .await?;
+            extract_special = false;
+        }
+        for err in failed_files.into_values() {
+            record_broken(&mut broken_files, err)?;
+        }
+    }
+
+    // A retained previous version must stay intact for rollback.
+    if !options.keep_previous {
+        progress.emit_phase(app, InstallPhase::Cleanup, "Removing old files")?;
+        if let Some(old_install_dir) = old_install_dir.as_ref() {
+            for file in new_patch_mf.stale_files.iter() {
+                tokio::fs::remove_file(&old_install_dir.join(file)).await?;
+            }
+        }
+        for file in files_to_remove.iter() {
+            tokio::fs::remove_file(file).await?;
+        }
+    }
+
+    progress.net.complete();
+    progress.decompress.complete();
+    progress.disk.complete();
+    progress.emit(app)?;
+
+    Ok(broken_files.unwrap_or_default())
+}
+
+/// Records a file that failed verification when `broken_files` collects
+/// them, and fails with `err` otherwise.
+fn record_broken(
+    broken_files: &mut Option<Vec<String>>,
+    err: InstallError,
+) -> Result<(), InstallError> {
+    match (broken_files, &err) {
+        (
+            Some(broken_files),
+            InstallError::WrongSize { path, .. } | InstallError::WrongHash { path, .. },
+        ) => {
+            broken_files.push(path.to_string_lossy().into());
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
+/// Rejects archive paths that are absolute or climb out of the install directory.
+fn check_archive_path(relative_path: &Path) -> Result<(), InstallError> {
+    if is_contained(relative_path) {
+        Ok(())
+    } else {
+        Err(InstallError::InvalidArchivePath(relative_path.into()))
+    }
+}
+
+/// Rejects an executable or working directory outside the install directory,
+/// as the executable would otherwise be made executable and run. Unlike
+/// archive paths, `..` is never allowed.
+///
+/// The check is lexical, so [`crate::launch::launch`] checks the paths again
+/// once symlinks from the archives can be resolved.
+fn check_exe_path(platform_mf: &PlatformManifest) -> Result<(), InstallError> {
+    let exe_path = Path::new(&platform_mf.exe_path);
+    if !is_below(exe_path) || exe_path.file_name().is_none() {
+        return Err(InstallError::InvalidExePath(platform_mf.exe_path.clone()));
+    }
+    if let Some(cwd) = &platform_mf.cwd {
+        if !is_below(Path::new(cwd)) {
+            return Err(InstallError::InvalidCwd(cwd.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` only names directories below its base, without any `..`.
+fn is_below(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Fails with [`InstallError::FileInPlaceOfDir`] if `dir` or one of its
+/// ancestors is a file, which would otherwise surface as a bare IO error
+/// once something is created below it.
+async fn check_dir_path(dir: &Path) -> Result<(), InstallError> {
+    // A trailing slash would make a file at `dir` itself report not being a directory.
+    let dir: PathBuf = dir.components().collect();
+    for path in dir.ancestors() {
+        match tokio::fs::metadata(path).await {
+            Ok(meta) if meta.is_dir() => return Ok(()),
+            Ok(_) => return Err(InstallError::FileInPlaceOfDir(path.into())),
+            // Paths below the file report it as not being a directory.
+            Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Whether a relative path stays within its base directory when resolved lexically.
+fn is_contained(relative_path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in relative_path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent_depth) => depth = parent_depth,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Extracts directory and symlink entries. Links may only point within the install directory.
+async fn extract_special_entry(
+    install_dir: &Path,
+    relative_path: &Path,
+    entry_type: async_tar::EntryType,
+    link_name: Option<PathBuf>,
+) -> Result<(), InstallError> {
+    let dst_path = install_dir.join(relative_path);
+
+    if entry_type.is_dir() {
+        tokio::fs::create_dir_all(&dst_path)
+            .await
+            .map_err(InstallError::CreateDir)?;
+        return Ok(());
+    }
+
+    if !entry_type.is_symlink() {
+        return Err(InstallError::UnsupportedArchiveEntry {
+            path: relative_path.into(),
+            kind: format!("{entry_type:?}"),
+        });
+    }
+
+    let target = link_name.ok_or_else(|| InstallError::InvalidArchivePath(relative_path.into()))?;
+    let link_parent = relative_path.parent().unwrap_or(Path::new(""));
+    if !is_contained(&link_parent.join(&target)) {
+        return Err(InstallError::InvalidArchivePath(relative_path.into()));
+    }
+
+    tokio::fs::create_dir_all(
+        dst_path
+            .parent()
+            .ok_or_else(|| InstallError::InvalidArchivePath(dst_path.clone()))?,
+    )
+    .await
+    .map_err(InstallError::CreateDir)?;
+
+    if tokio::fs::symlink_metadata(&dst_path).await.is_ok() {
+        tokio::fs::remove_file(&dst_path).await?;
+    }
+
+    #[cfg(target_family = "unix")]
+    tokio::fs::symlink(&target, &dst_path).await?;
+    #[cfg(target_family = "windows")]
+    {
+        // Windows links to directories differently, which is only known
+        // if the target was extracted before the link.
+        let resolved_target = install_dir.join(link_parent).join(&target);
+        match tokio::fs::metadata(&resolved_target).await {
+            Ok(metadata) if metadata.is_dir() => {
+                tokio::fs::symlink_dir(&target, &dst_path).await?;
+            }
+            _ => tokio::fs::symlink_file(&target, &dst_path).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails on the first manifest file that had no entry in its archive.
+fn check_archive_complete<V>(remaining: HashMap<&str, V>) -> Result<(), InstallError> {
+    match remaining.into_keys().next() {
+        Some(path) => Err(InstallError::MissingArchiveFile(path.into())),
+        None => Ok(()),
+    }
+}
+
+/// Opens a compressed archive for reading. With a cache and a known archive
+/// hash, the archive is served from (or first downloaded into) the cache.
+/// An archive published as `parts` is read from `{url}.000` onward
+/// as if it were one file.
+#[allow(clippy::too_many_arguments)]
+async fn open_archive<'a>(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    url: Url,
+    parts: &[u64],
+    hash: Option<&[u8; 32]>,
+    cache: Option<&ArchiveCache>,
+    net_counter: &'a atomic::AtomicU64,
+) -> Result<Pin<Box<dyn AsyncBufRead + Send + 'a>>, InstallError> {
+    if let Some(path) = to_local_path(&url)? {
+        if parts.is_empty() {
+            return Ok(Box::pin(BufReader::new(File::open(path).await?)));
+        }
+        let mut reader: Pin<Box<dyn AsyncBufRead + Send>> = Box::pin(tokio::io::empty());
+        for index in 0..parts.len() {
+            let part = File::open(append_extension(&path, &format!("{index:03}"))).await?;
+            reader = Box::pin(reader.chain(BufReader::new(part)));
+        }
+        return Ok(reader);
+    }
+
+    let cache = cache.zip(hash);
+    if let Some((cache, hash)) = cache {
+        if let Some(path) = cache.get(hash).await? {
+            return Ok(Box::pin(BufReader::new(File::open(path).await?)));
+        }
+    }
+
+    let archive_path = PathBuf::from(url.path());
+    let temp_path = match cache {
+        Some((cache, hash)) => cache.temp_path(hash),
+        None => get_temp_archive_path(&url),
+    };
+    if !parts.is_empty() {
+        download_parts(app, http, progress, &url, &temp_path, parts).await?;
+        return open_downloaded(temp_path, archive_path, hash, cache).await;
+    }
+    // A download cut off by an earlier install continues where it stopped,
+    // so it is not downloaded again in ranges.
+    let resume_len = match cache {
+        Some(_) => match tokio::fs::metadata(&temp_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        },
+        None => 0,
+    };
+    let range_split = match resume_len {
+        0 => get_range_split(http, &url).await,
+        _ => None,
+    };
+    if let Some((len, range_count)) = range_split {
+        // Ranges are written out of order, so they must not look like a partial download.
+        let ranges_path = append_extension(&temp_path, "ranges");
+        let net_value = progress.net.value;
+        match download_ranges(app, http, progress, &url, &ranges_path, len, range_count).await {
+            Ok(()) => return open_downloaded(ranges_path, archive_path, hash, cache).await,
+            // Ranges advertised by HEAD are not always honored by GET,
+            // so the archive is streamed whole instead.
+            Err(InstallError::RangeNotHonored(_)) => {
+                progress.net.value = net_value;
+                progress.net.max -= len;
+                tokio::fs::remove_file(&ranges_path).await?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let Some((cache, hash)) = cache else {
+        let response = progress.get_archive(http, url, 0).await?;
+        progress.emit(app)?;
+        let response_stream =
+            stall_guard(response.bytes_stream(), http.stall_timeout()).map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    net_counter.fetch_add(bytes.len() as u64, atomic::Ordering::Relaxed);
+                }
+                chunk
+            });
+        return Ok(Box::pin(StreamReader::new(response_stream)));
+    };
+
+    // The partial download is continued, unless the server sends the whole archive again.
+    let response = match progress.get_archive(http, url.clone(), resume_len).await {
+        // A download can be complete but not yet cached, leaving nothing to fetch.
+        Err(InstallError::Reqwest(err))
+            if resume_len > 0
+                && err.status() == Some(reqwest::StatusCode::RANGE_NOT_SATISFIABLE) =>
+        {
+            if hash_file(&temp_path).await? == *hash {
+                let path = cache.insert(hash, &temp_path).await?;
+                return Ok(Box::pin(BufReader::new(File::open(path).await?)));
+            }
+            progress.get_archive(http, url, 0).await?
+        }
+        result => result?,
+    };
+    progress.emit(app)?;
+
+    let mut actual_hash = Blake3Hash::default();
+    let mut temp_file = match response.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT if resume_len > 0 => {
+            let mut temp_file = File::options()
+                .read(true)
+                .append(true)
+                .open(&temp_path)
+                .await?;
+            let mut read_buf = vec![0u8; 1024 * 64];
+            loop {
+                let read = temp_file.read(&mut read_buf).await?;
+                if read == 0 {
+                    break;
+                }
+                actual_hash.update(&read_buf[..read]);
+            }
+            temp_file
+        }
+        _ => File::create(&temp_path).await?,
+    };
+    let mut emit_throttle = EmitThrottle::new();
+
+    let response_stream = stall_guard(response.bytes_stream(), http.stall_timeout());
+    pin_mut!(response_stream);
+    while let Some(chunk) = response_stream.next().await.transpose()? {
+        actual_hash.update(&chunk);
+        temp_file.write_all(&chunk).await?;
+
+        progress.net.value += chunk.len() as u64;
+        if emit_throttle.ready() {
+            progress.emit(app)?;
+        }
+    }
+    temp_file.flush().await?;
+    drop(temp_file);
+
+    let actual_hash = actual_hash.finish();
+    if hash != &actual_hash {
+        tokio::fs::remove_file(&temp_path).await?;
+        return Err(InstallError::WrongHash {
+            path: archive_path,
+            expected: hex::encode(hash),
+            actual: hex::encode(actual_hash),
+        });
+    }
+
+    let path = cache.insert(hash, &temp_path).await?;
+    Ok(Box::pin(BufReader::new(File::open(path).await?)))
+}
+
+/// A combined patch unpacked into a directory that is removed once dropped.
+struct UnpackedPatch {
+    dir: PathBuf,
+    url: Url,
+}
+
+impl Drop for UnpackedPatch {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Streams the archives out of the [`COMBINED_ARCHIVE_NAME`] of a platform
+/// into a temporary directory in `staging_dir` laid out like a split patch.
+///
+/// Installs stage it in the archive cache or the channel directory, which
+/// unlike the system temporary directory is known to have room for the patch.
+async fn unpack_combined(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    platform_url: &Url,
+    patch_mf: &PatchManifest,
+    staging_dir: &Path,
+) -> Result<UnpackedPatch, InstallError> {
+    progress.emit_phase(
+        app,
+        InstallPhase::FetchingManifests,
+        "Fetching combined patch",
+    )?;
+
+    let url = platform_url.join(COMBINED_ARCHIVE_NAME)?;
+    let dir = staging_dir
+        .join(get_temp_archive_name(&url))
+        .with_extension("unpacked");
+    if tokio::fs::try_exists(&dir).await? {
+        tokio::fs::remove_dir_all(&dir).await?;
+    }
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(InstallError::CreateDir)?;
+    let unpacked = UnpackedPatch {
+        url: Url::from_directory_path(&dir)
+            .map_err(|_| InstallError::InvalidLocalPath(dir.to_string_lossy().into()))?,
+        dir,
+    };
+
+    let net_counter = atomic::AtomicU64::new(0);
+    let reader = open_archive(app, http, progress, url, &[], None, None, &net_counter).await?;
+    let archive = async_tar::Archive::new(reader.compat());
+    let mut entries = archive.entries()?;
+
+    // The manifest at the head was already read by get_patch.
+    let mut remaining = vec![
+        "manifest.json".to_string(),
+        patch_mf.compression.archive_name("diff"),
+        patch_mf.compression.archive_name("raw"),
+    ];
+    let mut read_buf = vec![0u8; 1024 * 64];
+    let mut emit_throttle = EmitThrottle::new();
+    while let Some(mut entry) = entries.next().await.transpose()? {
+        let relative_path: PathBuf = entry.path()?.into_owned().into();
+        let index = relative_path
+            .to_str()
+            .and_then(|name| remaining.iter().position(|known| known == name))
+            .ok_or_else(|| InstallError::UnexpectedArchiveFile(relative_path.clone()))?;
+        let name = remaining.remove(index);
+        if name == "manifest.json" {
+            continue;
+        }
+
+        let mut dst_file = File::create(unpacked.dir.join(&name)).await?;
+        loop {
+            let read = futures::AsyncReadExt::read(&mut entry, read_buf.as_mut()).await?;
+            if read == 0 {
+                break;
+            }
+            dst_file.write_all(&read_buf[..read]).await?;
+
+            if emit_throttle.ready() {
+                progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+                progress.emit(app)?;
+            }
+        }
+        dst_file.flush().await?;
+    }
+    progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+    progress.emit(app)?;
+    Ok(unpacked)
+}
+
+/// Checks an archive downloaded to `temp_path` against `hash` and opens it,
+/// moving it into the cache if there is one.
+async fn open_downloaded(
+    temp_path: PathBuf,
+    archive_path: PathBuf,
+    hash: Option<&[u8; 32]>,
+    cache: Option<(&ArchiveCache, &[u8; 32])>,
+) -> Result<Pin<Box<dyn AsyncBufRead + Send>>, InstallError> {
+    if let Some(hash) = hash {
+        let actual_hash = hash_file(&temp_path).await?;
+        if hash != &actual_hash {
+            tokio::fs::remove_file(&temp_path).await?;
+            return Err(InstallError::WrongHash {
+                path: archive_path,
+                expected: hex::encode(hash),
+                actual: hex::encode(actual_hash),
+            });
+        }
+    }
+    match cache {
+        Some((cache, hash)) => {
+            let path = cache.insert(hash, &temp_path).await?;
+            Ok(Box::pin(BufReader::new(File::open(path).await?)))
+        }
+        None => Ok(Box::pin(TempFileReader::open(temp_path).await?)),
+    }
+}
+
+/// Smallest byte range worth downloading on its own connection.
+const MIN_RANGE_LEN: u64 = 1024 * 1024 * 16;
+
+/// Returns the archive length and how many ranges to download it in,
+/// or `None` if it should be streamed instead.
+async fn get_range_split(http: &HttpClient, url: &Url) -> Option<(u64, usize)> {
+    let parallelism = http.download_parallelism();
+    if parallelism < 2 {
+        return None;
+    }
+
+    let response = http.head(url).await.ok()?;
+    let headers = response.headers();
+    let accepts_bytes = headers
+        .get(reqwest::header::ACCEPT_RANGES)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|unit| unit.trim() == "bytes"));
+    if !accepts_bytes {
+        return None;
+    }
+    let len: u64 = headers
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+
+    let range_count = parallelism.min((len / MIN_RANGE_LEN) as usize);
+    (range_count > 1).then_some((len, range_count))
+}
+
+/// Temporary download location of an archive that is not cached.
+fn get_temp_archive_path(url: &Url) -> PathBuf {
+    std::env::temp_dir().join(get_temp_archive_name(url))
+}
+
+/// File name of a temporary download of the archive at `url`.
+fn get_temp_archive_name(url: &Url) -> String {
+    let url_hash = Blake3Hash::default()
+        .update(url.as_str().as_bytes())
+        .finish();
+    format!("packwisely-{}.part", hex::encode(&url_hash[..16]))
+}
+
+/// Downloads an archive of `len` bytes to `path` as `range_count` byte ranges at once.
+async fn download_ranges(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    url: &Url,
+    path: &Path,
+    len: u64,
+    range_count: usize,
+) -> Result<(), InstallError> {
+    File::create(path).await?.set_len(len).await?;
+
+    progress.archive_url = Some(url.to_string());
+    progress.net.max += len;
+    progress.net.known = true;
+    progress.emit(app)?;
+
+    let net_counter = atomic::AtomicU64::new(0);
+    let range_len = len.div_ceil(range_count as u64);
+    let downloads = futures::future::try_join_all(
+        (0..len)
+            .step_by(range_len as usize)
+            .map(|start| start..=(start + range_len).min(len) - 1)
+            .map(|range| download_range(http, url, path, range, &net_counter)),
+    );
+    track_downloads(app, progress, downloads, &net_counter).await?;
+    Ok(())
+}
+
+/// Downloads the parts of a split archive, whose sizes are `parts`, into their
+/// place in `path`, up to the download parallelism of them at once.
+async fn download_parts(
+    app: &dyn InstallHost,
+    http: &HttpClient,
+    progress: &mut InstallProgress,
+    url: &Url,
+    path: &Path,
+    parts: &[u64],
+) -> Result<(), InstallError> {
+    let len = parts.iter().sum();
+    File::create(path).await?.set_len(len).await?;
+
+    progress.archive_url = Some(url.to_string());
+    progress.net.max += len;
+    progress.net.known = true;
+    progress.emit(app)?;
+
+    let net_counter = atomic::AtomicU64::new(0);
+    let part_starts = parts.iter().scan(0, |start, part_len| {
+        let part_start = *start;
+        *start += part_len;
+        Some(part_start)
+    });
+    // Owned items, as a stream of borrowed ones makes the install future not `Send`.
+    let part_ranges: Vec<_> = parts.iter().copied().zip(part_starts).enumerate().collect();
+    let net_counter_ref = &net_counter;
+    let downloads = futures::stream::iter(part_ranges)
+        .map(|(index, (part_len, part_start))| async move {
+            let part_url = Url::parse(&format!("{url}.{index:03}"))?;
+            let response = http.get(&part_url).await?;
+            write_response_at(
+                http,
+                response,
+                &part_url,
+                path,
+                part_start,
+                part_len,
+                net_counter_ref,
+            )
+            .await
+        })
+        .buffer_unordered(http.download_parallelism().max(1))
+        .try_collect::<()>();
+    track_downloads(app, progress, downloads, &net_counter).await
+}
+
+/// Drives `downloads` to completion, moving the bytes they count in
+/// `net_counter` into the progress as they arrive.
+async fn track_downloads<T>(
+    app: &dyn InstallHost,
+    progress: &mut InstallProgress,
+    downloads: impl Future<Output = Result<T, InstallError>>,
+    net_counter: &atomic::AtomicU64,
+) -> Result<T, InstallError> {
+    pin_mut!(downloads);
+    let result = loop {
+        let tick = Box::pin(tokio::time::sleep(Duration::from_millis(50)));
+        match futures::future::select(downloads.as_mut(), tick).await {
+            Either::Left((result, _)) => break result?,
+            Either::Right(_) => {
+                progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+                progress.emit(app)?;
+            }
+        }
+    };
+    progress.net.value += net_counter.swap(0, atomic::Ordering::Relaxed);
+    progress.emit(app)?;
+    Ok(result)
+}
+
+async fn download_range(
+    http: &HttpClient,
+    url: &Url,
+    path: &Path,
+    range: RangeInclusive<u64>,
+    net_counter: &atomic::AtomicU64,
+) -> Result<(), InstallError> {
+    let response = http.get_range(url, range.clone()).await?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(InstallError::RangeNotHonored(url.to_string()));
+    }
+    let expected = range.end() - range.start() + 1;
+    write_response_at(
+        http,
+        response,
+        url,
+        path,
+        *range.start(),
+        expected,
+        net_counter,
+    )
+    .await
+}
+
+/// Writes the body of `response` into `path` from `start`,
+/// failing unless it is `expected` bytes long.
+async fn write_response_at(
+    http: &HttpClient,
+    response: Response,
+    url: &Url,
+    path: &Path,
+    start: u64,
+    expected: u64,
+    net_counter: &atomic::AtomicU64,
+) -> Result<(), InstallError> {
+    let mut file = File::options().write(true).open(path).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+
+    let mut written = 0;
+    let response_stream = stall_guard(response.bytes_stream(), http.stall_timeout());
+    pin_mut!(response_stream);
+    while let Some(chunk) = response_stream.next().await.transpose()? {
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        net_counter.fetch_add(chunk.len() as u64, atomic::Ordering::Relaxed);
+    }
+    file.flush().await?;
+
+    if written != expected {
+        return Err(InstallError::WrongSize {
+            path: url.path().into(),
+            expected,
+            actual: written,
+        });
+    }
+    Ok(())
+}
+
+/// A delta spilled to disk while it is received, deleted once dropped.
+struct DeltaSpill {
+    file: Option<std::fs::File>,
+    path: PathBuf,
+}
+
+impl DeltaSpill {
+    fn create(path: PathBuf) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Self {
+            file: Some(file),
+            path,
+        })
+    }
+
+    fn file(&mut self) -> &mut std::fs::File {
+        self.file.as_mut().expect("open until dropped")
+    }
+}
+
+impl Drop for DeltaSpill {
+    fn drop(&mut self) {
+        // Close the file first, as open files cannot be removed on Windows.
+        drop(self.file.take());
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads a downloaded archive that is not cached, deleting it once dropped.
+struct TempFileReader {
+    inner: Option<BufReader<File>>,
+    path: PathBuf,
+}
+
+impl TempFileReader {
+    async fn open(path: PathBuf) -> std::io::Result<Self> {
+        Ok(Self {
+            inner: Some(BufReader::new(File::open(&path).await?)),
+            path,
+        })
+    }
+
+    fn inner(self: Pin<&mut Self>) -> Pin<&mut BufReader<File>> {
+        Pin::new(self.get_mut().inner.as_mut().expect("open until dropped"))
+    }
+}
+
+impl Drop for TempFileReader {
+    fn drop(&mut self) {
+        // Close the file first, as open files cannot be removed on Windows.
+        drop(self.inner.take());
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl AsyncRead for TempFileReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.inner().poll_read(cx, buf)
+    }
+}
+
+impl AsyncBufRead for TempFileReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        self.inner().poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        AsyncBufRead::consume(self.inner(), amt)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+struct InstallProgress {
+    /// Compressed bytes downloaded.
+    net: ProgressState,
+    /// Uncompressed bytes coming out of the archive decoder.
+    decompress: ProgressState,
+    /// Bytes written to the install directory.
+    disk: ProgressState,
+    phase: InstallPhase,
+    /// Human-readable description of the current step.
+    message: String,
+    /// Final URL of the archive being downloaded.
+    archive_url: Option<String>,
+    /// Relative path of the file being written or verified.
+    current_file: Option<String>,
+    /// One-based index of [`Self::current_file`].
+    file_index: usize,
+    file_count: usize,
+    /// Duration of the whole operation, set in the final event.
+    elapsed_secs: Option<f64>,
+}
+
+impl InstallProgress {
+    fn emit(&self, app: &dyn InstallHost) -> Result<(), tauri::Error> {
+        app.emit("install-progress", self)
+    }
+
+    fn begin_files(&mut self, file_count: usize) {
+        self.current_file = None;
+        self.file_index = 0;
+        self.file_count = file_count;
+    }
+
+    fn begin_file(&mut self, path: &str) {
+        self.current_file = Some(path.into());
+        self.file_index += 1;
+    }
+
+    /// Emits the final progress event, with the time taken since `started`.
+    fn finish(
+        &mut self,
+        app: &dyn InstallHost,
+        message: &str,
+        started: Instant,
+    ) -> Result<(), tauri::Error> {
+        self.elapsed_secs = Some(started.elapsed().as_secs_f64());
+        self.emit_phase(app, InstallPhase::Done, message)
+    }
+
+    fn emit_phase(
+        &mut self,
+        app: &dyn InstallHost,
+        phase: InstallPhase,
+        message: &str,
+    ) -> Result<(), tauri::Error> {
+        self.phase = phase;
+        self.message = message.into();
+        self.emit(app)
+    }
+
+    /// Starts an archive download, recording the URL it resolved to after redirects.
+    /// Requests the archive at `url` from byte `start` on, which servers
+    /// that ignore ranges answer with the whole archive.
+    async fn get_archive(
+        &mut self,
+        http: &HttpClient,
+        url: Url,
+        start: u64,
+    ) -> Result<Response, InstallError> {
+        let response = match start {
+            0 => http.get(&url).await?,
+            start => http.get_from(&url, start).await?,
+        };
+        self.archive_url = Some(response.url().to_string());
+        self.net.max += response.content_length().unwrap_or(0);
+        self.net.known = true;
+        Ok(response)
+    }
+
+    /// Fetches and parses a JSON document, first checking it against
+    /// `expected_hash` if given. Only the fetch is retried;
+    /// a document that fails to parse is an error right away.
+    async fn get_json<T: DeserializeOwned>(
+        &mut self,
+        http: &HttpClient,
+        url: Url,
+        expected_hash: Option<&[u8; 32]>,
+    ) -> Result<T, InstallError> {
+        let bytes: Bytes = match to_local_path(&url)? {
+            Some(path) => tokio::fs::read(path).await?.into(),
+            None => http.get_bytes(&url).await?,
+        };
+        self.net.add_both(bytes.len() as u64);
+
+        check_document_hash(&url, &bytes, expected_hash)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+fn check_document_hash(
+    url: &Url,
+    bytes: &[u8],
+    expected_hash: Option<&[u8; 32]>,
+) -> Result<(), InstallError> {
+    if let Some(expected_hash) = expected_hash {
+        let actual_hash = Blake3Hash::default().update(bytes).finish();
+        if expected_hash != &actual_hash {
+            return Err(InstallError::WrongHash {
+                path: url.path().into(),
+                expected: hex::encode(expected_hash),
+                actual: hex::encode(actual_hash),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+enum InstallPhase {
+    #[default]
+    FetchingManifests,
+    DownloadingDiff,
+    DownloadingRaw,
+    CopyingSaves,
+    Cleanup,
+    Verifying,
+    Done,
+}
+
+/// Limits how often progress events are emitted from tight loops.
+pub(crate) struct EmitThrottle {
+    last: Instant,
+}
+
+impl EmitThrottle {
+    pub(crate) fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn ready(&mut self) -> bool {
+        let now = Instant::now();
+        if (now - self.last).as_secs_f32() > 0.05 {
+            self.last = now;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ProgressState {
+    value: u64,
+    max: u64,
+    known: bool,
+}
+
+impl Serialize for ProgressState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Targets are partly estimated, so never report past them mid-flight.
+        let value = if self.known {
+            self.value.min(self.max)
+        } else {
+            self.value
+        };
+        let mut state = serializer.serialize_struct("ProgressState", 3)?;
+        state.serialize_field("value", &value)?;
+        state.serialize_field("max", &self.max)?;
+        state.serialize_field("known", &self.known)?;
+        state.end()
+    }
+}
+
+impl ProgressState {
+    /// Snaps the target to what was actually processed once a step is done,
+    /// so it ends at exactly 100% even if the target was off.
+    fn complete(&mut self) {
+        self.max = self.value;
+        self.known = true;
+    }
+
+    fn add(&mut self, value: u64, target: u64) {
+        self.value += value;
+        self.max += target;
+    }
+
+    fn add_both(&mut self, value: u64) {
+        self.add(value, value);
+    }
+
+    /// Adds a target that may be unknown, in which case the state becomes unknown.
+    fn add_max(&mut self, target: Option<u64>) {
+        match target {
+            Some(target) => self.max += target,
+            None => self.known = false,
+        }
+    }
+}
+
+/// Counts the bytes read through it, so progress can be
+/// collected from readers owned by the tar decoder.
+struct CountingReader<'a, R> {
+    inner: R,
+    counter: &'a atomic::AtomicU64,
+}
+
+impl<'a, R> CountingReader<'a, R> {
+    fn new(inner: R, counter: &'a atomic::AtomicU64) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<R: futures::AsyncRead + Unpin> futures::AsyncRead for CountingReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(read)) = &poll {
+            self.counter
+                .fetch_add(*read as u64, atomic::Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/// Marks an error reading a download body, so it can still be told apart
+/// from a disk error after passing through the archive readers.
+#[derive(thiserror::Error, Debug)]
+enum DownloadReadError {
+    #[error("{0}")]
+    Body(#[source] reqwest::Error),
+    #[error("no data received for {} seconds", .0.as_secs())]
+    Stalled(Duration),
+}
+
+/// Marks an error reading the input of a decoder.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+struct SourceReadError(#[source] std::io::Error);
+
+/// Marks an error raised by a decoder itself, from corrupt compressed data.
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+struct DecompressReadError(#[source] std::io::Error);
+
+/// Reads a download body, failing it once no chunk has arrived
+/// for `stall_timeout`.
+fn stall_guard(
+    stream: impl Stream<Item = reqwest::Result<Bytes>>,
+    stall_timeout: Duration,
+) -> impl Stream<Item = std::io::Result<Bytes>> {
+    try_stream! {
+        pin_mut!(stream);
+        loop {
+            match tokio::time::timeout(stall_timeout, stream.next()).await {
+                Ok(Some(chunk)) => {
+                    yield chunk.map_err(|err| std::io::Error::other(DownloadReadError::Body(err)))?;
+                }
+                Ok(None) => break,
+                Err(_) => Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    DownloadReadError::Stalled(stall_timeout),
+                ))?,
+            }
+        }
+    }
+}
+
+/// Whether `E` is anywhere in the chain of errors wrapped by `err`.
+fn io_error_has<E: std::error::Error + 'static>(err: &std::io::Error) -> bool {
+    let mut next = err
+        .get_ref()
+        .map(|inner| inner as &(dyn std::error::Error + 'static));
+    while let Some(error) = next {
+        if error.is::<E>() {
+            return true;
+        }
+        // The source of an `io::Error` skips the error it wraps.
+        next = match error.downcast_ref::<std::io::Error>() {
+            Some(err) => err.get_ref().map(|inner| inner as _),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+/// Decompresses an archive, tagging errors of the decoder itself as
+/// [`DecompressReadError`] and leaving errors of `reader` as they are.
+/// Uncompressed archives are streamed through as they are.
+fn decompress<R: AsyncBufRead + Unpin>(reader: R, patch_mf: &PatchManifest) -> DecompressReader<R> {
+    match patch_mf.compression {
+        ArchiveCompression::Zstd => {
+            let reader = SourceReader { inner: reader };
+            let decoder = match patch_mf.zstd_window_log {
+                Some(window_log) => {
+                    ZstdDecoder::with_params(reader, &[DParameter::window_log_max(window_log)])
+                }
+                None => ZstdDecoder::new(reader),
+            };
+            DecompressReader::Zstd(decoder.compat())
+        }
+        ArchiveCompression::None => DecompressReader::None(reader.compat()),
+    }
+}
+
+struct SourceReader<R> {
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SourceReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner)
+            .poll_read(cx, buf)
+            .map_err(tag_source_error)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for SourceReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_fill_buf(cx)
+            .map_err(tag_source_error)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        AsyncBufRead::consume(Pin::new(&mut self.inner), amt)
+    }
+}
+
+fn tag_source_error(err: std::io::Error) -> std::io::Error {
+    std::io::Error::new(err.kind(), SourceReadError(err))
+}
+
+enum DecompressReader<R> {
+    Zstd(Compat<ZstdDecoder<SourceReader<R>>>),
+    None(Compat<R>),
+}
+
+impl<R: AsyncBufRead + Unpin> futures::AsyncRead for DecompressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match &mut *self {
+            Self::Zstd(inner) => {
+                futures::AsyncRead::poll_read(Pin::new(inner), cx, buf).map_err(|err| {
+                    if io_error_has::<SourceReadError>(&err) {
+                        err
+                    } else {
+                        std::io::Error::new(ErrorKind::InvalidData, DecompressReadError(err))
+                    }
+                })
+            }
+            Self::None(inner) => futures::AsyncRead::poll_read(Pin::new(inner), cx, buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{host::HeadlessHost, test_server::StaticServer};
+
+    fn blake3(contents: &[u8]) -> [u8; 32] {
+        Blake3Hash::default().update(contents).finish()
+    }
+
+    /// Publishes `files` as a version of the `stable` channel in the content
+    /// tree at `root`, shipping every file whole and removing the files of the
+    /// previous version that it drops.
+    async fn publish(root: &Path, version: &str, files: &[(&str, &[u8])]) {
+        let mut raw_tar = async_tar::Builder::new(Vec::new());
+        for (path, contents) in files {
+            let mut header = async_tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            raw_tar
+                .append_data(&mut header, path, *contents)
+                .await
+                .unwrap();
+        }
+        let raw_tar = raw_tar.into_inner().await.unwrap();
+        publish_archive(root, version, files, &raw_tar).await;
+    }
+
+    /// Like [`publish`], but with `raw_tar` as the raw archive of `files`.
+    async fn publish_archive(root: &Path, version: &str, files: &[(&str, &[u8])], raw_tar: &[u8]) {
+        let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+        let channel_dir = root.join("stable");
+        tokio::fs::create_dir_all(&channel_dir).await.unwrap();
+        tokio::fs::write(
+            root.join("channels.json"),
+            r#"[{ "name": "stable", "versionsHash": null }]"#,
+        )
+        .await
+        .unwrap();
+
+        let versions_path = channel_dir.join("versions.json");
+        let mut versions: Vec<serde_json::Value> = match tokio::fs::read(&versions_path).await {
+            Ok(json) => serde_json::from_slice(&json).unwrap(),
+            Err(_) => vec![],
+        };
+        let previous_version = versions
+            .last()
+            .map(|version_mf| version_mf["version"].as_str().unwrap().to_string());
+        let mut stale_files = Vec::new();
+        if let Some(previous_version) = &previous_version {
+            let previous_mf: PatchManifest = serde_json::from_slice(
+                &tokio::fs::read(
+                    channel_dir.join(format!("{previous_version}/{os}/{arch}/manifest.json")),
+                )
+                .await
+                .unwrap(),
+            )
+            .unwrap();
+            stale_files.extend(
+                previous_mf
+                    .new_files
+                    .into_iter()
+                    .map(|file| file.path)
+                    .filter(|path| files.iter().all(|(new_path, _)| new_path != path)),
+            );
+        }
+
+        let new_files: Vec<_> = files
+            .iter()
+            .map(|(path, contents)| FileManifest {
+                path: path.to_string(),
+                len: contents.len() as u64,
+                hash: blake3(contents),
+                hash_algorithm: HashAlgorithm::Blake3,
+                critical: false,
+                block_hashes: vec![],
+            })
+            .collect();
+        let patch_mf: PatchManifest = serde_json::from_value(serde_json::json!({
+            "manifest_version": "V1",
+            "version": version,
+            "previous_version": previous_version,
+            "new_files": new_files,
+            "diff_files": [],
+            "stale_files": stale_files,
+            "compression": "None",
+            "raw_archive_size": raw_tar.len(),
+        }))
+        .unwrap();
+
+        let platform_dir = channel_dir.join(format!("{version}/{os}/{arch}"));
+        tokio::fs::create_dir_all(&platform_dir).await.unwrap();
+        tokio::fs::write(platform_dir.join("raw.tar"), raw_tar)
+            .await
+            .unwrap();
+        tokio::fs::write(
+            platform_dir.join("manifest.json"),
+            serde_json::to_vec(&patch_mf).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        versions.push(serde_json::json!({
+            "version": version,
+            "platforms": [{ "os": os, "arch": arch, "exePath": "game" }],
+        }));
+        tokio::fs::write(&versions_path, serde_json::to_vec(&versions).unwrap())
+            .await
+            .unwrap();
+    }
+
+    /// Turns `path` of a published `version` from a stale file into one
+    /// updated by a delta from `old` to `new`.
+    async fn publish_diff(root: &Path, version: &str, path: &str, old: &[u8], new: &[u8]) {
+        let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+        let platform_dir = root.join(format!("stable/{version}/{os}/{arch}"));
+
+        let options = crate::signature_options(crate::DEFAULT_SIGNATURE_HASH_LEN);
+        let mut sig_buf = Vec::new();
+        fast_rsync::Signature::calculate(&mut &old[..], &mut sig_buf, &options)
+            .await
+            .unwrap();
+        let signature = fast_rsync::Signature::deserialize(&mut sig_buf.as_slice())
+            .await
+            .unwrap();
+        let mut delta = Vec::new();
+        fast_rsync::diff(&signature.index(&sig_buf), new, &mut delta).unwrap();
+
+        let mut diff_tar = async_tar::Builder::new(Vec::new());
+        let mut header = async_tar::Header::new_gnu();
+        header.set_size(delta.len() as u64);
+        header.set_mode(0o644);
+        diff_tar
+            .append_data(&mut header, path, delta.as_slice())
+            .await
+            .unwrap();
+        let diff_tar = diff_tar.into_inner().await.unwrap();
+        tokio::fs::write(platform_dir.join("diff.tar"), &diff_tar)
+            .await
+            .unwrap();
+
+        let manifest_path = platform_dir.join("manifest.json");
+        let mut patch_mf: PatchManifest =
+            serde_json::from_slice(&tokio::fs::read(&manifest_path).await.unwrap()).unwrap();
+        patch_mf.stale_files.retain(|stale| stale != path);
+        patch_mf.diff_files.push(FileManifest {
+            path: path.into(),
+            len: new.len() as u64,
+            hash: blake3(new),
+            hash_algorithm: HashAlgorithm::Blake3,
+            critical: false,
+            block_hashes: vec![],
+        });
+        patch_mf.diff_archive_size = Some(diff_tar.len() as u64);
+        tokio::fs::write(&manifest_path, serde_json::to_vec(&patch_mf).unwrap())
+            .await
+            .unwrap();
+    }
+
+    /// Installs the latest version from `root_url` without a window.
+    async fn install(
+        root_url: &str,
+        install_dir: &Path,
+        options: InstallOptions,
+    ) -> Result<InstalledPlatform, InstallError> {
+        let host = HeadlessHost::new(HashMap::new(), Version::new(1, 0, 0));
+        let options = InstallOptions {
+            root_url: Some(root_url.into()),
+            ..options
+        };
+        let http = HttpClient::new(&options).unwrap();
+        do_install(&host, &http, install_dir.into(), &options).await
+    }
+
+    fn platform(exe_path: &str, cwd: Option<&str>) -> PlatformManifest {
+        serde_json::from_value(serde_json::json!({
+            "os": "linux",
+            "arch": "x86_64",
+            "exePath": exe_path,
+            "cwd": cwd,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn exe_path_stays_in_install_dir() {
+        assert!(check_exe_path(&platform("Game/game.exe", None)).is_ok());
+        assert!(check_exe_path(&platform("./game", Some("Game/Binaries"))).is_ok());
+
+        for exe_path in ["../game", "Game/../../game", "/usr/bin/game", "", "."] {
+            assert!(
+                matches!(
+                    check_exe_path(&platform(exe_path, None)),
+                    Err(InstallError::InvalidExePath(_))
+                ),
+                "{exe_path} was accepted"
+            );
+        }
+        for cwd in ["..", "Game/../..", "/tmp"] {
+            assert!(
+                matches!(
+                    check_exe_path(&platform("game", Some(cwd))),
+                    Err(InstallError::InvalidCwd(_))
+                ),
+                "{cwd} was accepted"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unlisted_files_are_carried_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_dir = dir.path().join("1.0.0");
+        let new_dir = dir.path().join("1.1.0");
+        for path in ["Game/listed.pak", "Game/stale.pak", "Game/protected.ini"] {
+            tokio::fs::create_dir_all(old_dir.join(path).parent().unwrap())
+                .await
+                .unwrap();
+            tokio::fs::write(old_dir.join(path), path).await.unwrap();
+        }
+        tokio::fs::create_dir_all(old_dir.join("PackWisely/Saved/Logs"))
+            .await
+            .unwrap();
+        tokio::fs::write(old_dir.join("PackWisely/Saved/Logs/game.log"), "log")
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(new_dir.join("Game"))
+            .await
+            .unwrap();
+        tokio::fs::write(new_dir.join("Game/listed.pak"), "new")
+            .await
+            .unwrap();
+
+        let patch_mf: PatchManifest = serde_json::from_value(serde_json::json!({
+            "manifest_version": "V1",
+            "version": "1.1.0",
+            "previous_version": "1.0.0",
+            "new_files": [{ "path": "Game/listed.pak", "len": 3, "hash": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=" }],
+            "diff_files": [],
+            "stale_files": ["Game/stale.pak"],
+        }))
+        .unwrap();
+        let options = InstallOptions {
+            keep_previous: true,
+            ..Default::default()
+        };
+        carry_unlisted_files(
+            &old_dir,
+            &new_dir,
+            &platform("game", None),
+            &patch_mf,
+            &options,
+        )
+        .await
+        .unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(new_dir.join(path)).ok();
+        assert_eq!(read("Game/listed.pak").as_deref(), Some("new"));
+        assert_eq!(
+            read("Game/protected.ini").as_deref(),
+            Some("Game/protected.ini")
+        );
+        assert_eq!(read("Game/stale.pak"), None);
+        assert_eq!(read("PackWisely/Saved/Logs/game.log"), None);
+    }
+
+    #[tokio::test]
+    async fn installs_and_updates_from_a_served_tree() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        publish(
+            root.path(),
+            "1.0.0",
+            &[("game", &b"v1"[..]), ("Game/data.pak", &b"data"[..])],
+        )
+        .await;
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+
+        let installed = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(installed.summary.added_files, 2);
+        assert_eq!(
+            std::fs::read(installed.install_dir.join("game")).unwrap(),
+            b"v1"
+        );
+        assert_eq!(
+            std::fs::read(installed.install_dir.join("Game/data.pak")).unwrap(),
+            b"data"
+        );
+
+        publish(root.path(), "1.1.0", &[("game", &b"v2"[..])]).await;
+        let updated = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        assert_ne!(updated.install_dir, installed.install_dir);
+        assert_eq!(
+            std::fs::read(updated.install_dir.join("game")).unwrap(),
+            b"v2"
+        );
+        assert!(!updated.install_dir.join("Game/data.pak").exists());
+    }
+
+    #[tokio::test]
+    async fn newest_version_is_installed_from_a_shuffled_versions_file() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        for (version, contents) in [("1.0.0", b"v1"), ("1.1.0", b"v2"), ("1.2.0", b"v3")] {
+            publish(root.path(), version, &[("game", &contents[..])]).await;
+        }
+        let versions_path = root.path().join("stable/versions.json");
+        let mut versions: Vec<serde_json::Value> =
+            serde_json::from_slice(&std::fs::read(&versions_path).unwrap()).unwrap();
+        // Neither first nor last is the newest.
+        versions.swap(1, 2);
+        assert_eq!(versions[1]["version"], "1.2.0");
+        std::fs::write(&versions_path, serde_json::to_vec(&versions).unwrap()).unwrap();
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+
+        let installed = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        assert!(installed
+            .install_dir
+            .strip_prefix(install_dir.path())
+            .unwrap()
+            .starts_with("stable/1.2.0"));
+        assert_eq!(
+            std::fs::read(installed.install_dir.join("game")).unwrap(),
+            b"v3"
+        );
+    }
+
+    #[tokio::test]
+    async fn forced_reinstall_of_a_diff_based_version_restores_it() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let (old_data, new_data) = ("data-v2".repeat(1000), "data-v3".repeat(1000));
+        publish(
+            root.path(),
+            "1.1.0",
+            &[("game", &b"v2"[..]), ("Game/data.pak", old_data.as_bytes())],
+        )
+        .await;
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        publish(root.path(), "1.2.0", &[("game", &b"v3"[..])]).await;
+        publish_diff(
+            root.path(),
+            "1.2.0",
+            "Game/data.pak",
+            old_data.as_bytes(),
+            new_data.as_bytes(),
+        )
+        .await;
+        let updated = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        std::fs::write(updated.install_dir.join("Game/data.pak"), "damaged").unwrap();
+
+        let options = InstallOptions {
+            force_reinstall: true,
+            ..Default::default()
+        };
+        let repaired = install(&server.root_url(), install_dir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(repaired.install_dir, updated.install_dir);
+        assert_eq!(
+            std::fs::read(repaired.install_dir.join("Game/data.pak")).unwrap(),
+            new_data.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn incomplete_install_is_reinstalled_before_applying_diffs() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let (old_data, new_data) = ("data-v2".repeat(1000), "data-v3".repeat(1000));
+        publish(
+            root.path(),
+            "1.1.0",
+            &[("game", &b"v2"[..]), ("Game/data.pak", old_data.as_bytes())],
+        )
+        .await;
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let installed = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        let save_path = installed
+            .install_dir
+            .join("PackWisely/Saved/SaveGames/Slot1.sav");
+        std::fs::create_dir_all(save_path.parent().unwrap()).unwrap();
+        std::fs::write(&save_path, "save").unwrap();
+        // Left like an install cut off before all files were written.
+        std::fs::remove_file(installed.install_dir.join("Game/data.pak")).unwrap();
+
+        publish(root.path(), "1.2.0", &[("game", &b"v3"[..])]).await;
+        publish_diff(
+            root.path(),
+            "1.2.0",
+            "Game/data.pak",
+            old_data.as_bytes(),
+            new_data.as_bytes(),
+        )
+        .await;
+        let updated = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(updated.summary.changed_files, 1);
+        assert_eq!(
+            std::fs::read(updated.install_dir.join("game")).unwrap(),
+            b"v3"
+        );
+        assert_eq!(
+            std::fs::read(updated.install_dir.join("Game/data.pak")).unwrap(),
+            new_data.as_bytes()
+        );
+        assert_eq!(
+            std::fs::read_to_string(
+                updated
+                    .install_dir
+                    .join("PackWisely/Saved/SaveGames/Slot1.sav")
+            )
+            .unwrap(),
+            "save"
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted_archive_download_is_resumed() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let contents = "game".repeat(64 * 1024);
+        publish(root.path(), "1.0.0", &[("game", contents.as_bytes())]).await;
+
+        let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+        let platform_dir = root.path().join(format!("stable/1.0.0/{os}/{arch}"));
+        let raw_tar = std::fs::read(platform_dir.join("raw.tar")).unwrap();
+        let manifest_path = platform_dir.join("manifest.json");
+        let mut patch_mf: PatchManifest =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        patch_mf.raw_archive_hash = Some(blake3(&raw_tar));
+        std::fs::write(&manifest_path, serde_json::to_vec(&patch_mf).unwrap()).unwrap();
+
+        // Left like a download cut off halfway.
+        let cache = ArchiveCache::open(cache_dir.path().into(), u64::MAX)
+            .await
+            .unwrap();
+        let resume_len = raw_tar.len() / 2;
+        std::fs::write(cache.temp_path(&blake3(&raw_tar)), &raw_tar[..resume_len]).unwrap();
+
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let options = InstallOptions {
+            cache_dir: Some(cache_dir.path().into()),
+            ..Default::default()
+        };
+        let installed = install(&server.root_url(), install_dir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(installed.install_dir.join("game")).unwrap(),
+            contents.as_bytes()
+        );
+        let archive_requests: Vec<_> = server
+            .take_requests()
+            .into_iter()
+            .filter(|request| request.contains("raw.tar"))
+            .collect();
+        assert_eq!(
+            archive_requests,
+            [format!(
+                "GET /stable/1.0.0/{os}/{arch}/raw.tar bytes={resume_len}-"
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn complete_uncached_archive_download_is_not_fetched_again() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let contents = "game".repeat(64 * 1024);
+        publish(root.path(), "1.0.0", &[("game", contents.as_bytes())]).await;
+
+        let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+        let platform_dir = root.path().join(format!("stable/1.0.0/{os}/{arch}"));
+        let raw_tar = std::fs::read(platform_dir.join("raw.tar")).unwrap();
+        let manifest_path = platform_dir.join("manifest.json");
+        let mut patch_mf: PatchManifest =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        patch_mf.raw_archive_hash = Some(blake3(&raw_tar));
+        std::fs::write(&manifest_path, serde_json::to_vec(&patch_mf).unwrap()).unwrap();
+
+        // Left like a download that finished just before it was cached.
+        let cache = ArchiveCache::open(cache_dir.path().into(), u64::MAX)
+            .await
+            .unwrap();
+        let resume_len = raw_tar.len();
+        std::fs::write(cache.temp_path(&blake3(&raw_tar)), &raw_tar).unwrap();
+
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let options = InstallOptions {
+            cache_dir: Some(cache_dir.path().into()),
+            ..Default::default()
+        };
+        let installed = install(&server.root_url(), install_dir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(installed.install_dir.join("game")).unwrap(),
+            contents.as_bytes()
+        );
+        let archive_requests: Vec<_> = server
+            .take_requests()
+            .into_iter()
+            .filter(|request| request.contains("raw.tar"))
+            .collect();
+        assert_eq!(
+            archive_requests,
+            [format!(
+                "GET /stable/1.0.0/{os}/{arch}/raw.tar bytes={resume_len}-"
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_names_leaving_the_install_dir_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        for name in ["..", "../stable", "stable/..", "C:"] {
+            std::fs::write(
+                root.path().join("channels.json"),
+                serde_json::json!([{ "name": name, "versionsHash": null }]).to_string(),
+            )
+            .unwrap();
+            let result = install(&server.root_url(), install_dir.path(), Default::default()).await;
+            assert!(
+                matches!(&result, Err(InstallError::MalformedManifest { reason }) if reason.contains(name)),
+                "{name}: {:?}",
+                result.map(|installed| installed.install_dir)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn file_missing_from_the_archive_fails_the_install() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        publish(root.path(), "1.0.0", &[("game", &b"v1"[..])]).await;
+
+        // The manifest lists a file that the archive was built without.
+        let (os, arch) = (std::env::consts::OS, std::env::consts::ARCH);
+        let manifest_path = root
+            .path()
+            .join(format!("stable/1.0.0/{os}/{arch}/manifest.json"));
+        let mut patch_mf: PatchManifest =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        let mut extra_file = patch_mf.new_files[0].clone();
+        extra_file.path = "Game/extra.pak".into();
+        patch_mf.new_files.push(extra_file);
+        std::fs::write(&manifest_path, serde_json::to_vec(&patch_mf).unwrap()).unwrap();
+
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let result = install(&server.root_url(), install_dir.path(), Default::default()).await;
+        assert!(
+            matches!(&result, Err(InstallError::MissingArchiveFile(path)) if path == Path::new("Game/extra.pak")),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    fn tar_entry(
+        path: &str,
+        entry_type: async_tar::EntryType,
+        link_name: Option<&str>,
+        contents: &[u8],
+    ) -> (async_tar::Header, String, Vec<u8>) {
+        let mut header = async_tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o755);
+        if let Some(link_name) = link_name {
+            header.set_link_name(link_name).unwrap();
+        }
+        (header, path.into(), contents.into())
+    }
+
+    async fn build_tar(entries: Vec<(async_tar::Header, String, Vec<u8>)>) -> Vec<u8> {
+        let mut tar = async_tar::Builder::new(Vec::new());
+        for (mut header, path, contents) in entries {
+            tar.append_data(&mut header, path, &contents[..])
+                .await
+                .unwrap();
+        }
+        tar.into_inner().await.unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn archives_with_mixed_entry_types_are_extracted() {
+        use async_tar::EntryType;
+
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        let raw_tar = build_tar(vec![
+            tar_entry("Game", EntryType::Directory, None, b""),
+            tar_entry("Game/data.pak", EntryType::Regular, None, b"data"),
+            tar_entry("Game/link.pak", EntryType::Symlink, Some("data.pak"), b""),
+            tar_entry("Content", EntryType::Symlink, Some("Game"), b""),
+            tar_entry("game", EntryType::Regular, None, b"v1"),
+        ])
+        .await;
+        publish_archive(
+            root.path(),
+            "1.0.0",
+            &[("Game/data.pak", &b"data"[..]), ("game", &b"v1"[..])],
+            &raw_tar,
+        )
+        .await;
+
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let installed = install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        let version_dir = &installed.install_dir;
+        assert!(version_dir.join("Game").is_dir());
+        assert_eq!(
+            std::fs::read(version_dir.join("Game/link.pak")).unwrap(),
+            b"data"
+        );
+        assert_eq!(
+            std::fs::read(version_dir.join("Content/data.pak")).unwrap(),
+            b"data"
+        );
+        assert!(std::fs::symlink_metadata(version_dir.join("Content"))
+            .unwrap()
+            .is_symlink());
+
+        // Anything but files, directories and links is refused.
+        let raw_tar = build_tar(vec![
+            tar_entry("pipe", EntryType::Fifo, None, b""),
+            tar_entry("game", EntryType::Regular, None, b"v2"),
+        ])
+        .await;
+        publish_archive(root.path(), "1.1.0", &[("game", &b"v2"[..])], &raw_tar).await;
+        let result = install(&server.root_url(), install_dir.path(), Default::default()).await;
+        assert!(
+            matches!(result, Err(InstallError::UnsupportedArchiveEntry { .. })),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn file_in_place_of_the_channel_dir_is_reported() {
+        let install_dir = tempfile::tempdir().unwrap();
+        let channel_path = install_dir.path().join("stable");
+        std::fs::write(&channel_path, "not a directory").unwrap();
+
+        for dir in [
+            install_dir.path().join("stable/"),
+            install_dir.path().join("stable/1.0.0/linux-x86_64"),
+        ] {
+            assert!(
+                matches!(
+                    check_dir_path(&dir).await,
+                    Err(InstallError::FileInPlaceOfDir(path)) if path == channel_path
+                ),
+                "{dir:?} was accepted"
+            );
+        }
+        assert!(check_dir_path(&install_dir.path().join("beta/"))
+            .await
+            .is_ok());
+
+        let root = tempfile::tempdir().unwrap();
+        publish(root.path(), "1.0.0", &[("game", &b"v1"[..])]).await;
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        let result = install(&server.root_url(), install_dir.path(), Default::default()).await;
+        assert!(
+            matches!(&result, Err(InstallError::FileInPlaceOfDir(path)) if path == &channel_path),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    /// Every file below `dir` with its contents and modification time.
+    fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>, std::time::SystemTime)> {
+        let mut files = Vec::new();
+        let mut to_visit = vec![dir.to_path_buf()];
+        while let Some(dir) = to_visit.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                let metadata = std::fs::symlink_metadata(&path).unwrap();
+                if metadata.is_dir() {
+                    to_visit.push(path);
+                } else {
+                    files.push((
+                        path.clone(),
+                        std::fs::read(&path).unwrap(),
+                        metadata.modified().unwrap(),
+                    ));
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn installing_twice_leaves_the_install_alone() {
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = tempfile::tempdir().unwrap();
+        publish(
+            root.path(),
+            "1.0.0",
+            &[("game", &b"v1"[..]), ("Game/data.pak", &b"data"[..])],
+        )
+        .await;
+        let server = StaticServer::serve(root.path().into()).await.unwrap();
+        install(&server.root_url(), install_dir.path(), Default::default())
+            .await
+            .unwrap();
+        let before = snapshot(install_dir.path());
+        server.take_requests();
+
+        let options = InstallOptions {
+            verify_installed: true,
+            ..Default::default()
+        };
+        let installed = install(&server.root_url(), install_dir.path(), options)
+            .await
+            .unwrap();
+        assert_eq!(installed.summary.written_bytes, 0);
+        let archive_requests: Vec<_> = server
+            .take_requests()
+            .into_iter()
+            .filter(|request| !request.ends_with(".json"))
+            .collect();
+        assert!(archive_requests.is_empty(), "{archive_requests:?}");
+        assert_eq!(snapshot(install_dir.path()), before);
     }
 }
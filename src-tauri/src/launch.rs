@@ -0,0 +1,186 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    install::PlatformManifest,
+    wine_util::{get_wine_path, WineError},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum LaunchError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("failed to find Wine: {0}")]
+    Wine(#[from] WineError),
+    #[error("{0} resolves outside the install directory")]
+    OutsideInstallDir(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(crate) struct LaunchOptions {
+    /// Run the game in a process group of its own with its output discarded,
+    /// so it keeps running after the launcher exits.
+    pub(crate) detach: bool,
+    /// Spawn without hashing the executable and critical files first.
+    pub(crate) skip_verify: bool,
+}
+
+/// Other spellings of the names in [`std::env::consts::OS`], as used by build pipelines.
+const OS_ALIASES: [(&str, &str); 6] = [
+    ("darwin", "macos"),
+    ("osx", "macos"),
+    ("mac", "macos"),
+    ("win", "windows"),
+    ("win32", "windows"),
+    ("win64", "windows"),
+];
+
+/// Other spellings of the names in [`std::env::consts::ARCH`].
+const ARCH_ALIASES: [(&str, &str); 6] = [
+    ("amd64", "x86_64"),
+    ("x64", "x86_64"),
+    ("x86-64", "x86_64"),
+    ("arm64", "aarch64"),
+    ("i386", "x86"),
+    ("i686", "x86"),
+];
+
+fn normalize<'a>(name: &'a str, aliases: &[(&str, &'static str)]) -> &'a str {
+    aliases
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+        .map_or(name, |(_, canonical)| canonical)
+}
+
+/// Whether the platform is built for `os`, as named by [`std::env::consts::OS`].
+pub(crate) fn is_os(platform_mf: &PlatformManifest, os: &str) -> bool {
+    normalize(&platform_mf.os, &OS_ALIASES).eq_ignore_ascii_case(os)
+}
+
+/// Whether the platform is built for `arch`, as named by [`std::env::consts::ARCH`].
+pub(crate) fn is_arch(platform_mf: &PlatformManifest, arch: &str) -> bool {
+    normalize(&platform_mf.arch, &ARCH_ALIASES).eq_ignore_ascii_case(arch)
+}
+
+/// Whether the platform has to be run through Wine on this host.
+pub(crate) fn needs_wine(platform_mf: &PlatformManifest) -> bool {
+    is_os(platform_mf, "windows") && std::env::consts::OS != "windows"
+}
+
+/// Fails unless `path` below `install_dir` still is once symlinks are
+/// resolved. Archives may contain links whose targets only pass the lexical
+/// checks on their own, like `a -> .` followed by a link below `a/`.
+async fn check_contained(install_dir: &Path, path: &Path) -> Result<(), LaunchError> {
+    let install_dir = tokio::fs::canonicalize(install_dir).await?;
+    let resolved = tokio::fs::canonicalize(install_dir.join(path)).await?;
+    if resolved.starts_with(&install_dir) {
+        Ok(())
+    } else {
+        Err(LaunchError::OutsideInstallDir(path.into()))
+    }
+}
+
+pub(crate) async fn launch(
+    install_dir: &Path,
+    platform_mf: &PlatformManifest,
+    options: &LaunchOptions,
+) -> Result<Child, LaunchError> {
+    check_contained(install_dir, Path::new(&platform_mf.exe_path)).await?;
+    if let Some(cwd) = &platform_mf.cwd {
+        check_contained(install_dir, Path::new(cwd)).await?;
+    }
+    let exe_path = install_dir.join(&platform_mf.exe_path);
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        tokio::fs::set_permissions(&exe_path, std::fs::Permissions::from_mode(0o770)).await?;
+    }
+
+    let mut command = if needs_wine(platform_mf) {
+        let mut command = Command::new(get_wine_path()?.trim());
+        command.arg(&exe_path);
+        command
+    } else {
+        Command::new(&exe_path)
+    };
+
+    // Manifest variables are merged on top of the inherited environment,
+    // so anything Wine relies on (WINEPREFIX etc.) is kept unless overridden.
+    command.args(&platform_mf.args).envs(&platform_mf.env);
+
+    if options.detach {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::CommandExt;
+
+            command.process_group(0);
+        }
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+
+            const DETACHED_PROCESS: u32 = 0x00000008;
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+            command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+        }
+    } else {
+        command.stdout(Stdio::inherit());
+    }
+
+    if let Some(cwd) = &platform_mf.cwd {
+        command.current_dir(install_dir.join(cwd));
+    }
+
+    Ok(command.spawn()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn symlinks_out_of_install_dir_are_rejected() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir().unwrap();
+        let install_dir = root.path().join("install");
+        let outside_dir = root.path().join("outside");
+        std::fs::create_dir_all(install_dir.join("Game")).unwrap();
+        std::fs::create_dir_all(&outside_dir).unwrap();
+        std::fs::write(install_dir.join("Game/game"), "").unwrap();
+        std::fs::write(outside_dir.join("game"), "").unwrap();
+
+        // Each link passes the lexical checks on its own: `a` points at its
+        // own directory, so `a/..` seems to stay inside but does not.
+        symlink(".", install_dir.join("a")).unwrap();
+        symlink("../outside", install_dir.join("a/b")).unwrap();
+
+        check_contained(&install_dir, Path::new("Game/game"))
+            .await
+            .unwrap();
+        check_contained(&install_dir, Path::new("Game"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            check_contained(&install_dir, Path::new("a/b/game")).await,
+            Err(LaunchError::OutsideInstallDir(_))
+        ));
+        assert!(matches!(
+            check_contained(&install_dir, Path::new("b")).await,
+            Err(LaunchError::OutsideInstallDir(_))
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use semver::Version;
+
+use crate::install::PlatformManifest;
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid install layout \"{template}\": {reason}")]
+pub(crate) struct LayoutError {
+    template: String,
+    reason: String,
+}
+
+const PLACEHOLDERS: [&str; 4] = ["channel", "version", "os", "arch"];
+
+/// Directory layout of installed platforms below the install root, as a
+/// `/`-separated template with `{channel}`, `{version}`, `{os}` and `{arch}`.
+///
+/// `{version}` must be a path segment of its own, so the versions of a
+/// channel can be listed and pruned from the directory above it, and
+/// `{channel}` must appear so that channels are kept apart.
+#[derive(Debug, Clone)]
+pub(crate) struct InstallLayout {
+    template: String,
+}
+
+impl Default for InstallLayout {
+    fn default() -> Self {
+        Self {
+            template: Self::DEFAULT.into(),
+        }
+    }
+}
+
+impl InstallLayout {
+    const DEFAULT: &'static str = "{channel}/{version}/{os}-{arch}";
+
+    pub(crate) fn parse(template: &str) -> Result<Self, LayoutError> {
+        let invalid = |reason: String| LayoutError {
+            template: template.into(),
+            reason,
+        };
+
+        if template.starts_with('/') || template.contains(['\\', ':']) {
+            return Err(invalid("must be a relative path separated by \"/\"".into()));
+        }
+
+        let mut version_segments = 0;
+        for segment in template.split('/') {
+            if segment.is_empty() || segment == "." || segment == ".." {
+                return Err(invalid(
+                    "segments must not be empty, \".\" or \"..\"".into(),
+                ));
+            }
+
+            let mut rest = segment;
+            while let Some(start) = rest.find(['{', '}']) {
+                if rest[start..].starts_with('}') {
+                    return Err(invalid("unmatched \"}\"".into()));
+                }
+                let end = start
+                    + rest[start..]
+                        .find('}')
+                        .ok_or_else(|| invalid("unmatched \"{\"".into()))?;
+                let name = &rest[start + 1..end];
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(invalid(format!("unknown placeholder {{{name}}}")));
+                }
+                rest = &rest[end + 1..];
+            }
+
+            if segment.contains("{version}") {
+                if segment != "{version}" {
+                    return Err(invalid("{version} must be a whole path segment".into()));
+                }
+                version_segments += 1;
+            }
+        }
+        if version_segments != 1 {
+            return Err(invalid("must contain {version} exactly once".into()));
+        }
+        // Channels would otherwise install over each other.
+        if !template.contains("{channel}") {
+            return Err(invalid("must contain {channel}".into()));
+        }
+
+        Ok(Self {
+            template: template.into(),
+        })
+    }
+
+    /// Install directory of a platform, relative to the install root.
+    pub(crate) fn render(
+        &self,
+        channel: &str,
+        version: &Version,
+        platform_mf: &PlatformManifest,
+    ) -> PathBuf {
+        render_part(&self.template, channel, version, platform_mf).into()
+    }
+
+    /// Directory containing one entry per installed version of a channel,
+    /// relative to the install root.
+    pub(crate) fn versions_dir(&self, channel: &str, platform_mf: &PlatformManifest) -> PathBuf {
+        let prefix_len = self
+            .template
+            .find("{version}")
+            .expect("layout contains {version}");
+        let prefix = &self.template[..prefix_len];
+        // The version is not part of the prefix, so any value renders the same.
+        render_part(prefix, channel, &Version::new(0, 0, 0), platform_mf).into()
+    }
+}
+
+fn render_part(
+    template: &str,
+    channel: &str,
+    version: &Version,
+    platform_mf: &PlatformManifest,
+) -> String {
+    let version = version.to_string();
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').expect("layout braces are balanced");
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(match &rest[start + 1..end] {
+            "channel" => channel,
+            "version" => &version,
+            "os" => &platform_mf.os,
+            "arch" => &platform_mf.arch,
+            name => unreachable!("unknown placeholder {name}"),
+        });
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_without_a_channel_are_rejected() {
+        InstallLayout::parse(InstallLayout::DEFAULT).unwrap();
+        InstallLayout::parse("{channel}-{os}/{version}").unwrap();
+        for template in ["{version}/{os}-{arch}", "game/{version}"] {
+            let err = InstallLayout::parse(template).unwrap_err();
+            assert!(err.to_string().contains("must contain {channel}"), "{err}");
+        }
+    }
+}
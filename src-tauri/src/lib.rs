@@ -1,28 +1,67 @@
+mod cache;
+mod delta_check;
 mod file_util;
+mod host;
+mod http_util;
 mod install;
+mod launch;
+mod layout;
+mod patch_journal;
+#[cfg(feature = "rsync-commands")]
+mod rsync_commands;
+mod telemetry;
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
 mod wine_util;
 
-use std::{collections::HashSet, fmt::Display, path::PathBuf, process::Stdio, sync::Mutex};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Child,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Instant,
+};
 
 use async_compat::{Compat, CompatExt};
+use async_compression::{
+    tokio::{bufread::ZstdDecoder, write::ZstdEncoder},
+    zstd::{CParameter, DParameter},
+    Level,
+};
 use fast_rsync::{
     sum_hash::{Blake3Hash, SumHash},
     SignatureOptions,
 };
-use futures::{pin_mut, AsyncReadExt, StreamExt};
-use install::do_install;
+use file_util::{BlockHasher, FileHasher};
+use futures::{channel::oneshot, future::Either, pin_mut, AsyncReadExt, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use host::{HeadlessHost, ProgressSink};
+use http_util::HttpClient;
+use install::{
+    do_check_update, do_cleanup_storage, do_get_install_size, do_heal, do_install,
+    do_preflight_install, do_preview_removals, do_preview_update, do_rollback, do_trace_file,
+    do_validate_release, verify_launch_files, EmitThrottle, FileRevision, HealSummary,
+    InstallOptions, InstallSize, InstallSummary, InstalledPlatform, PreflightReport, ReleaseReport,
+    RemovalPreview, StorageCleanup, UpdateCheck, UpdatePreview,
+};
+use launch::LaunchOptions;
+use patch_journal::{hash_inputs, open_tar_at, JournalArchive, PatchJournal};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use serde_with::base64::Base64;
 use serde_with::serde_as;
 use tauri::{AppHandle, Emitter, Listener, Manager};
-use tauri_plugin_http::reqwest;
-use tauri_plugin_updater::UpdaterExt;
+use tauri_plugin_updater::{Update, UpdaterExt};
 use tokio::{
     fs::File,
-    io::{AsyncReadExt as OtherAsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    io::{AsyncBufRead, AsyncReadExt as OtherAsyncReadExt, AsyncWriteExt, BufReader, ReadBuf},
+    task::JoinSet,
 };
-use tokio_util::bytes::BytesMut;
+use tokio_util::{bytes::Bytes, io::StreamReader};
+use tracing_subscriber::EnvFilter;
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -31,32 +70,395 @@ fn get_update_check_status(app: AppHandle) -> (bool, String) {
 }
 
 #[tauri::command]
-async fn install(app: AppHandle) -> Result<(), String> {
-    let http_client = reqwest::Client::builder()
-        .build()
-        .map_err(|err| err.to_string())?;
+async fn apply_self_update(app: AppHandle) -> Result<(), String> {
+    let state = app.state::<UpdateCheckerState>();
+    let update = state
+        .pending
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or("no update available")?;
+
+    // Only a cancelled or failed update returns here, so it can be applied again,
+    // unless a newer one was found in the meantime.
+    let result = apply_update(&app, update.clone()).await;
+    state.pending.lock().unwrap().get_or_insert(update);
+    result.map_err(|err| err.to_string())
+}
+
+/// Aborts the self-update download started by `apply_self_update`,
+/// which then returns without installing anything.
+#[tauri::command]
+fn cancel_self_update(app: AppHandle) -> Result<(), String> {
+    let state = app.state::<UpdateCheckerState>();
+    let cancel = state
+        .cancel_download
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or("no launcher update is downloading")?;
+    // The download may have finished in the meantime, which is not an error.
+    _ = cancel.send(());
+    Ok(())
+}
 
-    let install_dir = dirs::data_local_dir().ok_or("missing install dir")?;
+/// Installs or updates the game files only, never spawning the game,
+/// so it can provision an install directory headlessly.
+#[tauri::command]
+async fn install(
+    app: AppHandle,
+    options: Option<InstallOptions>,
+) -> Result<InstallSummary, String> {
+    let options = options.unwrap_or_default();
+    let installed = install_files(&app, &options).await?;
+    Ok(installed.summary)
+}
 
-    let exe_path = do_install(&app, &http_client, install_dir.join("PackWisely"))
+/// Installs or updates the game, then spawns it and returns its PID.
+#[tauri::command]
+async fn play(
+    app: AppHandle,
+    options: Option<InstallOptions>,
+    launch_options: Option<LaunchOptions>,
+) -> Result<u32, String> {
+    let options = options.unwrap_or_default();
+    let installed = install_files(&app, &options).await?;
+
+    let launch_options = launch_options.unwrap_or_default();
+    if !launch_options.skip_verify {
+        verify_launch_files(&installed.install_dir, &installed.platform)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    let child = launch::launch(&installed.install_dir, &installed.platform, &launch_options)
         .await
         .map_err(|err| err.to_string())?;
 
-    #[cfg(target_family = "unix")]
-    {
-        use std::os::unix::fs::PermissionsExt;
+    Ok(watch_process(&app, child))
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ProcessExit {
+    pid: u32,
+    /// Exit code, or `None` if the process was killed by a signal.
+    code: Option<i32>,
+}
+
+/// Emits `process-exit` once the game exits, so the frontend can track
+/// whether it is running, and returns its PID.
+fn watch_process(app: &AppHandle, mut child: Child) -> u32 {
+    let pid = child.id();
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let code = match child.wait() {
+            Ok(status) => status.code(),
+            Err(err) => {
+                tracing::warn!(pid, "failed to wait for game process: {err}");
+                None
+            }
+        };
+        if let Err(err) = app.emit("process-exit", ProcessExit { pid, code }) {
+            tracing::warn!(pid, "failed to emit process exit: {err}");
+        }
+    });
+    pid
+}
+
+async fn install_files(
+    app: &AppHandle,
+    options: &InstallOptions,
+) -> Result<InstalledPlatform, String> {
+    let http_client = HttpClient::new(options).map_err(|err| err.to_string())?;
+
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
 
-        tokio::fs::set_permissions(&exe_path, std::fs::Permissions::from_mode(0o770))
+    do_install(app, &http_client, install_dir, options)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn rollback(
+    app: AppHandle,
+    channel: String,
+    launch_options: Option<LaunchOptions>,
+) -> Result<u32, String> {
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    let installed = do_rollback(&app, install_dir, &channel)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let launch_options = launch_options.unwrap_or_default();
+    if !launch_options.skip_verify {
+        verify_launch_files(&installed.install_dir, &installed.platform)
             .await
             .map_err(|err| err.to_string())?;
     }
+    let child = launch::launch(&installed.install_dir, &installed.platform, &launch_options)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(watch_process(&app, child))
+}
+
+/// Verifies the installed version of a channel and re-fetches its broken files,
+/// which is much cheaper than reinstalling it.
+#[tauri::command]
+async fn heal(
+    app: AppHandle,
+    channel: String,
+    options: Option<InstallOptions>,
+) -> Result<HealSummary, String> {
+    let options = options.unwrap_or_default();
 
-    std::process::Command::new(exe_path)
-        .stdout(Stdio::inherit())
-        .spawn()
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_heal(&app, &http_client, install_dir, &channel, &options)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("no install directory: local data and data directories are unavailable, and the executable directory could not be found: {0}")]
+struct MissingInstallDir(std::io::Error);
+
+/// Picks the first available of the local data directory, the data directory,
+/// and the directory containing the executable.
+fn get_install_dir() -> Result<PathBuf, MissingInstallDir> {
+    let base_dir = match dirs::data_local_dir().or_else(dirs::data_dir) {
+        Some(dir) => dir,
+        None => {
+            let exe_path = std::env::current_exe().map_err(MissingInstallDir)?;
+            exe_path
+                .parent()
+                .ok_or_else(|| {
+                    MissingInstallDir(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "executable has no parent directory",
+                    ))
+                })?
+                .to_path_buf()
+        }
+    };
+    Ok(base_dir.join("PackWisely"))
+}
+
+#[tauri::command]
+async fn preview_update(
+    app: AppHandle,
+    channel: Option<String>,
+    version: Option<String>,
+    options: Option<InstallOptions>,
+) -> Result<UpdatePreview, String> {
+    let options = options.unwrap_or_default();
+    let version = version
+        .map(|version| Version::parse(&version))
+        .transpose()
         .map_err(|err| err.to_string())?;
 
-    Ok(())
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    do_preview_update(
+        &app,
+        &http_client,
+        channel.as_deref(),
+        version.as_ref(),
+        &options,
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn preview_removals(
+    app: AppHandle,
+    channel: Option<String>,
+    version: Option<String>,
+    options: Option<InstallOptions>,
+) -> Result<RemovalPreview, String> {
+    let options = options.unwrap_or_default();
+    let version = version
+        .map(|version| Version::parse(&version))
+        .transpose()
+        .map_err(|err| err.to_string())?;
+
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_preview_removals(
+        &app,
+        &http_client,
+        install_dir,
+        channel.as_deref(),
+        version.as_ref(),
+        &options,
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn preflight_install(
+    app: AppHandle,
+    channel: Option<String>,
+    version: Option<String>,
+    options: Option<InstallOptions>,
+) -> Result<PreflightReport, String> {
+    let options = options.unwrap_or_default();
+    let version = version
+        .map(|version| Version::parse(&version))
+        .transpose()
+        .map_err(|err| err.to_string())?;
+
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_preflight_install(
+        &app,
+        &http_client,
+        install_dir,
+        channel.as_deref(),
+        version.as_ref(),
+        &options,
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn check_update(
+    app: AppHandle,
+    channel: Option<String>,
+    options: Option<InstallOptions>,
+) -> Result<UpdateCheck, String> {
+    let options = options.unwrap_or_default();
+
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_check_update(
+        &app,
+        &http_client,
+        install_dir,
+        channel.as_deref(),
+        &options,
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn get_install_size(app: AppHandle, channel: Option<String>) -> Result<InstallSize, String> {
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_get_install_size(&app, install_dir, channel.as_deref())
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn trace_file(
+    app: AppHandle,
+    channel: Option<String>,
+    path: String,
+    options: Option<InstallOptions>,
+) -> Result<Vec<FileRevision>, String> {
+    let options = options.unwrap_or_default();
+
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    do_trace_file(&app, &http_client, channel.as_deref(), &path, &options)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Reports data that can be removed to free disk space, and removes it only if `confirm` is set.
+#[tauri::command]
+async fn cleanup_storage(
+    app: AppHandle,
+    options: Option<InstallOptions>,
+    confirm: bool,
+) -> Result<StorageCleanup, String> {
+    let options = options.unwrap_or_default();
+    let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+
+    do_cleanup_storage(&app, install_dir, &options, confirm)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn validate_release(
+    app: AppHandle,
+    options: Option<InstallOptions>,
+) -> Result<ReleaseReport, String> {
+    let options = options.unwrap_or_default();
+
+    let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+
+    do_validate_release(&app, &http_client, &options)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Options of [`create_patch`], all of which default to a plain patch.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct CreatePatchOptions {
+    hash_algorithm: HashAlgorithm,
+    /// Globs of files that are never diffed against the old directory.
+    protected: Vec<String>,
+    min_launcher_version: Option<String>,
+    compression: ArchiveCompression,
+    /// Number of directories listed at once.
+    walk_concurrency: Option<usize>,
+    /// Pack the patch into a single file once it is built.
+    combined: bool,
+    /// Split archives into parts of at most this many bytes.
+    part_size: Option<u64>,
+    /// Read the built archives back and check them against the manifest.
+    verify_output: bool,
+    /// Globs of files that are marked [`FileManifest::critical`].
+    critical: Vec<String>,
+    signature_hash_len: Option<u32>,
+    /// Files of at least this size get [`FileManifest::block_hashes`].
+    block_hash_threshold: Option<u64>,
+    /// zstd level, where 0 is the default, as the frontend sends for a level left blank.
+    compression_level: Option<i32>,
+    /// Number of files signed at once, where 0 is one per available core.
+    sign_concurrency: Option<usize>,
+    /// Enables long-distance matching with this window log.
+    long_window_log: Option<u32>,
+}
+
+impl CreatePatchOptions {
+    fn walk_concurrency(&self) -> usize {
+        self.walk_concurrency.unwrap_or(1).max(1)
+    }
+
+    fn signature_hash_len(&self) -> u32 {
+        self.signature_hash_len
+            .unwrap_or(DEFAULT_SIGNATURE_HASH_LEN)
+    }
+
+    fn compression_level(&self) -> i32 {
+        self.compression_level
+            .filter(|&level| level != 0)
+            .unwrap_or(DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    fn sign_concurrency(&self) -> usize {
+        self.sign_concurrency
+            .filter(|&concurrency| concurrency != 0)
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+            })
+    }
 }
 
 #[tauri::command]
@@ -66,13 +468,16 @@ async fn create_patch(
     new_dir: String,
     old_dir: String,
     version: String,
+    options: Option<CreatePatchOptions>,
 ) -> Result<CreatePatchResult, String> {
+    let options = options.unwrap_or_default();
     let result = do_create_patch(
         app,
         out_dir.into(),
         new_dir.into(),
         (!old_dir.is_empty()).then(|| old_dir.into()),
         version,
+        &options,
     )
     .await
     .map_err(|err| err.to_string())?;
@@ -80,26 +485,117 @@ async fn create_patch(
     Ok(result)
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[tauri::command]
+async fn recompress(
+    app: AppHandle,
+    out_dir: String,
+    level: i32,
+    long_window_log: Option<u32>,
+) -> Result<CreatePatchResult, String> {
+    do_recompress(&app, out_dir.into(), level, long_window_log)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn pack_patch(out_dir: String) -> Result<u64, String> {
+    do_pack_patch(Path::new(&out_dir))
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 struct CreatePatchProgress {
     done_files: usize,
     total_files: usize,
+    /// Bytes of the files done so far, excluding [`Self::file_done_bytes`].
+    done_bytes: u64,
+    total_bytes: u64,
+    /// File most recently started.
     path: String,
+    /// Bytes read from the files still being signed, for progress within large files.
+    file_done_bytes: u64,
+    /// Size of [`Self::path`].
+    file_total_bytes: u64,
+    /// Duration of the whole patch creation, set in the final event.
+    elapsed_secs: Option<f64>,
 }
 
+#[serde_as]
 #[derive(Debug, Clone, Serialize)]
 struct CreatePatchResult {
     manifest: PatchManifest,
     patch_size: u64,
+    /// What `patch_size` would be with the archives left as tars, whose
+    /// headers and padding are counted too, to judge the compression ratio.
+    tar_size: u64,
+    /// Hash of the written `manifest.json`, for `manifest_hash` in versions.json.
+    /// Changes whenever the patch is recompressed, so a pinned hash must follow it.
+    #[serde_as(as = "Base64")]
+    manifest_hash: [u8; 32],
+    /// Likewise for `manifest-delta.json`, if the patch has one.
+    #[serde_as(as = "Option<Base64>")]
+    manifest_delta_hash: Option<[u8; 32]>,
 }
 
 #[serde_as]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct FileManifest {
     path: String,
     len: u64,
     #[serde_as(as = "Base64")]
     hash: [u8; 32],
+    #[serde(default, skip_serializing_if = "HashAlgorithm::is_default")]
+    hash_algorithm: HashAlgorithm,
+    /// Hashed before every launch along with the executable, for files the
+    /// game cannot start without.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    critical: bool,
+    /// Blake3 hashes of every [`PatchManifest::hash_block_len`] bytes, kept
+    /// for large files so installs notice a corrupt download before its end.
+    /// Blocks are always hashed with Blake3, whatever `hash_algorithm` is.
+    #[serde_as(as = "Vec<Base64>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    block_hashes: Vec<[u8; 32]>,
+}
+
+/// Algorithm of [`FileManifest::hash`]. Files of manifests that
+/// predate the choice are hashed with Blake3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+/// Compression of the archives of a patch. Patches that predate the
+/// choice are compressed with zstd.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum ArchiveCompression {
+    #[default]
+    Zstd,
+    /// Plain tars, for networks fast enough that decompression is the bottleneck.
+    None,
+}
+
+impl ArchiveCompression {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// File name of the archive with the given stem, such as `raw`.
+    fn archive_name(self, stem: &str) -> String {
+        match self {
+            Self::Zstd => format!("{stem}.tar.zst"),
+            Self::None => format!("{stem}.tar"),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,6 +603,7 @@ enum PatchManifestVersion {
     V1,
 }
 
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct PatchManifest {
     manifest_version: PatchManifestVersion,
@@ -115,18 +612,147 @@ struct PatchManifest {
     new_files: Vec<FileManifest>,
     diff_files: Vec<FileManifest>,
     stale_files: Vec<String>,
+    /// Size of the raw archive, for estimating downloads without HEAD support.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_archive_size: Option<u64>,
+    /// Size of the diff archive, for estimating downloads without HEAD support.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    diff_archive_size: Option<u64>,
+    /// Hash of the raw archive, used to key the archive cache.
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_archive_hash: Option<[u8; 32]>,
+    /// Hash of the diff archive, used to key the archive cache.
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff_archive_hash: Option<[u8; 32]>,
+    /// Uncompressed size of the raw tar, for decompression progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_tar_size: Option<u64>,
+    /// Uncompressed size of the diff tar, for decompression progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    diff_tar_size: Option<u64>,
+    /// Oldest launcher able to apply this patch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_launcher_version: Option<Version>,
+    #[serde(default, skip_serializing_if = "ArchiveCompression::is_default")]
+    compression: ArchiveCompression,
+    /// Length of the blocks that [`FileManifest::block_hashes`] are taken over.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash_block_len: Option<u64>,
+    /// Bytes of the block hashes in `sig.tar`, or
+    /// [`DEFAULT_SIGNATURE_HASH_LEN`] if not recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature_hash_len: Option<u32>,
+    /// Base-2 log of the zstd window when the archives were compressed with
+    /// long-distance matching, which decoders have to be allowed to use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zstd_window_log: Option<u32>,
+    /// Sizes of the parts the raw archive is published as, named
+    /// `raw.tar.zst.000` onward, or empty if it is published whole.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    raw_archive_parts: Vec<u64>,
+    /// Sizes of the parts the diff archive is published as, like `raw_archive_parts`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    diff_archive_parts: Vec<u64>,
+}
+
+/// A patch manifest relative to the manifest of its previous version, so an
+/// installer holding that one only has to fetch the files that changed.
+///
+/// The files of the previous version that are still present become
+/// `diff_files`, so of those only the changed ones are listed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestDelta {
+    base_version: Version,
+    /// The manifest with only the changed files in `diff_files`.
+    manifest: PatchManifest,
+    /// Files of the base version missing from this one, including protected
+    /// files that are not in `stale_files`.
+    removed_files: Vec<String>,
+}
+
+impl ManifestDelta {
+    fn new(base: &PatchManifest, manifest: &PatchManifest) -> Self {
+        let base_files: HashMap<_, _> = base
+            .new_files
+            .iter()
+            .chain(base.diff_files.iter())
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        let diff_paths: HashSet<_> = manifest
+            .diff_files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+
+        let mut delta_mf = manifest.clone();
+        delta_mf
+            .diff_files
+            .retain(|file| base_files.get(file.path.as_str()) != Some(&file));
+        let removed_files = base_files
+            .into_keys()
+            .filter(|path| !diff_paths.contains(path))
+            .map(String::from)
+            .collect();
+
+        Self {
+            base_version: base.version.clone(),
+            manifest: delta_mf,
+            removed_files,
+        }
+    }
+}
+
+/// Converts a path for the manifest, which stores paths as strings.
+/// Non-UTF-8 names are rejected, as a lossy conversion would make the
+/// installer write the file under a different name.
+fn to_manifest_path(path: &Path) -> anyhow::Result<String> {
+    match path.to_str() {
+        Some(path) => Ok(path.into()),
+        None => anyhow::bail!("path is not valid UTF-8: {}", path.display()),
+    }
+}
+
+/// Finds a path listed more than once, which would make an installer
+/// drop one of the entries.
+fn find_duplicate_path<'a>(paths: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let mut seen = HashSet::new();
+    paths.into_iter().find(|path| !seen.insert(*path))
 }
 
-async fn get_files(path: &PathBuf) -> std::io::Result<HashSet<PathBuf>> {
+/// Finds two paths differing only in case, which are the same file on
+/// case-insensitive filesystems like the Windows and macOS defaults.
+fn find_case_collision<'a>(paths: impl IntoIterator<Item = &'a str>) -> Option<(&'a str, &'a str)> {
+    let mut folded = HashMap::new();
+    paths
+        .into_iter()
+        .find_map(|path| Some((path, folded.insert(path.to_lowercase(), path)?)))
+}
+
+/// Compiles the globs of paths, relative to the install directory,
+/// that are never removed as stale.
+fn build_globs(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// Lists the files below `path`, along with their total size.
+async fn get_files(path: &PathBuf, concurrency: usize) -> std::io::Result<(HashSet<PathBuf>, u64)> {
     let mut files = HashSet::new();
-    let dir_visit = file_util::visit_stream(path);
+    let mut total_len = 0;
+    let dir_visit = file_util::visit_stream_concurrent(path, concurrency);
     pin_mut!(dir_visit);
     while let Some((ty, entry)) = dir_visit.next().await.transpose()? {
         if ty.is_file() {
+            total_len += entry.metadata().await?.len();
             files.insert(entry.path());
         }
     }
-    Ok(files)
+    Ok((files, total_len))
 }
 
 async fn do_create_patch(
@@ -135,161 +761,669 @@ async fn do_create_patch(
     new_dir: PathBuf,
     old_dir: Option<PathBuf>,
     version: String,
+    options: &CreatePatchOptions,
 ) -> anyhow::Result<CreatePatchResult> {
+    let started = Instant::now();
     let version = Version::parse(&version)?;
+    let hash_algorithm = options.hash_algorithm;
+    let protected = build_globs(&options.protected)?;
+    let critical = build_globs(&options.critical)?;
+    let min_launcher_version = options
+        .min_launcher_version
+        .as_deref()
+        .map(Version::parse)
+        .transpose()?;
+    let signature_hash_len = options.signature_hash_len();
+    let block_hash_threshold = options.block_hash_threshold;
+    let compression_level = options.compression_level();
+    let long_window_log = options.long_window_log;
+    if !ZSTD_LEVELS.contains(&compression_level) {
+        anyhow::bail!(
+            "compression level must be between {} and {}, got {compression_level}",
+            ZSTD_LEVELS.start(),
+            ZSTD_LEVELS.end()
+        );
+    }
+    if !SIGNATURE_HASH_LENS.contains(&signature_hash_len) {
+        anyhow::bail!(
+            "signature hash length must be between {} and {} bytes, got {signature_hash_len}",
+            SIGNATURE_HASH_LENS.start(),
+            SIGNATURE_HASH_LENS.end()
+        );
+    }
+    if let Some(window_log) = long_window_log {
+        if !ZSTD_WINDOW_LOGS.contains(&window_log) {
+            anyhow::bail!(
+                "window log must be between {} and {}, got {window_log}",
+                ZSTD_WINDOW_LOGS.start(),
+                ZSTD_WINDOW_LOGS.end()
+            );
+        }
+    }
 
-    let mut out_raw_tar = create_tar(&out_dir.join("raw.tar")).await?;
-    let mut out_sig_tar = create_tar(&out_dir.join("sig.tar")).await?;
-    let mut out_manifest_fs = File::create(out_dir.join("manifest.json")).await?;
+    // Canonical paths also catch the same directory reached through a symlink.
+    if let Some(old_dir) = &old_dir {
+        if tokio::fs::canonicalize(&new_dir).await? == tokio::fs::canonicalize(old_dir).await? {
+            anyhow::bail!(
+                "new and old directory are both {}, the old directory must be the output of the previous patch",
+                new_dir.display()
+            );
+        }
+    }
 
-    let diff_result = if let Some(old_dir) = old_dir {
-        do_create_diff(&app, &out_dir, &new_dir, &old_dir).await?
+    let (new_files, total_bytes) = get_files(&new_dir, options.walk_concurrency()).await?;
+    // Fail before any work is done rather than on the first bad file.
+    let mut manifest_paths = Vec::with_capacity(new_files.len());
+    for file in &new_files {
+        manifest_paths.push(to_manifest_path(file.strip_prefix(&new_dir)?)?);
+    }
+    if let Some((path, other)) = find_case_collision(manifest_paths.iter().map(String::as_str)) {
+        anyhow::bail!(
+            "{path} and {other} differ only in case, so they would overwrite each other when installed on a case-insensitive filesystem"
+        );
+    }
+    let input_hash = hash_inputs(
+        &new_dir,
+        &new_files,
+        old_dir.as_deref(),
+        &version,
+        hash_algorithm,
+        signature_hash_len,
+        block_hash_threshold,
+    )
+    .await?;
+    let (mut journal, resumed) = PatchJournal::open(&out_dir, input_hash).await?;
+
+    let mut progress = CreatePatchProgress {
+        done_files: resumed.raw_files.len() + resumed.diff_files.len(),
+        total_files: new_files.len(),
+        done_bytes: resumed
+            .raw_files
+            .iter()
+            .chain(&resumed.diff_files)
+            .map(|file| file.len)
+            .sum(),
+        total_bytes,
+        path: "".into(),
+        file_done_bytes: 0,
+        file_total_bytes: 0,
+        elapsed_secs: None,
+    };
+
+    let mut out_raw_tar = open_tar_at(&out_dir.join("raw.tar"), resumed.raw_tar_len).await?;
+    let mut out_sig_tar = open_tar_at(&out_dir.join("sig.tar"), resumed.sig_tar_len).await?;
+    // Emptied up front, so an interrupted run leaves no manifest of older archives.
+    File::create(out_dir.join("manifest.json")).await?;
+
+    let diff_result = if let Some(old_dir) = &old_dir {
+        do_create_diff(
+            &app,
+            &out_dir,
+            &new_dir,
+            old_dir,
+            new_files,
+            hash_algorithm,
+            &protected,
+            &mut journal,
+            &mut progress,
+            resumed.diff_files,
+            resumed.diff_tar_len,
+        )
+        .await?
     } else {
-        let new_files = get_files(&new_dir).await?;
+        // Publish an empty diff archive too, so every version has the same layout.
+        let out_diff_tar = create_tar(&out_dir.join("diff.tar")).await?;
+        let mut out_diff_fs = out_diff_tar.into_inner().await?.into_inner();
+        out_diff_fs.flush().await?;
         DiffResult {
-            prev_version: None,
+            prev_manifest: None,
             new_files,
             diff_files: vec![],
             stale_files: vec![],
-            diff_size: 0,
+            diff_size: out_diff_fs.metadata().await?.len(),
+            diff_hash: None,
         }
     };
     let diff_files = diff_result.diff_files;
 
-    let mut progress = CreatePatchProgress {
-        done_files: diff_files.len(),
-        total_files: diff_files.len() + diff_result.new_files.len(),
-        path: "".into(),
+    let mut new_files = diff_result.new_files;
+    for file in &resumed.raw_files {
+        new_files.remove(&new_dir.join(&file.path));
+    }
+    let mut new_mf_files = resumed.raw_files;
+
+    // Files are signed concurrently, but appended in path order. Each is read
+    // once, and its bytes are passed on to the raw archive as it is signed.
+    let mut new_files: Vec<_> = new_files.into_iter().collect();
+    new_files.sort();
+    progress.file_done_bytes = 0;
+    let shared_progress = Arc::new(Mutex::new(progress));
+    let sign_options = SignFileOptions {
+        hash_algorithm,
+        block_hash_threshold,
+        signature_hash_len,
     };
+    // Dropped on an early return, which aborts the files still being signed.
+    let mut sign_tasks = JoinSet::new();
+    let sign_concurrency = options.sign_concurrency();
+    let mut signing = VecDeque::with_capacity(sign_concurrency);
+    let mut new_files = new_files.into_iter();
+    loop {
+        while signing.len() < sign_concurrency {
+            let Some(file) = new_files.next() else {
+                break;
+            };
+            signing.push_back(start_signing(
+                &mut sign_tasks,
+                &app,
+                &shared_progress,
+                file,
+                sign_options,
+            ));
+        }
+        let Some(SigningFile { path, data, signed }) = signing.pop_front() else {
+            break;
+        };
+        let relative_path = path.strip_prefix(&new_dir)?;
 
-    let mut new_mf_files = Vec::new();
-
-    let mut write_buf = Vec::with_capacity(1024 * 16);
-    let mut read_buf = BytesMut::with_capacity(1024 * 16);
-
-    for file in diff_result.new_files.into_iter() {
-        let relative_path = file.strip_prefix(&new_dir)?;
-
-        progress.path = file.to_string_lossy().into();
-        progress.emit(&app);
-
-        let mut src_fs = File::open(&file).await?;
-        let src_meta = src_fs.metadata().await?;
-
+        let len = tokio::fs::metadata(&path).await?.len();
+        let mut data = StreamReader::new(data);
         let mut raw_header = async_tar::Header::new_gnu();
-        raw_header.set_size(src_meta.len());
+        raw_header.set_size(len);
         out_raw_tar
-            .append_data(&mut raw_header, relative_path, src_fs.compat_mut())
+            .append_data(
+                &mut raw_header,
+                relative_path,
+                (&mut data).take(len).compat(),
+            )
             .await?;
-        src_fs.seek(std::io::SeekFrom::Start(0)).await?;
-
-        fast_rsync::Signature::calculate(
-            &mut src_fs,
-            &mut write_buf,
-            &SignatureOptions::new(
-                fast_rsync::RollingHashType::RabinKarp,
-                fast_rsync::CryptoHashType::Blake2,
-                2048,
-                8,
-            ),
-        )
-        .await?;
-        src_fs.seek(std::io::SeekFrom::Start(0)).await?;
+        // Bytes past the header size mean the file grew, but have to be
+        // received for the signing to finish.
+        let excess = tokio::io::copy(&mut data, &mut tokio::io::sink()).await?;
+        let signed = signed
+            .await
+            .map_err(|_| anyhow::anyhow!("signing {} stopped", path.display()))??;
+        while sign_tasks.try_join_next().is_some() {}
+        if excess != 0 || signed.len != len {
+            anyhow::bail!(
+                "{} changed while the patch was being created",
+                path.display()
+            );
+        }
 
         let mut sig_header = async_tar::Header::new_gnu();
-        sig_header.set_size(write_buf.len().try_into().unwrap());
+        sig_header.set_size(signed.signature.len().try_into().unwrap());
         out_sig_tar
-            .append_data(&mut sig_header, relative_path, write_buf.as_slice())
+            .append_data(&mut sig_header, relative_path, signed.signature.as_slice())
             .await?;
 
-        let mut hash = Blake3Hash::default();
-        while src_fs.read_buf(&mut read_buf).await? != 0 {
-            hash.update(&read_buf.split());
+        let file_mf = FileManifest {
+            path: to_manifest_path(relative_path)?,
+            len,
+            hash: signed.hash,
+            hash_algorithm,
+            critical: false,
+            block_hashes: signed.block_hashes,
+        };
+        journal.push(JournalArchive::Raw, &file_mf).await?;
+        {
+            let mut progress = shared_progress.lock().unwrap();
+            progress.done_files += 1;
+            progress.done_bytes += file_mf.len;
+            // Its bytes were counted as they were signed.
+            progress.file_done_bytes = progress.file_done_bytes.saturating_sub(file_mf.len);
+            progress.emit(&app);
         }
+        new_mf_files.push(file_mf);
+    }
+    let mut progress = shared_progress.lock().unwrap().clone();
 
-        write_buf.clear();
-        read_buf.clear();
-
-        new_mf_files.push(FileManifest {
-            path: relative_path.to_string_lossy().into(),
-            len: src_meta.len(),
-            hash: hash.finish(),
-        });
+    let mut out_raw_fs = out_raw_tar.into_inner().await?.into_inner();
+    out_raw_fs.flush().await?;
+    let out_raw_size = out_raw_fs.metadata().await?.len();
+    let out_raw_hash = file_util::hash_file(&out_dir.join("raw.tar")).await?;
 
-        progress.done_files += 1;
-        progress.emit(&app);
-    }
+    let out_sig_fs = out_sig_tar.into_inner().await?;
+    let out_sig_size = out_sig_fs.into_inner().metadata().await?.len();
 
-    let manifest = PatchManifest {
+    let mut manifest = PatchManifest {
         manifest_version: PatchManifestVersion::V1,
         version,
-        previous_version: diff_result.prev_version,
+        previous_version: diff_result
+            .prev_manifest
+            .as_ref()
+            .map(|mf| mf.version.clone()),
+        raw_archive_size: (!new_mf_files.is_empty()).then_some(out_raw_size),
+        diff_archive_size: (!diff_files.is_empty()).then_some(diff_result.diff_size),
+        raw_archive_hash: (!new_mf_files.is_empty()).then_some(out_raw_hash),
+        diff_archive_hash: diff_result.diff_hash.filter(|_| !diff_files.is_empty()),
+        raw_tar_size: (!new_mf_files.is_empty()).then_some(out_raw_size),
+        diff_tar_size: (!diff_files.is_empty()).then_some(diff_result.diff_size),
         new_files: new_mf_files,
         diff_files,
         stale_files: diff_result.stale_files,
+        min_launcher_version,
+        compression: options.compression,
+        hash_block_len: block_hash_threshold.map(|_| HASH_BLOCK_LEN),
+        signature_hash_len: Some(signature_hash_len),
+        zstd_window_log: None,
+        raw_archive_parts: vec![],
+        diff_archive_parts: vec![],
     };
-    serde_json::to_writer(&mut write_buf, &manifest)?;
-    out_manifest_fs.write_all(&mut write_buf).await?;
-
-    let out_raw_fs = out_raw_tar.into_inner().await?;
-    let out_raw_size = out_raw_fs.into_inner().metadata().await?.len();
+    for file in manifest
+        .new_files
+        .iter_mut()
+        .chain(manifest.diff_files.iter_mut())
+    {
+        file.critical = critical.is_match(&file.path);
+    }
+    let files = manifest.new_files.iter().chain(&manifest.diff_files);
+    if let Some(path) = find_duplicate_path(files.map(|file| file.path.as_str())) {
+        anyhow::bail!("{path} is listed in the manifest more than once");
+    }
+    if options.verify_output {
+        verify_patch_output(&out_dir, &manifest).await?;
+    }
+    let uncompressed_archive_size = diff_result.diff_size + out_sig_size + out_raw_size;
+    let archive_size = match manifest.compression {
+        ArchiveCompression::Zstd => {
+            compress_archives(&out_dir, &mut manifest, compression_level, long_window_log).await?
+        }
+        ArchiveCompression::None => uncompressed_archive_size,
+    };
+    if let Some(part_size) = options.part_size {
+        split_patch(&out_dir, &mut manifest, part_size).await?;
+    }
+    let manifest_path = out_dir.join("manifest.json");
+    let mut out_manifest_size = write_manifest(&manifest_path, &manifest).await?;
+    let manifest_hash = file_util::hash_file(&manifest_path).await?;
+    let mut manifest_delta_hash = None;
+    if let Some(prev_manifest) = &diff_result.prev_manifest {
+        let delta = ManifestDelta::new(prev_manifest, &manifest);
+        let delta_path = out_dir.join("manifest-delta.json");
+        out_manifest_size += write_manifest(&delta_path, &delta).await?;
+        manifest_delta_hash = Some(file_util::hash_file(&delta_path).await?);
+    }
+    journal.finish(&out_dir).await?;
+    // Only now that nothing can resume from them, the tars are replaced by
+    // their compressed archives.
+    if manifest.compression == ArchiveCompression::Zstd {
+        for stem in ARCHIVE_STEMS {
+            tokio::fs::remove_file(out_dir.join(format!("{stem}.tar"))).await?;
+        }
+    }
+    if options.combined {
+        do_pack_patch(&out_dir).await?;
+    }
 
-    let out_sig_fs = out_sig_tar.into_inner().await?;
-    let out_sig_size = out_sig_fs.into_inner().metadata().await?.len();
+    progress.elapsed_secs = Some(started.elapsed().as_secs_f64());
+    progress.emit(&app);
 
-    let patch_size = diff_result.diff_size + out_sig_size + out_raw_size + write_buf.len() as u64;
     Ok(CreatePatchResult {
         manifest,
-        patch_size,
+        patch_size: archive_size + out_manifest_size,
+        tar_size: uncompressed_archive_size + out_manifest_size,
+        manifest_hash,
+        manifest_delta_hash,
     })
 }
 
+/// zstd level archives are compressed at when a patch is created, unless
+/// another is given. Higher levels are worth it for releases, lower ones
+/// for test builds.
+const DEFAULT_COMPRESSION_LEVEL: i32 = 9;
+
+/// Levels supported by zstd, from fastest to smallest.
+const ZSTD_LEVELS: std::ops::RangeInclusive<i32> = 1..=22;
+
+/// Stems of the tars a patch is built into.
+const ARCHIVE_STEMS: [&str; 3] = ["raw", "diff", "sig"];
+
+/// Compresses the tars of a freshly built patch into archives next to them,
+/// records the compressed sizes and hashes in `manifest`, and returns the
+/// total size of the archives. The tars themselves are left for the caller
+/// to remove, since an interrupted build resumes from them.
+///
+/// With `long_window_log`, long-distance matching is enabled as in [`do_recompress`].
+async fn compress_archives(
+    out_dir: &Path,
+    manifest: &mut PatchManifest,
+    level: i32,
+    long_window_log: Option<u32>,
+) -> anyhow::Result<u64> {
+    manifest.zstd_window_log = long_window_log;
+    let mut total_size = 0;
+    for stem in ARCHIVE_STEMS {
+        let path = out_dir.join(manifest.compression.archive_name(stem));
+        let part_path = file_util::append_extension(&path, "part");
+        let tar_path = out_dir.join(format!("{stem}.tar"));
+        let size = compress_file(&tar_path, &part_path, level, long_window_log).await?;
+        tokio::fs::rename(&part_path, &path).await?;
+        total_size += size;
+
+        let archive = match stem {
+            "raw" => Some((
+                &mut manifest.raw_archive_size,
+                &mut manifest.raw_archive_hash,
+            )),
+            "diff" => Some((
+                &mut manifest.diff_archive_size,
+                &mut manifest.diff_archive_hash,
+            )),
+            _ => None,
+        };
+        // Archives without files are not recorded, so keep them that way.
+        if let Some((archive_size, archive_hash)) = archive {
+            if archive_size.is_some() {
+                *archive_size = Some(size);
+            }
+            if archive_hash.is_some() {
+                *archive_hash = Some(file_util::hash_file(&path).await?);
+            }
+        }
+    }
+    Ok(total_size)
+}
+
+#[derive(thiserror::Error, Debug)]
+enum CorruptOutput {
+    #[error("{path} in raw.tar does not match the manifest, the source may have been misread")]
+    Mismatch { path: String },
+    #[error("{path} is in the manifest but missing from {archive}")]
+    Missing { path: String, archive: &'static str },
+    #[error("{path} is in {archive} but not in the manifest")]
+    Unlisted { path: String, archive: &'static str },
+}
+
+/// Re-reads the archives of a freshly built patch and checks them against
+/// `manifest`, so a read error while packing fails the build instead of
+/// every install.
+///
+/// Raw files are hashed again. Diffs can only be checked for presence,
+/// since verifying their output would need the old files.
+async fn verify_patch_output(out_dir: &Path, manifest: &PatchManifest) -> anyhow::Result<()> {
+    let mut expected: HashMap<_, _> = manifest
+        .new_files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    let raw_tar = open_tar(&out_dir.join("raw.tar")).await?;
+    let mut entries = raw_tar.entries()?;
+    let mut read_buf = vec![0; 1024 * 64];
+    while let Some(mut entry) = entries.next().await.transpose()? {
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let Some(file) = expected.remove(path.as_str()) else {
+            return Err(CorruptOutput::Unlisted {
+                path,
+                archive: "raw.tar",
+            }
+            .into());
+        };
+
+        let mut hasher = FileHasher::new(file.hash_algorithm);
+        let mut len = 0;
+        loop {
+            let read = entry.read(&mut read_buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&read_buf[..read]);
+            len += read as u64;
+        }
+        if len != file.len || hasher.finish() != file.hash {
+            return Err(CorruptOutput::Mismatch { path }.into());
+        }
+    }
+    if let Some(path) = expected.into_keys().next() {
+        return Err(CorruptOutput::Missing {
+            path: path.into(),
+            archive: "raw.tar",
+        }
+        .into());
+    }
+
+    let mut expected: HashSet<_> = manifest
+        .diff_files
+        .iter()
+        .map(|file| file.path.as_str())
+        .collect();
+    let diff_tar = open_tar(&out_dir.join("diff.tar")).await?;
+    let mut entries = diff_tar.entries()?;
+    while let Some(entry) = entries.next().await.transpose()? {
+        let path = entry.path()?.to_string_lossy().into_owned();
+        if !expected.remove(path.as_str()) {
+            return Err(CorruptOutput::Unlisted {
+                path,
+                archive: "diff.tar",
+            }
+            .into());
+        }
+    }
+    if let Some(path) = expected.into_iter().next() {
+        return Err(CorruptOutput::Missing {
+            path: path.into(),
+            archive: "diff.tar",
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Splits the archives of a built patch into parts of at most `part_size`
+/// bytes, for hosts that limit the size of a file, and records them in `manifest`.
+async fn split_patch(
+    out_dir: &Path,
+    manifest: &mut PatchManifest,
+    part_size: u64,
+) -> anyhow::Result<()> {
+    if part_size == 0 {
+        anyhow::bail!("part size must not be zero");
+    }
+    let archives = [
+        (
+            "raw",
+            !manifest.new_files.is_empty(),
+            manifest.raw_archive_hash,
+            &mut manifest.raw_archive_parts,
+        ),
+        (
+            "diff",
+            !manifest.diff_files.is_empty(),
+            manifest.diff_archive_hash,
+            &mut manifest.diff_archive_parts,
+        ),
+    ];
+    for (stem, needed, hash, parts) in archives {
+        if !needed {
+            continue;
+        }
+        let name = manifest.compression.archive_name(stem);
+        let path = out_dir.join(&name);
+        if !tokio::fs::try_exists(&path).await? {
+            anyhow::bail!("{name} is missing, compress the patch before splitting it");
+        }
+        *parts = split_archive(&path, part_size).await?;
+
+        // Installers reassemble the parts, so they have to add up to the archive exactly.
+        let expected_hash = match hash {
+            Some(hash) => hash,
+            None => file_util::hash_file(&path).await?,
+        };
+        if hash_parts(&path, parts.len()).await? != expected_hash {
+            anyhow::bail!("the parts of {name} do not reassemble into it");
+        }
+    }
+    Ok(())
+}
+
+/// Hashes the `count` parts of the archive at `path` as one file.
+async fn hash_parts(path: &Path, count: usize) -> std::io::Result<[u8; 32]> {
+    let mut hasher = file_util::FileHasher::new(HashAlgorithm::Blake3);
+    let mut buf = vec![0u8; 1024 * 64];
+    for index in 0..count {
+        let part_path = file_util::append_extension(path, &format!("{index:03}"));
+        let mut part_fs = File::open(part_path).await?;
+        loop {
+            let read = part_fs.read(&mut buf).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Copies the archive at `path` into parts of at most `part_size` bytes named
+/// `{archive}.000` onward, returning their sizes. The archive itself is kept,
+/// so it can be recompressed and split again.
+async fn split_archive(path: &Path, part_size: u64) -> std::io::Result<Vec<u64>> {
+    let mut src_fs = File::open(path).await?;
+    let mut remaining = src_fs.metadata().await?.len();
+    let mut parts = Vec::new();
+    loop {
+        let part_len = remaining.min(part_size);
+        let part_path = file_util::append_extension(path, &format!("{:03}", parts.len()));
+        let mut part_fs = File::create(part_path).await?;
+        tokio::io::copy(&mut (&mut src_fs).take(part_len), &mut part_fs).await?;
+        part_fs.flush().await?;
+        parts.push(part_len);
+
+        remaining -= part_len;
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    // Parts beyond these are left over from splitting into more of them.
+    for index in parts.len().. {
+        let part_path = file_util::append_extension(path, &format!("{index:03}"));
+        if !tokio::fs::try_exists(&part_path).await? {
+            break;
+        }
+        tokio::fs::remove_file(&part_path).await?;
+    }
+    Ok(parts)
+}
+
+/// Name of the single archive a combined patch is published as.
+const COMBINED_ARCHIVE_NAME: &str = "patch.tar";
+
+/// Packs a built patch into one [`COMBINED_ARCHIVE_NAME`] for hosts that can
+/// only serve single files, returning its size. The manifest comes first, so
+/// installers can check it before the data, followed by the archives in the
+/// order they are installed. The signatures stay out, as only the creator needs them.
+async fn do_pack_patch(out_dir: &Path) -> anyhow::Result<u64> {
+    let manifest: PatchManifest =
+        serde_json::from_slice(&tokio::fs::read(out_dir.join("manifest.json")).await?)?;
+    if !manifest.raw_archive_parts.is_empty() || !manifest.diff_archive_parts.is_empty() {
+        anyhow::bail!("patch is split into parts, which cannot be combined");
+    }
+
+    let mut names = vec!["manifest.json".to_string()];
+    if !manifest.diff_files.is_empty() {
+        names.push(manifest.compression.archive_name("diff"));
+    }
+    if !manifest.new_files.is_empty() {
+        names.push(manifest.compression.archive_name("raw"));
+    }
+
+    let out_path = out_dir.join(COMBINED_ARCHIVE_NAME);
+    let part_path = file_util::append_extension(&out_path, "part");
+    let mut out_tar = create_tar(&part_path).await?;
+    for name in &names {
+        let path = out_dir.join(name);
+        if !tokio::fs::try_exists(&path).await? {
+            anyhow::bail!("{name} is missing, compress the patch before packing it");
+        }
+        let src_fs = File::open(&path).await?;
+        let mut header = async_tar::Header::new_gnu();
+        header.set_size(src_fs.metadata().await?.len());
+        out_tar
+            .append_data(&mut header, name, src_fs.compat())
+            .await?;
+    }
+    let mut out_fs = out_tar.into_inner().await?.into_inner();
+    out_fs.flush().await?;
+    let size = out_fs.metadata().await?.len();
+    drop(out_fs);
+
+    tokio::fs::rename(&part_path, &out_path).await?;
+    Ok(size)
+}
+
+/// Writes `manifest` to `path` as JSON, returning the size of the file.
+async fn write_manifest(path: &Path, manifest: &impl Serialize) -> anyhow::Result<u64> {
+    let json = serde_json::to_vec(manifest)?;
+    let mut fs = File::create(path).await?;
+    fs.write_all(&json).await?;
+    fs.flush().await?;
+    Ok(fs.metadata().await?.len())
+}
+
 #[derive(Debug)]
 struct DiffResult {
-    prev_version: Option<Version>,
+    prev_manifest: Option<PatchManifest>,
     new_files: HashSet<PathBuf>,
     diff_files: Vec<FileManifest>,
     stale_files: Vec<String>,
     diff_size: u64,
+    diff_hash: Option<[u8; 32]>,
 }
 
+/// Diffs `new_files` against the signatures in `old_dir`, carrying over
+/// `resumed_files` from the first `resumed_len` bytes of an interrupted `diff.tar`.
+#[allow(clippy::too_many_arguments)]
 async fn do_create_diff(
     app: &AppHandle,
     out_dir: &PathBuf,
     new_dir: &PathBuf,
     old_dir: &PathBuf,
+    mut new_files: HashSet<PathBuf>,
+    hash_algorithm: HashAlgorithm,
+    protected: &GlobSet,
+    journal: &mut PatchJournal,
+    progress: &mut CreatePatchProgress,
+    resumed_files: Vec<FileManifest>,
+    resumed_len: u64,
 ) -> anyhow::Result<DiffResult> {
     let old_patch_mf: PatchManifest = {
-        let mut fs = File::open(out_dir.join("manifest.json")).await?;
+        let mut fs = File::open(old_dir.join("manifest.json")).await?;
         let mut str = String::new();
         fs.read_to_string(&mut str).await?;
         serde_json::from_str(&str)?
     };
 
-    let old_sig_tar = open_tar(&old_dir.join("sig.tar")).await?;
-    let mut out_diff_tar = create_tar(&out_dir.join("diff.tar")).await?;
+    let old_sig_path = sig_archive_path(old_dir).await?;
+    check_old_signatures(&old_patch_mf, &old_sig_path).await?;
+
+    let old_sig_tar = open_sig_tar(&old_sig_path, old_patch_mf.zstd_window_log).await?;
+    let mut out_diff_tar = open_tar_at(&out_dir.join("diff.tar"), resumed_len).await?;
 
-    let mut new_files = get_files(&new_dir).await?;
-    let mut diff_files = Vec::new();
+    let resumed_paths: HashSet<_> = resumed_files
+        .iter()
+        .map(|file| PathBuf::from(&file.path))
+        .collect();
+    let mut diff_files = resumed_files;
     let mut stale_files = Vec::new();
 
     let mut sig_buf = Vec::new();
     let mut new_buf = Vec::new();
     let mut diff_buf = Vec::new();
 
-    let mut progress = CreatePatchProgress {
-        done_files: 0,
-        total_files: new_files.len(),
-        path: "".into(),
-    };
-
     let mut old_entries = old_sig_tar.entries()?;
     while let Some(mut old_sig_entry) = old_entries.next().await.transpose()? {
-        let relative_path = old_sig_entry.path()?.into_owned();
+        let relative_path: PathBuf = old_sig_entry.path()?.into_owned().into();
         let new_path = new_dir.join(&relative_path);
 
         if !new_files.remove(&new_path) {
-            stale_files.push(relative_path.to_string_lossy().into());
+            // Protected files are left in place even though the new version lacks them.
+            if !protected.is_match(&relative_path) {
+                stale_files.push(to_manifest_path(&relative_path)?);
+            }
+            continue;
+        }
+        if resumed_paths.contains(&relative_path) {
             continue;
         }
 
@@ -310,38 +1444,545 @@ async fn do_create_diff(
             .append_data(&mut diff_header, &relative_path, &mut diff_buf.as_slice())
             .await?;
 
-        diff_files.push(FileManifest {
-            path: relative_path.to_string_lossy().into(),
+        let file_mf = FileManifest {
+            path: to_manifest_path(&relative_path)?,
             len: new_buf.len() as u64,
-            hash: Blake3Hash::default().update(&new_buf).finish(),
-        });
+            hash: {
+                let mut hash = FileHasher::new(hash_algorithm);
+                hash.update(&new_buf);
+                hash.finish()
+            },
+            hash_algorithm,
+            critical: false,
+            // Diffed files are patched locally, not downloaded whole.
+            block_hashes: vec![],
+        };
+        journal.push(JournalArchive::Diff, &file_mf).await?;
+        progress.done_files += 1;
+        progress.done_bytes += file_mf.len;
+        diff_files.push(file_mf);
 
         sig_buf.clear();
         new_buf.clear();
         diff_buf.clear();
 
-        progress.done_files += 1;
         progress.emit(app);
     }
 
-    let out_diff_fs = out_diff_tar.into_inner().await?;
-    let out_diff_len = out_diff_fs.into_inner().metadata().await?.len();
+    let mut out_diff_fs = out_diff_tar.into_inner().await?.into_inner();
+    out_diff_fs.flush().await?;
+    let out_diff_len = out_diff_fs.metadata().await?.len();
+    let out_diff_hash = file_util::hash_file(&out_dir.join("diff.tar")).await?;
 
     Ok(DiffResult {
-        prev_version: Some(old_patch_mf.version),
+        prev_manifest: Some(old_patch_mf),
         new_files,
         diff_files,
         stale_files,
         diff_size: out_diff_len,
+        diff_hash: Some(out_diff_hash),
+    })
+}
+
+/// Signatures that do not match the old manifest, which usually means the
+/// old directory is from a different release than its manifest.
+#[derive(thiserror::Error, Debug)]
+enum InconsistentOldPatch {
+    #[error("old manifest and signatures are inconsistent: {count} raw files of the manifest have no signature, like {example}")]
+    Unsigned { count: usize, example: String },
+    #[error("old manifest and signatures are inconsistent: {count} signatures are of files missing from the manifest, like {example}")]
+    Unlisted { count: usize, example: String },
+}
+
+/// Checks that `sig_path` holds a signature for exactly the raw files of
+/// `old_patch_mf`, which are the only ones a patch signs.
+async fn check_old_signatures(old_patch_mf: &PatchManifest, sig_path: &Path) -> anyhow::Result<()> {
+    let mut unsigned: HashSet<_> = old_patch_mf
+        .new_files
+        .iter()
+        .map(|file| file.path.as_str())
+        .collect();
+    let mut unlisted = Vec::new();
+
+    let sig_tar = open_sig_tar(sig_path, old_patch_mf.zstd_window_log).await?;
+    let mut entries = sig_tar.entries()?;
+    while let Some(entry) = entries.next().await.transpose()? {
+        let path = entry.path()?.to_string_lossy().into_owned();
+        if !unsigned.remove(path.as_str()) {
+            unlisted.push(path);
+        }
+    }
+
+    if let Some(example) = unsigned.iter().next() {
+        return Err(InconsistentOldPatch::Unsigned {
+            count: unsigned.len(),
+            example: example.to_string(),
+        }
+        .into());
+    }
+    if let Some(example) = unlisted.first() {
+        return Err(InconsistentOldPatch::Unlisted {
+            count: unlisted.len(),
+            example: example.clone(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Re-encodes the compressed archives of a built patch at `level`, leaving
+/// their decompressed contents untouched, and records the new compressed
+/// sizes and hashes in its manifest.
+///
+/// Rewriting the manifest changes its hash, so the returned
+/// [`CreatePatchResult::manifest_hash`] replaces any pinned in versions.json.
+///
+/// With `long_window_log`, long-distance matching is enabled over a window of
+/// that many bits, which pays off for large files sharing distant content.
+async fn do_recompress(
+    app: &AppHandle,
+    out_dir: PathBuf,
+    level: i32,
+    long_window_log: Option<u32>,
+) -> anyhow::Result<CreatePatchResult> {
+    let started = Instant::now();
+    if !ZSTD_LEVELS.contains(&level) {
+        anyhow::bail!(
+            "compression level must be between {} and {}, got {level}",
+            ZSTD_LEVELS.start(),
+            ZSTD_LEVELS.end()
+        );
+    }
+    if let Some(window_log) = long_window_log {
+        if !ZSTD_WINDOW_LOGS.contains(&window_log) {
+            anyhow::bail!(
+                "window log must be between {} and {}, got {window_log}",
+                ZSTD_WINDOW_LOGS.start(),
+                ZSTD_WINDOW_LOGS.end()
+            );
+        }
+    }
+
+    let manifest_path = out_dir.join("manifest.json");
+    let mut manifest: PatchManifest =
+        serde_json::from_slice(&tokio::fs::read(&manifest_path).await?)?;
+    if manifest.compression == ArchiveCompression::None {
+        anyhow::bail!("patch was created without compression");
+    }
+
+    let names = ["raw.tar.zst", "diff.tar.zst", "sig.tar.zst"];
+    let mut progress = CreatePatchProgress {
+        done_files: 0,
+        total_files: names.len(),
+        done_bytes: 0,
+        total_bytes: 0,
+        path: "".into(),
+        file_done_bytes: 0,
+        file_total_bytes: 0,
+        elapsed_secs: None,
+    };
+
+    let mut patch_size = 0;
+    let mut tar_size = 0;
+    for name in names {
+        let path = out_dir.join(name);
+        progress.path = path.to_string_lossy().into();
+        progress.emit(app);
+
+        if tokio::fs::try_exists(&path).await? {
+            let part_path = file_util::append_extension(&path, "part");
+            let (content_hash, content_len) = recompress_file(
+                &path,
+                &part_path,
+                level,
+                manifest.zstd_window_log,
+                long_window_log,
+            )
+            .await?;
+            if hash_zstd_contents(&part_path, long_window_log).await? != content_hash {
+                tokio::fs::remove_file(&part_path).await?;
+                anyhow::bail!("recompressed {name} does not match the original contents");
+            }
+            tokio::fs::rename(&part_path, &path).await?;
+
+            let size = tokio::fs::metadata(&path).await?.len();
+            let hash = file_util::hash_file(&path).await?;
+            patch_size += size;
+            tar_size += content_len;
+
+            let archive = match name {
+                "raw.tar.zst" => Some((
+                    &mut manifest.raw_archive_size,
+                    &mut manifest.raw_archive_hash,
+                    &mut manifest.raw_archive_parts,
+                )),
+                "diff.tar.zst" => Some((
+                    &mut manifest.diff_archive_size,
+                    &mut manifest.diff_archive_hash,
+                    &mut manifest.diff_archive_parts,
+                )),
+                _ => None,
+            };
+            // Archives without files are not recorded, so keep them that way.
+            if let Some((archive_size, archive_hash, parts)) = archive {
+                if archive_size.is_some() {
+                    *archive_size = Some(size);
+                }
+                if archive_hash.is_some() {
+                    *archive_hash = Some(hash);
+                }
+                // No part was larger than the first, so it still fits the host.
+                if let Some(&part_size) = parts.first() {
+                    *parts = split_archive(&path, part_size).await?;
+                }
+            }
+        }
+
+        progress.done_files += 1;
+        progress.emit(app);
+    }
+    manifest.zstd_window_log = long_window_log;
+
+    let manifest_json = serde_json::to_vec(&manifest)?;
+    tokio::fs::write(&manifest_path, &manifest_json).await?;
+    patch_size += manifest_json.len() as u64;
+    tar_size += manifest_json.len() as u64;
+    let manifest_hash = Blake3Hash::default().update(&manifest_json).finish();
+
+    // The delta carries the archive fields of the manifest, so they must match.
+    let delta_path = out_dir.join("manifest-delta.json");
+    let mut manifest_delta_hash = None;
+    if tokio::fs::try_exists(&delta_path).await? {
+        let mut delta: ManifestDelta =
+            serde_json::from_slice(&tokio::fs::read(&delta_path).await?)?;
+        delta.manifest.raw_archive_size = manifest.raw_archive_size;
+        delta.manifest.raw_archive_hash = manifest.raw_archive_hash;
+        delta.manifest.diff_archive_size = manifest.diff_archive_size;
+        delta.manifest.diff_archive_hash = manifest.diff_archive_hash;
+        delta.manifest.raw_archive_parts = manifest.raw_archive_parts.clone();
+        delta.manifest.diff_archive_parts = manifest.diff_archive_parts.clone();
+        delta.manifest.zstd_window_log = manifest.zstd_window_log;
+        let delta_json = serde_json::to_vec(&delta)?;
+        tokio::fs::write(&delta_path, &delta_json).await?;
+        patch_size += delta_json.len() as u64;
+        tar_size += delta_json.len() as u64;
+        manifest_delta_hash = Some(Blake3Hash::default().update(&delta_json).finish());
+    }
+
+    // Likewise for a combined archive, which holds the old archives otherwise.
+    if tokio::fs::try_exists(out_dir.join(COMBINED_ARCHIVE_NAME)).await? {
+        do_pack_patch(&out_dir).await?;
+    }
+
+    progress.elapsed_secs = Some(started.elapsed().as_secs_f64());
+    progress.emit(app);
+
+    Ok(CreatePatchResult {
+        manifest,
+        patch_size,
+        tar_size,
+        manifest_hash,
+        manifest_delta_hash,
     })
 }
 
+/// Window logs accepted for long-distance matching, from zstd's minimum up
+/// to the largest window a 32-bit decoder can hold.
+const ZSTD_WINDOW_LOGS: std::ops::RangeInclusive<u32> = 10..=30;
+
+fn zstd_decoder<R: AsyncBufRead>(reader: R, window_log: Option<u32>) -> ZstdDecoder<R> {
+    let mut decoder = match window_log {
+        Some(window_log) => {
+            ZstdDecoder::with_params(reader, &[DParameter::window_log_max(window_log)])
+        }
+        None => ZstdDecoder::new(reader),
+    };
+    decoder.multiple_members(true);
+    decoder
+}
+
+/// Encodes into `dst` at `level`, with long-distance matching over a window
+/// of `long_window_log` bits if given.
+fn zstd_encoder<W: tokio::io::AsyncWrite>(
+    dst: W,
+    level: i32,
+    long_window_log: Option<u32>,
+) -> ZstdEncoder<W> {
+    match long_window_log {
+        Some(window_log) => ZstdEncoder::with_quality_and_params(
+            dst,
+            Level::Precise(level),
+            &[
+                CParameter::enable_long_distance_matching(true),
+                CParameter::window_log(window_log),
+            ],
+        ),
+        None => ZstdEncoder::with_quality(dst, Level::Precise(level)),
+    }
+}
+
+/// Compresses `src_path` into a new zstd stream at `dst_path`, returning its size.
+async fn compress_file(
+    src_path: &Path,
+    dst_path: &Path,
+    level: i32,
+    long_window_log: Option<u32>,
+) -> std::io::Result<u64> {
+    let mut src_fs = File::open(src_path).await?;
+    let dst_fs = File::create(dst_path).await?;
+    let mut encoder = zstd_encoder(dst_fs, level, long_window_log);
+    tokio::io::copy(&mut src_fs, &mut encoder).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner().metadata().await?.len())
+}
+
+/// Decompresses `src_path`, compressed with `src_window_log`, into a new zstd
+/// stream at `dst_path`, returning the hash and length of the decompressed contents.
+async fn recompress_file(
+    src_path: &Path,
+    dst_path: &Path,
+    level: i32,
+    src_window_log: Option<u32>,
+    long_window_log: Option<u32>,
+) -> std::io::Result<([u8; 32], u64)> {
+    let mut decoder = zstd_decoder(BufReader::new(File::open(src_path).await?), src_window_log);
+    let dst_fs = File::create(dst_path).await?;
+    let mut encoder = zstd_encoder(dst_fs, level, long_window_log);
+
+    let mut hash = Blake3Hash::default();
+    let mut len = 0;
+    let mut buf = vec![0u8; 1024 * 64];
+    loop {
+        let read = decoder.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buf[..read]);
+        len += read as u64;
+        encoder.write_all(&buf[..read]).await?;
+    }
+    encoder.shutdown().await?;
+    Ok((hash.finish(), len))
+}
+
+/// Hashes the decompressed contents of a zstd file.
+async fn hash_zstd_contents(path: &Path, window_log: Option<u32>) -> std::io::Result<[u8; 32]> {
+    let mut decoder = zstd_decoder(BufReader::new(File::open(path).await?), window_log);
+
+    let mut hash = Blake3Hash::default();
+    let mut buf = vec![0u8; 1024 * 64];
+    loop {
+        let read = decoder.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buf[..read]);
+    }
+    Ok(hash.finish())
+}
+
 impl CreatePatchProgress {
-    fn emit(&self, app: &AppHandle) {
-        app.emit("create-patch-progress", self).unwrap();
+    fn emit(&self, app: &dyn ProgressSink) {
+        app.emit_event("create-patch-progress", serde_json::to_value(self).unwrap())
+            .unwrap();
     }
 }
 
+/// Hashes the bytes read from `inner`, as a whole and in blocks, and sends
+/// them on to `tx`, reading no further ahead than the receiver allows.
+struct TeeReader<R> {
+    inner: R,
+    hasher: FileHasher,
+    blocks: Option<BlockHasher>,
+    len: u64,
+    tx: futures::channel::mpsc::Sender<std::io::Result<Bytes>>,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for TeeReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if ready!(this.tx.poll_ready(cx)).is_err() {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "receiver stopped reading",
+            )));
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[filled..];
+        if !read.is_empty() {
+            this.hasher.update(read);
+            if let Some(blocks) = &mut this.blocks {
+                blocks.update(read);
+            }
+            this.len += read.len() as u64;
+            // The slot was reserved by `poll_ready` above.
+            _ = this.tx.start_send(Ok(Bytes::copy_from_slice(read)));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// How [`sign_file`] hashes and signs new files.
+#[derive(Debug, Clone, Copy)]
+struct SignFileOptions {
+    hash_algorithm: HashAlgorithm,
+    block_hash_threshold: Option<u64>,
+    signature_hash_len: u32,
+}
+
+/// A new file being hashed and signed by [`sign_file`].
+struct SigningFile {
+    path: PathBuf,
+    /// Bytes of the file as they are read, for the raw archive.
+    data: futures::channel::mpsc::Receiver<std::io::Result<Bytes>>,
+    signed: oneshot::Receiver<anyhow::Result<SignedFile>>,
+}
+
+/// A new file as hashed and signed by [`sign_file`].
+struct SignedFile {
+    /// Bytes read, which are the ones passed on through [`SigningFile::data`].
+    len: u64,
+    hash: [u8; 32],
+    block_hashes: Vec<[u8; 32]>,
+    signature: Vec<u8>,
+}
+
+/// Chunks a file being signed may read ahead of the raw archive.
+const SIGN_READ_AHEAD: usize = 16;
+
+/// Spawns [`sign_file`] for `path` into `tasks`.
+fn start_signing(
+    tasks: &mut JoinSet<()>,
+    app: &AppHandle,
+    progress: &Arc<Mutex<CreatePatchProgress>>,
+    path: PathBuf,
+    options: SignFileOptions,
+) -> SigningFile {
+    let (data_tx, data) = futures::channel::mpsc::channel(SIGN_READ_AHEAD);
+    let (signed_tx, signed) = oneshot::channel();
+    let app = app.clone();
+    let progress = progress.clone();
+    let task_path = path.clone();
+    tasks.spawn(async move {
+        let result = sign_file(&app, &progress, &task_path, options, data_tx).await;
+        _ = signed_tx.send(result);
+    });
+    SigningFile { path, data, signed }
+}
+
+/// Hashes and signs the file at `path` in a single read, passing its bytes
+/// on to `data_tx` and counting them as progress within the files being signed.
+async fn sign_file(
+    app: &dyn ProgressSink,
+    progress: &Mutex<CreatePatchProgress>,
+    path: &Path,
+    options: SignFileOptions,
+    data_tx: futures::channel::mpsc::Sender<std::io::Result<Bytes>>,
+) -> anyhow::Result<SignedFile> {
+    let src_fs = File::open(path).await?;
+    let src_meta = src_fs.metadata().await?;
+    {
+        let mut progress = progress.lock().unwrap();
+        progress.path = path.to_string_lossy().into();
+        progress.file_total_bytes = src_meta.len();
+        progress.emit(app);
+    }
+
+    let mut reader = TeeReader {
+        inner: src_fs,
+        hasher: FileHasher::new(options.hash_algorithm),
+        blocks: options
+            .block_hash_threshold
+            .is_some_and(|threshold| src_meta.len() >= threshold)
+            .then(|| BlockHasher::new(HASH_BLOCK_LEN)),
+        len: 0,
+        tx: data_tx,
+    };
+    let mut signature = Vec::with_capacity(1024 * 16);
+    fast_rsync::Signature::calculate(
+        &mut SignProgressReader {
+            inner: &mut reader,
+            app,
+            progress,
+            emit_throttle: EmitThrottle::new(),
+        },
+        &mut signature,
+        &signature_options(options.signature_hash_len),
+    )
+    .await?;
+    reader.tx.close_channel();
+
+    Ok(SignedFile {
+        len: reader.len,
+        hash: reader.hasher.finish(),
+        block_hashes: reader.blocks.map(BlockHasher::finish).unwrap_or_default(),
+        signature,
+    })
+}
+
+/// Reports the bytes read from a file being signed as progress within it.
+struct SignProgressReader<'a, R> {
+    inner: R,
+    app: &'a dyn ProgressSink,
+    progress: &'a Mutex<CreatePatchProgress>,
+    emit_throttle: EmitThrottle,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for SignProgressReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &poll {
+            let this = &mut *self;
+            let mut progress = this.progress.lock().unwrap();
+            progress.file_done_bytes += (buf.filled().len() - filled) as u64;
+            if this.emit_throttle.ready() {
+                progress.emit(this.app);
+            }
+        }
+        poll
+    }
+}
+
+/// Length of the blocks of [`FileManifest::block_hashes`], which is how much
+/// of a corrupt download is received before an install notices.
+const HASH_BLOCK_LEN: u64 = 1024 * 1024 * 64;
+
+/// Bytes of the Blake2 hash kept per signature block, unless a patch sets
+/// [`PatchManifest::signature_hash_len`].
+///
+/// A block of a new file is only reused once its rolling hash and this many
+/// hash bytes match, so each comparison mistakes a different block for it
+/// with a chance of about 2^-(8 * len). At 8 bytes that is negligible even
+/// across millions of blocks; below 4 it becomes likely for large files. A
+/// mistaken block yields a wrong file, which installs reject by its hash
+/// instead of corrupting silently, but then have to download in full.
+/// Each byte adds 1/2048 to the size of a signature relative to its file.
+const DEFAULT_SIGNATURE_HASH_LEN: u32 = 8;
+
+/// Hash lengths accepted for signatures, up to the full Blake2 output.
+const SIGNATURE_HASH_LENS: std::ops::RangeInclusive<u32> = 1..=32;
+
+/// Options of every signature in `sig.tar`, which diffs are made against.
+/// Signatures record these themselves, so diffs work against any length.
+fn signature_options(hash_len: u32) -> SignatureOptions {
+    SignatureOptions::new(
+        fast_rsync::RollingHashType::RabinKarp,
+        fast_rsync::CryptoHashType::Blake2,
+        2048,
+        hash_len,
+    )
+}
+
 async fn create_tar(path: &PathBuf) -> std::io::Result<async_tar::Builder<Compat<File>>> {
     Ok(async_tar::Builder::new(File::create(path).await?.compat()))
 }
@@ -350,7 +1991,35 @@ async fn open_tar(path: &PathBuf) -> std::io::Result<async_tar::Archive<Compat<F
     Ok(async_tar::Archive::new(File::open(path).await?.compat()))
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Path of the signatures in the output of a patch, which are only left
+/// uncompressed by patches created without compression.
+async fn sig_archive_path(dir: &Path) -> std::io::Result<PathBuf> {
+    let path = dir.join(ArchiveCompression::Zstd.archive_name("sig"));
+    if tokio::fs::try_exists(&path).await? {
+        return Ok(path);
+    }
+    Ok(dir.join(ArchiveCompression::None.archive_name("sig")))
+}
+
+type SigReader = Compat<Box<dyn tokio::io::AsyncRead + Unpin + Send>>;
+
+/// Opens the signatures at `path`, decompressing them if they are a zstd
+/// archive compressed with `window_log`.
+async fn open_sig_tar(
+    path: &Path,
+    window_log: Option<u32>,
+) -> std::io::Result<async_tar::Archive<SigReader>> {
+    let fs = File::open(path).await?;
+    let reader: Box<dyn tokio::io::AsyncRead + Unpin + Send> =
+        if path.extension().is_some_and(|ext| ext == "zst") {
+            Box::new(zstd_decoder(BufReader::new(fs), window_log))
+        } else {
+            Box::new(fs)
+        };
+    Ok(async_tar::Archive::new(reader.compat()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct SingleInstancePayload {
     args: Vec<String>,
     cwd: String,
@@ -358,34 +2027,88 @@ struct SingleInstancePayload {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Events are off unless enabled with `RUST_LOG`, which also works in release builds.
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let context = tauri::generate_context!();
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("--install") {
+        std::process::exit(install_headless(&context, args.next()));
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
-            println!("{}, {argv:?}, {cwd}", app.package_info().name);
-
-            app.emit("single-instance", SingleInstancePayload { args: argv, cwd })
-                .unwrap();
+            tracing::debug!(
+                app = %app.package_info().name,
+                ?argv,
+                %cwd,
+                "second instance launched"
+            );
+
+            let payload = SingleInstancePayload { args: argv, cwd };
+            if let Err(err) = app.emit("single-instance", payload) {
+                tracing::warn!("failed to forward second instance: {err}");
+            }
         }))
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .invoke_handler(tauri::generate_handler![
             get_update_check_status,
+            apply_self_update,
+            cancel_self_update,
             install,
-            create_patch
+            play,
+            rollback,
+            preview_update,
+            preview_removals,
+            preflight_install,
+            check_update,
+            validate_release,
+            get_install_size,
+            cleanup_storage,
+            trace_file,
+            heal,
+            create_patch,
+            recompress,
+            pack_patch,
+            #[cfg(feature = "rsync-commands")]
+            rsync_commands::make_signature,
+            #[cfg(feature = "rsync-commands")]
+            rsync_commands::make_diff,
+            #[cfg(feature = "rsync-commands")]
+            rsync_commands::apply_diff
         ])
         .setup(|app| {
             let app_handle = app.handle().clone();
             app.listen("single-instance", move |ev| {
-                if let Ok(_) = serde_json::from_str::<SingleInstancePayload>(&ev.payload()) {
-                    if let Some((_, window)) = app_handle.webview_windows().iter().next() {
-                        _ = window.unminimize();
-                        _ = window.set_focus();
+                let payload = match serde_json::from_str::<SingleInstancePayload>(ev.payload()) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        tracing::warn!(
+                            payload = ev.payload(),
+                            "malformed single-instance payload: {err}"
+                        );
+                        return;
                     }
+                };
+                tracing::debug!(?payload, "focusing window for second instance");
+
+                let Some((_, window)) = app_handle.webview_windows().into_iter().next() else {
+                    tracing::warn!("no window to focus for second instance");
+                    return;
+                };
+                if let Err(err) = window.unminimize().and_then(|()| window.set_focus()) {
+                    tracing::warn!("failed to focus window for second instance: {err}");
                 }
             });
 
             app.manage(UpdateCheckerState {
                 status: Mutex::new(UpdateStatus::Initial),
+                pending: Mutex::new(None),
+                cancel_download: Mutex::new(None),
             });
             let app_handle = app.handle().clone();
             let update_join_handle =
@@ -395,9 +2118,7 @@ pub fn run() {
             tauri::async_runtime::spawn(async move {
                 let state = app_handle.state::<UpdateCheckerState>();
                 state.set(match update_join_handle.await {
-                    Ok(res) => res
-                        .err()
-                        .map_or(UpdateStatus::UpToDate, |err| UpdateStatus::Error(err)),
+                    Ok(res) => res.unwrap_or_else(UpdateStatus::Error),
                     Err(err) => UpdateStatus::JoinError(err),
                 });
                 app_handle.emit("update-check-finished", state.get()).unwrap();
@@ -405,12 +2126,52 @@ pub fn run() {
 
             Ok(())
         })
-        .run(tauri::generate_context!())
+        .run(context)
         .expect("error while running tauri application");
 }
 
+/// Runs `--install [options]`, which installs or updates the game files like
+/// the `install` command but without opening a window, for provisioning install
+/// directories from scripts and CI. `options` are [`InstallOptions`] as JSON.
+///
+/// Prints the [`InstallSummary`] as JSON and returns the exit code. Release
+/// builds on Windows have no console, so redirect the output to read it.
+fn install_headless(context: &tauri::Context<tauri::Wry>, options: Option<String>) -> i32 {
+    let host = HeadlessHost::new(
+        context.config().plugins.0.clone(),
+        context.package_info().version.clone(),
+    );
+    let result = tauri::async_runtime::block_on(async {
+        let options: InstallOptions = match options {
+            Some(options) => serde_json::from_str(&options).map_err(|err| err.to_string())?,
+            None => InstallOptions::default(),
+        };
+        let http_client = HttpClient::new(&options).map_err(|err| err.to_string())?;
+        let install_dir = get_install_dir().map_err(|err| err.to_string())?;
+        do_install(&host, &http_client, install_dir, &options)
+            .await
+            .map_err(|err| err.to_string())
+    });
+    match result.and_then(|installed| {
+        serde_json::to_string(&installed.summary).map_err(|err| err.to_string())
+    }) {
+        Ok(summary) => {
+            println!("{summary}");
+            0
+        }
+        Err(err) => {
+            eprintln!("install failed: {err}");
+            1
+        }
+    }
+}
+
 struct UpdateCheckerState {
     status: Mutex<UpdateStatus>,
+    /// Update found by the check, waiting for `apply_self_update`.
+    pending: Mutex<Option<Update>>,
+    /// Aborts the running self-update download, see `cancel_self_update`.
+    cancel_download: Mutex<Option<oneshot::Sender<()>>>,
 }
 impl UpdateCheckerState {
     fn set(&self, status: UpdateStatus) {
@@ -421,6 +2182,8 @@ impl UpdateCheckerState {
         let state = &*self.status.lock().unwrap();
         let done = match state {
             UpdateStatus::UpToDate => true,
+            UpdateStatus::Available { .. } => true,
+            UpdateStatus::Cancelled => true,
             UpdateStatus::Error(_) => true,
             UpdateStatus::JoinError(_) => true,
             _ => false,
@@ -433,11 +2196,13 @@ impl UpdateCheckerState {
 enum UpdateStatus {
     Initial,
     Checking,
+    Available { version: String },
 
     Downloading { len: u64, total_len: Option<u64> },
     DownloadFinished,
 
     Installing,
+    Cancelled,
 
     UpToDate,
     Error(tauri_plugin_updater::Error),
@@ -447,6 +2212,7 @@ enum UpdateStatus {
 impl Display for UpdateStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            UpdateStatus::Available { version } => write!(f, "Update to {version} available"),
             UpdateStatus::Error(err) => err.fmt(f),
             UpdateStatus::JoinError(err) => err.fmt(f),
             _ => write!(f, "{:?}", self),
@@ -454,36 +2220,353 @@ impl Display for UpdateStatus {
     }
 }
 
-async fn update(app: AppHandle) -> tauri_plugin_updater::Result<()> {
+#[derive(Debug, Clone, Serialize)]
+struct UpdateAvailablePayload {
+    version: String,
+    /// Download size, if the update server announces it.
+    size: Option<u64>,
+}
+
+/// Checks for a launcher update. It is applied right away only with
+/// `plugins.installer.autoApplySelfUpdate`, otherwise an `update-available`
+/// event asks the frontend to call `apply_self_update` once the user agrees.
+async fn update(app: AppHandle) -> tauri_plugin_updater::Result<UpdateStatus> {
     let state = app.state::<UpdateCheckerState>();
     state.set(UpdateStatus::Checking);
 
-    if let Some(update) = app.updater()?.check().await? {
-        state.set(UpdateStatus::Downloading {
+    let Some(update) = app.updater()?.check().await? else {
+        return Ok(UpdateStatus::UpToDate);
+    };
+
+    let auto_apply = app
+        .config()
+        .plugins
+        .0
+        .get("installer")
+        .and_then(|o| o.get("autoApplySelfUpdate").and_then(|o| o.as_bool()))
+        .unwrap_or(false);
+    if auto_apply {
+        apply_update(&app, update).await?;
+        return Ok(UpdateStatus::Cancelled);
+    }
+
+    // Sent with the same timeouts, retries and user agent as installs.
+    let size = match HttpClient::new(&InstallOptions::default()) {
+        Ok(http) => get_update_size(&http, &update).await,
+        Err(err) => {
+            tracing::warn!("failed to create HTTP client for update size: {err}");
+            None
+        }
+    };
+    let payload = UpdateAvailablePayload {
+        version: update.version.clone(),
+        size,
+    };
+    *state.pending.lock().unwrap() = Some(update);
+    app.emit("update-available", &payload)?;
+    Ok(UpdateStatus::Available {
+        version: payload.version,
+    })
+}
+
+/// Returns the size announced by the update server, or `None` if HEAD is not supported.
+async fn get_update_size(http: &HttpClient, update: &Update) -> Option<u64> {
+    let response = http.head(&update.download_url).await.ok()?;
+    response
+        .headers()
+        .get(tauri_plugin_http::reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SelfUpdateProgress {
+    downloaded: u64,
+    content_length: Option<u64>,
+}
+
+/// Downloads and installs `update`, then restarts into it. Returns only if
+/// the download is cancelled, in which case the bytes downloaded so far,
+/// which are only held in memory, are dropped.
+async fn apply_update(app: &AppHandle, update: Update) -> tauri_plugin_updater::Result<()> {
+    let state = app.state::<UpdateCheckerState>();
+    let (cancel_tx, cancel_rx) = oneshot::channel();
+    *state.cancel_download.lock().unwrap() = Some(cancel_tx);
+
+    state.set(UpdateStatus::Downloading {
+        len: 0,
+        total_len: None,
+    });
+
+    let mut downloaded = 0;
+    let mut content_length = None;
+    let mut emit_throttle = EmitThrottle::new();
+    let emit_progress = |downloaded, content_length| {
+        let progress = SelfUpdateProgress {
+            downloaded,
+            content_length,
+        };
+        if let Err(err) = app.emit("self-update-progress", progress) {
+            tracing::warn!("failed to emit self-update progress: {err}");
+        }
+    };
+    // The download is dropped before the progress it updates is read again.
+    let downloaded_bytes = {
+        let download = update.download(
+            |chunk_len, total_len| {
+                downloaded += chunk_len as u64;
+                content_length = total_len;
+                state.set(UpdateStatus::Downloading {
+                    len: downloaded,
+                    total_len,
+                });
+                if emit_throttle.ready() {
+                    emit_progress(downloaded, total_len);
+                }
+            },
+            || {
+                state.set(UpdateStatus::DownloadFinished);
+            },
+        );
+        pin_mut!(download);
+        match futures::future::select(download, cancel_rx).await {
+            Either::Left((bytes, _)) => Some(bytes),
+            Either::Right(_) => None,
+        }
+    };
+    state.cancel_download.lock().unwrap().take();
+    let Some(bytes) = downloaded_bytes else {
+        state.set(UpdateStatus::Cancelled);
+        return Ok(());
+    };
+    let bytes = bytes?;
+    // The last chunks are usually throttled away.
+    emit_progress(downloaded, content_length);
+
+    state.set(UpdateStatus::Installing);
+    update.install(bytes)?;
+
+    app.restart();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch_manifest() -> PatchManifest {
+        serde_json::from_value(serde_json::json!({
+            "manifest_version": "V1",
+            "version": "1.0.0",
+            "previous_version": null,
+            "new_files": [],
+            "diff_files": [],
+            "stale_files": [],
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn archive_hashes_are_of_the_published_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        for stem in ARCHIVE_STEMS {
+            tokio::fs::write(dir.path().join(format!("{stem}.tar")), stem.repeat(4096))
+                .await
+                .unwrap();
+        }
+        let mut manifest = patch_manifest();
+        manifest.raw_archive_size = Some(0);
+        manifest.raw_archive_hash = Some([0; 32]);
+
+        let total_size = compress_archives(dir.path(), &mut manifest, 3, None)
+            .await
+            .unwrap();
+
+        let published = dir.path().join("raw.tar.zst");
+        let published_size = tokio::fs::metadata(&published).await.unwrap().len();
+        assert_eq!(manifest.raw_archive_size, Some(published_size));
+        assert_eq!(
+            manifest.raw_archive_hash,
+            Some(file_util::hash_file(&published).await.unwrap())
+        );
+        assert!(published_size < 3 * 4096);
+        assert!(total_size > published_size);
+        // An archive without files stays unrecorded.
+        assert_eq!(manifest.diff_archive_size, None);
+        assert_eq!(manifest.diff_archive_hash, None);
+    }
+
+    #[tokio::test]
+    async fn split_archives_reassemble_exactly() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        tokio::fs::write(dir.path().join("raw.tar.zst"), &data)
+            .await
+            .unwrap();
+        let mut manifest = patch_manifest();
+        manifest.new_files.push(FileManifest {
+            path: "game".into(),
             len: 0,
-            total_len: None,
+            hash: [0; 32],
+            hash_algorithm: HashAlgorithm::Blake3,
+            critical: false,
+            block_hashes: vec![],
         });
+        manifest.raw_archive_hash = Some(
+            file_util::hash_file(&dir.path().join("raw.tar.zst"))
+                .await
+                .unwrap(),
+        );
+
+        split_patch(dir.path(), &mut manifest, 4096).await.unwrap();
+        assert_eq!(manifest.raw_archive_parts, [4096, 4096, 1808]);
+        let mut reassembled = Vec::new();
+        for index in 0..3 {
+            let part = dir.path().join(format!("raw.tar.zst.{index:03}"));
+            reassembled.extend(tokio::fs::read(part).await.unwrap());
+        }
+        assert_eq!(reassembled, data);
 
-        let mut downloaded = 0;
-        let bytes = update
-            .download(
-                |chunk_len, total_len| {
-                    downloaded += chunk_len as u64;
-                    state.set(UpdateStatus::Downloading {
-                        len: downloaded,
-                        total_len,
-                    });
-                },
-                || {
-                    state.set(UpdateStatus::DownloadFinished);
-                },
-            )
-            .await?;
+        // An archive that changed since it was hashed is caught.
+        manifest.raw_archive_hash = Some([0; 32]);
+        assert!(split_patch(dir.path(), &mut manifest, 4096).await.is_err());
+    }
+
+    fn file_manifest(path: &str) -> FileManifest {
+        FileManifest {
+            path: path.into(),
+            len: 0,
+            hash: [0; 32],
+            hash_algorithm: HashAlgorithm::Blake3,
+            critical: false,
+            block_hashes: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn mismatched_old_signatures_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let sig_path = dir.path().join("sig.tar");
+        let mut old_patch_mf = patch_manifest();
+        old_patch_mf.new_files = vec![file_manifest("game"), file_manifest("Game/data.pak")];
+
+        for (signed, expected) in [
+            (&["game", "Game/data.pak"][..], None),
+            (
+                &["game"][..],
+                Some("1 raw files of the manifest have no signature"),
+            ),
+            (
+                &["game", "Game/data.pak", "Game/other.pak"][..],
+                Some("1 signatures are of files missing from the manifest"),
+            ),
+        ] {
+            let mut sig_tar = async_tar::Builder::new(Vec::new());
+            for path in signed {
+                let mut header = async_tar::Header::new_gnu();
+                header.set_size(0);
+                sig_tar
+                    .append_data(&mut header, path, &b""[..])
+                    .await
+                    .unwrap();
+            }
+            tokio::fs::write(&sig_path, sig_tar.into_inner().await.unwrap())
+                .await
+                .unwrap();
+
+            let result = check_old_signatures(&old_patch_mf, &sig_path).await;
+            match expected {
+                None => result.unwrap(),
+                Some(expected) => {
+                    let err = result.unwrap_err();
+                    assert!(err.downcast_ref::<InconsistentOldPatch>().is_some());
+                    assert!(err.to_string().contains(expected), "{err}");
+                }
+            }
+        }
+    }
 
-        state.set(UpdateStatus::Installing);
-        update.install(bytes)?;
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn non_utf8_paths_are_rejected() {
+        use std::os::unix::ffi::OsStrExt;
 
-        app.restart();
+        let dir = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"save\xff.dat");
+        tokio::fs::write(dir.path().join(name), b"data")
+            .await
+            .unwrap();
+        tokio::fs::write(dir.path().join("game"), b"game")
+            .await
+            .unwrap();
+
+        let new_dir = dir.path().to_path_buf();
+        let (files, _) = get_files(&new_dir, 1).await.unwrap();
+        let mut rejected = Vec::new();
+        for file in &files {
+            let relative_path = file.strip_prefix(&new_dir).unwrap();
+            match to_manifest_path(relative_path) {
+                Ok(path) => assert_eq!(path, "game"),
+                Err(err) => rejected.push((relative_path.to_path_buf(), err.to_string())),
+            }
+        }
+        assert_eq!(rejected.len(), 1, "{rejected:?}");
+        assert_eq!(rejected[0].0, Path::new(name));
+        assert!(
+            rejected[0].1.contains("not valid UTF-8"),
+            "{}",
+            rejected[0].1
+        );
+    }
+
+    #[tokio::test]
+    async fn single_read_signing_matches_separate_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.pak");
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let contents: Vec<u8> = (0..3 * 1024 * 1024 + 17)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        tokio::fs::write(&path, &contents).await.unwrap();
+
+        let host = HeadlessHost::new(HashMap::new(), Version::new(1, 0, 0));
+        let progress = Mutex::new(CreatePatchProgress::default());
+        let options = SignFileOptions {
+            hash_algorithm: HashAlgorithm::Blake3,
+            block_hash_threshold: Some(0),
+            signature_hash_len: DEFAULT_SIGNATURE_HASH_LEN,
+        };
+        let (data_tx, data) = futures::channel::mpsc::channel(SIGN_READ_AHEAD);
+        let (signed, data) = tokio::join!(
+            sign_file(&host, &progress, &path, options, data_tx),
+            data.map(Result::unwrap).collect::<Vec<_>>(),
+        );
+        let signed = signed.unwrap();
+
+        // Each output as it was made by reading the file once per output.
+        let mut signature = Vec::new();
+        fast_rsync::Signature::calculate(
+            &mut &contents[..],
+            &mut signature,
+            &signature_options(DEFAULT_SIGNATURE_HASH_LEN),
+        )
+        .await
+        .unwrap();
+        let mut hasher = FileHasher::new(HashAlgorithm::Blake3);
+        hasher.update(&contents);
+        let mut blocks = BlockHasher::new(HASH_BLOCK_LEN);
+        blocks.update(&contents);
+
+        assert_eq!(data.concat(), contents);
+        assert_eq!(signed.len, contents.len() as u64);
+        assert_eq!(signed.signature, signature);
+        assert_eq!(signed.hash, hasher.finish());
+        assert_eq!(signed.block_hashes, blocks.finish());
     }
-    Ok(())
 }
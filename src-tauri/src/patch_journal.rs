@@ -0,0 +1,301 @@
+//! Lets an interrupted `create_patch` resume instead of starting over.
+//!
+//! Every file written to the output archives is also appended to a journal
+//! next to them. A rerun over the same inputs keeps the archive entries that
+//! were both completely written and journaled, truncates everything after
+//! them, and carries on appending.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use async_compat::{Compat, CompatExt};
+use fast_rsync::sum_hash::{Blake3Hash, SumHash};
+use futures::StreamExt;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+};
+
+use crate::{FileManifest, HashAlgorithm};
+
+const JOURNAL_NAME: &str = "create-patch.journal";
+
+#[serde_as]
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalHeader {
+    #[serde_as(as = "Base64")]
+    input_hash: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum JournalArchive {
+    /// An entry in both `raw.tar` and `sig.tar`.
+    Raw,
+    /// An entry in `diff.tar`.
+    Diff,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JournalEntry {
+    archive: JournalArchive,
+    file: FileManifest,
+}
+
+/// Files carried over from an interrupted run, together with the lengths
+/// the archives were truncated to.
+#[derive(Debug, Default)]
+pub(crate) struct Resumed {
+    pub(crate) raw_files: Vec<FileManifest>,
+    pub(crate) raw_tar_len: u64,
+    pub(crate) sig_tar_len: u64,
+    pub(crate) diff_files: Vec<FileManifest>,
+    pub(crate) diff_tar_len: u64,
+}
+
+pub(crate) struct PatchJournal {
+    fs: File,
+    line_buf: Vec<u8>,
+}
+
+impl PatchJournal {
+    /// Opens the journal in `out_dir`, resuming from it if it was written
+    /// for the same `input_hash`, and starts a fresh one otherwise.
+    pub(crate) async fn open(
+        out_dir: &Path,
+        input_hash: [u8; 32],
+    ) -> std::io::Result<(Self, Resumed)> {
+        let path = out_dir.join(JOURNAL_NAME);
+        let entries = read_entries(&path, &input_hash).await?;
+
+        let mut resumed = Resumed::default();
+        if let Some(mut entries) = entries {
+            resume_raw(out_dir, &mut entries, &mut resumed).await?;
+            resume_diff(out_dir, &mut entries, &mut resumed).await?;
+        }
+
+        let mut journal = Self {
+            fs: File::create(&path).await?,
+            line_buf: Vec::new(),
+        };
+        journal.write_line(&JournalHeader { input_hash }).await?;
+        for file in &resumed.raw_files {
+            journal.push(JournalArchive::Raw, file).await?;
+        }
+        for file in &resumed.diff_files {
+            journal.push(JournalArchive::Diff, file).await?;
+        }
+        Ok((journal, resumed))
+    }
+
+    /// Records a file whose archive entries have been written.
+    pub(crate) async fn push(
+        &mut self,
+        archive: JournalArchive,
+        file: &FileManifest,
+    ) -> std::io::Result<()> {
+        self.write_line(&JournalEntry {
+            archive,
+            file: file.clone(),
+        })
+        .await
+    }
+
+    /// Removes the journal once the patch is complete.
+    pub(crate) async fn finish(self, out_dir: &Path) -> std::io::Result<()> {
+        drop(self.fs);
+        fs::remove_file(out_dir.join(JOURNAL_NAME)).await
+    }
+
+    async fn write_line(&mut self, value: &impl Serialize) -> std::io::Result<()> {
+        self.line_buf.clear();
+        serde_json::to_writer(&mut self.line_buf, value)?;
+        self.line_buf.push(b'\n');
+        self.fs.write_all(&self.line_buf).await?;
+        self.fs.flush().await
+    }
+}
+
+/// Hashes what a patch is built from, so a journal is only resumed
+/// if nothing changed since it was written.
+///
+/// Files are identified by path, size and modification time rather than
+/// contents, since hashing every file would cost as much as a rebuild.
+pub(crate) async fn hash_inputs(
+    new_dir: &Path,
+    new_files: &HashSet<PathBuf>,
+    old_dir: Option<&Path>,
+    version: &Version,
+    hash_algorithm: HashAlgorithm,
+    signature_hash_len: u32,
+    block_hash_threshold: Option<u64>,
+) -> std::io::Result<[u8; 32]> {
+    let mut hash = Blake3Hash::default();
+    hash.update(version.to_string().as_bytes());
+    hash.update(&[0]);
+    hash.update(format!("{hash_algorithm:?}").as_bytes());
+    hash.update(&[0]);
+    hash.update(&signature_hash_len.to_le_bytes());
+    hash.update(&block_hash_threshold.unwrap_or(u64::MAX).to_le_bytes());
+
+    if let Some(old_dir) = old_dir {
+        let old_sig_path = crate::sig_archive_path(old_dir).await?;
+        hash.update(old_sig_path.to_string_lossy().as_bytes());
+        hash_metadata(&mut hash, &old_sig_path).await?;
+    }
+
+    let mut new_files: Vec<_> = new_files.iter().collect();
+    new_files.sort();
+    for file in new_files {
+        hash.update(
+            file.strip_prefix(new_dir)
+                .unwrap_or(file)
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hash_metadata(&mut hash, file).await?;
+    }
+    Ok(hash.finish())
+}
+
+async fn hash_metadata(hash: &mut Blake3Hash, path: &Path) -> std::io::Result<()> {
+    let meta = fs::metadata(path).await?;
+    let modified = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hash.update(&[0]);
+    hash.update(&meta.len().to_le_bytes());
+    hash.update(&modified.as_nanos().to_le_bytes());
+    Ok(())
+}
+
+/// Opens a tar for appending after its first `len` bytes, discarding the rest.
+pub(crate) async fn open_tar_at(
+    path: &Path,
+    len: u64,
+) -> std::io::Result<async_tar::Builder<Compat<File>>> {
+    let mut fs = File::options()
+        .write(true)
+        .create(true)
+        .truncate(len == 0)
+        .open(path)
+        .await?;
+    fs.set_len(len).await?;
+    fs.seek(std::io::SeekFrom::Start(len)).await?;
+    Ok(async_tar::Builder::new(fs.compat()))
+}
+
+/// Reads the journaled files by archive, or `None` if there is no journal
+/// for `input_hash`. A line cut off by the interruption ends the journal.
+async fn read_entries(
+    path: &Path,
+    input_hash: &[u8; 32],
+) -> std::io::Result<Option<HashMap<JournalArchive, HashMap<PathBuf, FileManifest>>>> {
+    let fs = match File::open(path).await {
+        Ok(fs) => fs,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut lines = BufReader::new(fs).lines();
+
+    let Some(header) = lines.next_line().await? else {
+        return Ok(None);
+    };
+    match serde_json::from_str::<JournalHeader>(&header) {
+        Ok(header) if &header.input_hash == input_hash => {}
+        _ => return Ok(None),
+    }
+
+    let mut entries: HashMap<_, HashMap<_, _>> = HashMap::new();
+    while let Some(line) = lines.next_line().await? {
+        let Ok(entry) = serde_json::from_str::<JournalEntry>(&line) else {
+            break;
+        };
+        entries
+            .entry(entry.archive)
+            .or_default()
+            .insert(PathBuf::from(&entry.file.path), entry.file);
+    }
+    Ok(Some(entries))
+}
+
+/// Keeps the leading files that are complete in both `raw.tar` and `sig.tar`.
+async fn resume_raw(
+    out_dir: &Path,
+    entries: &mut HashMap<JournalArchive, HashMap<PathBuf, FileManifest>>,
+    resumed: &mut Resumed,
+) -> std::io::Result<()> {
+    let Some(journaled) = entries.get_mut(&JournalArchive::Raw) else {
+        return Ok(());
+    };
+    let raw_entries = read_complete_entries(&out_dir.join("raw.tar")).await?;
+    let sig_entries = read_complete_entries(&out_dir.join("sig.tar")).await?;
+
+    for ((raw_path, raw_end), (sig_path, sig_end)) in raw_entries.into_iter().zip(sig_entries) {
+        if raw_path != sig_path {
+            break;
+        }
+        let Some(file) = journaled.remove(&raw_path) else {
+            break;
+        };
+        resumed.raw_files.push(file);
+        resumed.raw_tar_len = raw_end;
+        resumed.sig_tar_len = sig_end;
+    }
+    Ok(())
+}
+
+/// Keeps the leading files that are complete in `diff.tar`.
+async fn resume_diff(
+    out_dir: &Path,
+    entries: &mut HashMap<JournalArchive, HashMap<PathBuf, FileManifest>>,
+    resumed: &mut Resumed,
+) -> std::io::Result<()> {
+    let Some(journaled) = entries.get_mut(&JournalArchive::Diff) else {
+        return Ok(());
+    };
+    for (path, end) in read_complete_entries(&out_dir.join("diff.tar")).await? {
+        let Some(file) = journaled.remove(&path) else {
+            break;
+        };
+        resumed.diff_files.push(file);
+        resumed.diff_tar_len = end;
+    }
+    Ok(())
+}
+
+/// Lists the entries of a possibly truncated tar with the offset each ends
+/// at, stopping at the first entry that was not completely written.
+async fn read_complete_entries(path: &Path) -> std::io::Result<Vec<(PathBuf, u64)>> {
+    let fs = match File::open(path).await {
+        Ok(fs) => fs,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let len = fs.metadata().await?.len();
+
+    let mut complete = Vec::new();
+    let archive = async_tar::Archive::new(fs.compat());
+    let mut entries = archive.entries()?;
+    while let Some(Ok(entry)) = entries.next().await {
+        let Ok(size) = entry.header().entry_size() else {
+            break;
+        };
+        let end = entry.raw_file_position() + size.next_multiple_of(512);
+        if end > len {
+            break;
+        }
+        let Ok(path) = entry.path() else {
+            break;
+        };
+        complete.push((path.into_owned().into(), end));
+    }
+    Ok(complete)
+}
@@ -0,0 +1,68 @@
+//! The rsync steps of patching, run on single files so a bad diff can be
+//! reproduced and the apply path fuzzed without the install pipeline.
+
+use tokio::fs::File;
+
+use crate::{signature_options, DEFAULT_SIGNATURE_HASH_LEN};
+
+/// Largest output [`apply_diff`] produces unless told otherwise.
+const DEFAULT_MAX_APPLY_LEN: usize = 1024 * 1024 * 1024;
+
+/// Calculates the signature of `file` as it would be stored in `sig.tar`.
+#[tauri::command]
+pub(crate) async fn make_signature(file: String) -> Result<Vec<u8>, String> {
+    do_make_signature(&file)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Diffs `new_file` against a signature from [`make_signature`].
+#[tauri::command]
+pub(crate) async fn make_diff(sig: Vec<u8>, new_file: String) -> Result<Vec<u8>, String> {
+    do_make_diff(&sig, &new_file)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Applies a diff from [`make_diff`] to `old_file`, refusing to produce
+/// more than `max_len` bytes.
+#[tauri::command]
+pub(crate) async fn apply_diff(
+    old_file: String,
+    diff: Vec<u8>,
+    max_len: Option<usize>,
+) -> Result<Vec<u8>, String> {
+    do_apply_diff(&old_file, &diff, max_len.unwrap_or(DEFAULT_MAX_APPLY_LEN))
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn do_make_signature(file: &str) -> anyhow::Result<Vec<u8>> {
+    let mut src_fs = File::open(file).await?;
+    let mut sig = Vec::new();
+    fast_rsync::Signature::calculate(
+        &mut src_fs,
+        &mut sig,
+        &signature_options(DEFAULT_SIGNATURE_HASH_LEN),
+    )
+    .await?;
+    Ok(sig)
+}
+
+async fn do_make_diff(sig: &[u8], new_file: &str) -> anyhow::Result<Vec<u8>> {
+    let old_sig = fast_rsync::Signature::deserialize(&mut &sig[..]).await?;
+    let old_sig_index = old_sig.index(sig);
+    let new_buf = tokio::fs::read(new_file).await?;
+
+    let mut diff = Vec::new();
+    fast_rsync::diff(&old_sig_index, &new_buf, &mut diff)?;
+    Ok(diff)
+}
+
+async fn do_apply_diff(old_file: &str, diff: &[u8], max_len: usize) -> anyhow::Result<Vec<u8>> {
+    let old_buf = tokio::fs::read(old_file).await?;
+
+    let mut new_buf = Vec::new();
+    fast_rsync::apply_limited(&old_buf, diff, &mut new_buf, max_len)?;
+    Ok(new_buf)
+}
@@ -0,0 +1,148 @@
+//! Opt-in `install-telemetry` events for publishers aggregating fleet health.
+//!
+//! An event is emitted once per install, update, repair or heal, whether it
+//! succeeded or not. Events only carry versions, the platform and counters,
+//! and failures are reduced to a [`FailureKind`], since error messages can
+//! contain paths naming the user.
+
+use semver::Version;
+use serde::Serialize;
+
+use crate::{host::InstallHost, install::InstallError};
+
+/// Bumped whenever a field changes meaning or is removed, so collectors can
+/// tell events apart. Adding fields keeps the version.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TelemetryOperation {
+    /// Nothing was installed on the channel before.
+    Install,
+    Update,
+    /// The installed version was reinstalled over itself.
+    Repair,
+    /// Broken files of the installed version were re-fetched.
+    Heal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum FailureKind {
+    Network,
+    Unauthorized,
+    /// A downloaded archive or the files written from it failed verification.
+    CorruptDownload,
+    Manifest,
+    /// The requested channel or version cannot be installed by this launcher.
+    Release,
+    Disk,
+    Config,
+    Internal,
+}
+
+impl From<&InstallError> for FailureKind {
+    fn from(err: &InstallError) -> Self {
+        use InstallError::*;
+
+        match err {
+            Download(_) | Reqwest(_) | Redirect { .. } | RangeNotHonored(_) => Self::Network,
+            Unauthorized(_) => Self::Unauthorized,
+            Decompress(_)
+            | WrongSize { .. }
+            | WrongHash { .. }
+            | WrongBlockHash { .. }
+            | DiffApplyFailed { .. }
+            | MalformedDelta { .. }
+            | UnexpectedArchiveFile(_)
+            | MissingArchiveFile(_)
+            | UnsupportedArchiveEntry { .. }
+            | InvalidArchivePath(_) => Self::CorruptDownload,
+            MalformedManifest { .. }
+            | Json(_)
+            | InvalidVersion(_)
+            | InvalidUrl(_)
+            | InvalidExePath(_)
+            | InvalidCwd(_)
+            | CaseCollision { .. } => Self::Manifest,
+            UnknownChannel
+            | UnknownVersion
+            | UpdateLocked(_)
+            | LauncherOutdated { .. }
+            | UnsupportedArch
+            | UnsupportedOS
+            | MissingPreviousVersion
+            | PreviousVersionPruned(_)
+            | NothingToRollBack => Self::Release,
+            Io(_)
+            | CreateDir(_)
+            | FileInPlaceOfDir(_)
+            | CopyError(_)
+            | InvalidInstalledPatch(_)
+            | NotInstalled
+            | MissingInstalledFile(_)
+            | UnresolvedSaveDir(_) => Self::Disk,
+            MissingRootUrl
+            | InvalidAssetsPath(_)
+            | InvalidHeader(_)
+            | InvalidLocalPath(_)
+            | InvalidLayout(_)
+            | InvalidConfiguredHash(_) => Self::Config,
+            Tauri(_) => Self::Internal,
+        }
+    }
+}
+
+/// Filled in while an operation runs, with whatever it got to know before
+/// it finished or failed.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct InstallTelemetry {
+    schema_version: u32,
+    /// `None` while nothing is to be changed, in which case no event is emitted.
+    pub(crate) operation: Option<TelemetryOperation>,
+    pub(crate) succeeded: bool,
+    pub(crate) failure: Option<FailureKind>,
+    pub(crate) channel: Option<String>,
+    pub(crate) from_version: Option<Version>,
+    pub(crate) to_version: Option<Version>,
+    pub(crate) os: Option<String>,
+    pub(crate) arch: Option<String>,
+    pub(crate) downloaded_bytes: u64,
+    pub(crate) written_bytes: u64,
+    /// Files still failing verification once the operation finished.
+    pub(crate) broken_files: usize,
+    pub(crate) elapsed_secs: f64,
+}
+
+impl InstallTelemetry {
+    pub(crate) fn new(operation: TelemetryOperation) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            operation: Some(operation),
+            succeeded: false,
+            failure: None,
+            channel: None,
+            from_version: None,
+            to_version: None,
+            os: None,
+            arch: None,
+            downloaded_bytes: 0,
+            written_bytes: 0,
+            broken_files: 0,
+            elapsed_secs: 0.0,
+        }
+    }
+
+    /// Records the outcome and emits the event. Failing to emit is only
+    /// logged, so it never fails the operation itself.
+    pub(crate) fn finish<T>(mut self, app: &dyn InstallHost, result: &Result<T, InstallError>) {
+        if self.operation.is_none() {
+            return;
+        }
+        self.succeeded = result.is_ok();
+        self.failure = result.as_ref().err().map(FailureKind::from);
+        if let Err(err) = app.emit("install-telemetry", self) {
+            tracing::warn!("failed to emit install telemetry: {err}");
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Serves a directory over HTTP on `127.0.0.1`, so a patch tree produced by
+//! `create_patch` can be installed end to end without a live CDN.
+
+use std::{
+    io::{ErrorKind, SeekFrom},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// A static file server that stops when dropped.
+///
+/// Only `GET` and `HEAD` are supported, and request paths are not
+/// percent-decoded, which suffices for the names `create_patch` produces.
+pub struct StaticServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl StaticServer {
+    pub async fn serve(root: PathBuf) -> std::io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let task_requests = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let root = root.clone();
+                let requests = task_requests.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, &root, &requests).await {
+                        tracing::warn!("test server connection failed: {err}");
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            addr,
+            requests,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Takes the `METHOD target` of every request served so far,
+    /// followed by its range if it asked for one.
+    pub fn take_requests(&self) -> Vec<String> {
+        std::mem::take(&mut self.requests.lock().unwrap())
+    }
+
+    /// Root URL to install from, with the trailing slash the installer expects.
+    pub fn root_url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+}
+
+impl Drop for StaticServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    root: &Path,
+    requests: &Mutex<Vec<String>>,
+) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    // Connections are kept alive until the client closes them.
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default().to_string();
+
+        // Of the headers, only an open-ended range like `bytes=N-` is understood.
+        let mut range_start = None;
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if stream.read_line(&mut header).await? == 0 {
+                return Ok(());
+            }
+            if header.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("range") {
+                    range_start = value
+                        .trim()
+                        .strip_prefix("bytes=")
+                        .and_then(|range| range.strip_suffix('-'))
+                        .and_then(|start| start.parse::<u64>().ok());
+                }
+            }
+        }
+        requests.lock().unwrap().push(match range_start {
+            Some(start) => format!("{method} {target} bytes={start}-"),
+            None => format!("{method} {target}"),
+        });
+
+        let writer = stream.get_mut();
+        if method != "GET" && method != "HEAD" {
+            write_status(writer, "405 Method Not Allowed").await?;
+            continue;
+        }
+
+        let Some(path) = resolve_path(root, &target) else {
+            write_status(writer, "404 Not Found").await?;
+            continue;
+        };
+        let mut file = match File::open(&path).await {
+            Ok(file) if file.metadata().await?.is_file() => file,
+            Ok(_) => {
+                write_status(writer, "404 Not Found").await?;
+                continue;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                write_status(writer, "404 Not Found").await?;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        let len = file.metadata().await?.len();
+        if range_start.is_some_and(|start| start >= len) {
+            write_status(writer, "416 Range Not Satisfiable").await?;
+            continue;
+        }
+        let head = match range_start {
+            Some(start) => {
+                file.seek(SeekFrom::Start(start)).await?;
+                format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {start}-{}/{len}\r\nContent-Length: {}\r\n\r\n",
+                    len - 1,
+                    len - start
+                )
+            }
+            None => format!("HTTP/1.1 200 OK\r\nContent-Length: {len}\r\n\r\n"),
+        };
+        writer.write_all(head.as_bytes()).await?;
+        if method == "GET" {
+            tokio::io::copy(&mut file, writer).await?;
+        }
+        writer.flush().await?;
+    }
+}
+
+async fn write_status(writer: &mut TcpStream, status: &str) -> std::io::Result<()> {
+    writer
+        .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+        .await?;
+    writer.flush().await
+}
+
+/// Maps a request target below `root`, rejecting anything that could escape it.
+fn resolve_path(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?.trim_start_matches('/');
+    let path = Path::new(path);
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(path))
+}
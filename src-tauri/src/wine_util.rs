@@ -1,4 +1,8 @@
-use std::string::FromUtf8Error;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum WineError {
@@ -24,3 +28,57 @@ pub(crate) fn get_wine_path() -> Result<String, WineError> {
         Err(WineError::UnsupportedOS)
     }
 }
+
+/// Prefix Wine runs in, honoring `WINEPREFIX` from the launch environment
+/// first, then from our own environment, like Wine itself.
+pub(crate) fn get_wine_prefix(env: &HashMap<String, String>) -> Option<PathBuf> {
+    env.get("WINEPREFIX")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("WINEPREFIX").map(PathBuf::from))
+        .or_else(|| dirs::home_dir().map(|home| home.join(".wine")))
+}
+
+/// Maps an absolute Windows path inside a prefix, such as `C:\users\...`,
+/// to the host path of its drive. Returns `None` for relative paths.
+pub(crate) fn to_host_path(prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let (drive, rest) = windows_path.split_once(':')?;
+    if drive.len() != 1 || !drive.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    // Drives are symlinks in `dosdevices`, with `c:` pointing at `drive_c`.
+    let mut path = prefix
+        .join("dosdevices")
+        .join(drive.to_ascii_lowercase() + ":");
+    path.extend(rest.split(['\\', '/']).filter(|part| !part.is_empty()));
+    Some(path)
+}
+
+/// Expands `%VARIABLE%`s with the profile folders Wine creates for the user.
+pub(crate) fn expand_wine_vars(path: &str) -> Option<String> {
+    let user = std::env::var("USER").ok()?;
+    let profile = format!("C:\\users\\{user}");
+    expand_vars(path, |name| match name.to_ascii_uppercase().as_str() {
+        "USERPROFILE" => Some(profile.clone()),
+        "APPDATA" => Some(format!("{profile}\\AppData\\Roaming")),
+        "LOCALAPPDATA" => Some(format!("{profile}\\AppData\\Local")),
+        "USERNAME" => Some(user.clone()),
+        _ => None,
+    })
+}
+
+/// Expands `%VARIABLE%`s from our own environment.
+pub(crate) fn expand_native_vars(path: &str) -> Option<String> {
+    expand_vars(path, |name| std::env::var(name).ok())
+}
+
+fn expand_vars(path: &str, lookup: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut parts = path.split('%');
+    expanded.push_str(parts.next()?);
+    // Odd parts are variable names, since they follow an opening `%`.
+    while let Some(name) = parts.next() {
+        expanded.push_str(&lookup(name)?);
+        expanded.push_str(parts.next()?);
+    }
+    Some(expanded)
+}
@@ -17,6 +17,7 @@ let createPatchMsgEl: HTMLElement;
 let createPatchPathMsgEl: HTMLElement;
 
 let installForm: HTMLFormElement;
+let skipSelfUpdateButton: HTMLButtonElement;
 let versionSpan: HTMLSpanElement;
 
 window.addEventListener("DOMContentLoaded", () => {
@@ -31,6 +32,7 @@ window.addEventListener("DOMContentLoaded", () => {
   createPatchPathMsgEl = document.querySelector("#create-patch-path-msg") ?? throwNull();
 
   installForm = document.querySelector<HTMLFormElement>("#install-form") ?? throwNull();
+  skipSelfUpdateButton = document.querySelector<HTMLButtonElement>("#skip-self-update-button") ?? throwNull();
   versionSpan = document.querySelector<HTMLSpanElement>("#patcher-version-span") ?? throwNull();
 
   getVersion().then((version) => {
@@ -44,6 +46,18 @@ window.addEventListener("DOMContentLoaded", () => {
     versionSpan.title = value[1];
   });
 
+  skipSelfUpdateButton.addEventListener("click", async () => {
+    skipSelfUpdateButton.disabled = true;
+    try {
+      await invoke("cancel_self_update");
+    }
+    catch (err) {
+      installMsgEl.textContent = `Error: ${err}`;
+    }
+    skipSelfUpdateButton.disabled = false;
+    skipSelfUpdateButton.hidden = true;
+  });
+
   installForm.addEventListener("submit", async (e) => {
     e.preventDefault();
 
@@ -56,7 +70,7 @@ window.addEventListener("DOMContentLoaded", () => {
     installDiskProgressEl.value = 0;
 
     try {
-      await invoke("install");
+      await invoke("play");
       installMsgEl.textContent = `Installation finished`;
     }
     catch (err) {
@@ -111,11 +125,15 @@ window.addEventListener("DOMContentLoaded", () => {
         let diffCount = manifest.diff_files.length;
         let staleCount = manifest.stale_files.length;
         let totalCount = newCount + diffCount;
-        createPatchProgressEl.value = totalCount;
+        createPatchProgressEl.value = createPatchProgressEl.max;
 
         let patchSizeMB = result.patch_size / (1024.0 * 1024.0);
         let fractionDigits = patchSizeMB >= 1000 ? 0 : 1;
         let sizeStr = patchSizeMB.toFixed(fractionDigits) + "MiB";
+        if (result.tar_size > result.patch_size) {
+          let ratio = result.tar_size / result.patch_size;
+          sizeStr += ` (${ratio.toFixed(1)}× compressed)`;
+        }
         createPatchMsgEl.textContent =
           `Created ${sizeStr} patch with ${totalCount} files ` +
           `(${newCount} new, ${diffCount} diff, ${staleCount} stale)`;
@@ -135,13 +153,33 @@ window.addEventListener("DOMContentLoaded", () => {
 type CreatePatchProgress = {
   done_files: number;
   total_files: number;
+  done_bytes: number;
+  total_bytes: number;
   path: string;
+  file_done_bytes: number;
+  elapsed_secs?: number;
 };
 
+type InstallPhase =
+  | "FetchingManifests"
+  | "DownloadingDiff"
+  | "DownloadingRaw"
+  | "CopyingSaves"
+  | "Cleanup"
+  | "Verifying"
+  | "Done";
+
 type InstallProgress = {
   net: ProgressState;
+  decompress: ProgressState;
   disk: ProgressState;
+  phase: InstallPhase;
   message: string;
+  archive_url?: string;
+  current_file?: string;
+  file_index: number;
+  file_count: number;
+  elapsed_secs?: number;
 };
 
 type ProgressState = {
@@ -172,6 +210,9 @@ function updateProgress(span: HTMLSpanElement, bar: HTMLProgressElement, state:
 type CreatePatchResult = {
   manifest: PatchManifest;
   patch_size: number;
+  tar_size: number;
+  manifest_hash: string;
+  manifest_delta_hash?: string;
 };
 
 type FileManifest = {
@@ -190,13 +231,39 @@ type PatchManifest = {
 listen<CreatePatchProgress>("create-patch-progress", (event) => {
   let payload = event.payload;
 
-  createPatchProgressEl.value = payload.done_files;
-  createPatchProgressEl.max = payload.total_files;
+  // Files vary wildly in size, so bytes are the better measure of the work done.
+  if (payload.total_bytes > 0) {
+    createPatchProgressEl.value = payload.done_bytes + payload.file_done_bytes;
+    createPatchProgressEl.max = payload.total_bytes;
+  } else {
+    createPatchProgressEl.value = payload.done_files;
+    createPatchProgressEl.max = payload.total_files;
+  }
 
   createPatchMsgEl.textContent = `${payload.done_files} / ${payload.total_files}`;
   createPatchPathMsgEl.textContent = `${payload.path}`;
 });
 
+type SelfUpdateProgress = {
+  downloaded: number,
+  content_length: number | null,
+};
+
+listen<SelfUpdateProgress>("self-update-progress", (event) => {
+  const { downloaded, content_length } = event.payload;
+
+  if (content_length != null) {
+    installNetProgressEl.value = downloaded;
+    installNetProgressEl.max = content_length;
+    installNetIoSpanEl.textContent = `${toReadableSize(downloaded, 2)} / ${toReadableSize(content_length, 2)}`;
+  } else {
+    installNetProgressEl.removeAttribute("value");
+    installNetIoSpanEl.textContent = `${toReadableSize(downloaded, 2)} / ?`;
+  }
+  installMsgEl.textContent = "Downloading launcher update";
+  skipSelfUpdateButton.hidden = false;
+});
+
 listen<[boolean, string]>("update-check-finished", (event) => {
   console.log("update check finished: ", event);
 
@@ -206,6 +273,57 @@ listen<[boolean, string]>("update-check-finished", (event) => {
   }
 });
 
+type PlatformFallback = {
+  os: string;
+  arch: string;
+  native_os: string;
+};
+
+listen<PlatformFallback>("platform-fallback", (event) => {
+  const { os, native_os } = event.payload;
+  installMsgEl.textContent = `No native ${native_os} build available, running the ${os} version via Wine`;
+});
+
+type InstallResolved = {
+  channel: string;
+  version: string;
+  os: string;
+  arch: string;
+  installed_version: string | null;
+  update: boolean;
+};
+
+listen<InstallResolved>("install-resolved", (event) => {
+  const { version, os, arch, installed_version, update } = event.payload;
+  if (installed_version === version) {
+    installMsgEl.textContent = `Checking ${version} (${os}/${arch})`;
+  } else {
+    const action = update ? "Updating to" : "Installing";
+    installMsgEl.textContent = `${action} ${version} (${os}/${arch})`;
+  }
+});
+
+type UpdateAvailable = {
+  version: string;
+  size?: number;
+};
+
+listen<UpdateAvailable>("update-available", async (event) => {
+  const { version, size } = event.payload;
+  const sizeText = size !== undefined ? ` (${toReadableSize(size)})` : "";
+  if (confirm(`Launcher version ${version}${sizeText} is available. Update now?`)) {
+    try {
+      // Only returns if the download is skipped, as the launcher restarts otherwise.
+      await invoke("apply_self_update");
+      installMsgEl.textContent = "Launcher update skipped";
+    }
+    catch (err) {
+      installMsgEl.textContent = `Error: ${err}`;
+    }
+    skipSelfUpdateButton.hidden = true;
+  }
+});
+
 function throwNull(): never {
   throw new Error();
 }